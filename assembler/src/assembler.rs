@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use disassembler::instruction::Instruction;
+
+use crate::errors::Error;
+
+// Address the ROM is loaded at
+pub(crate) const START_ADDRESS: u32 = 0x200;
+pub(crate) const MEMORY_SIZE: u32 = 0x10000;
+
+pub fn assembler(source_path: &str, output: Option<String>) -> Result<(), Error> {
+    let rom = assemble_file(Path::new(source_path))?;
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(source_path).with_extension("ch8"),
+    };
+    fs::write(output, rom).map_err(|e| Error::FileWriteError(e.to_string()))
+}
+
+/// Assemble a source file. Included files are found relative to the including file
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut assembler = Assembler::new();
+    assembler.read_file(path)?;
+    assembler.finish()
+}
+
+/// Assemble source text. Included files are found relative to the working directory
+pub fn assemble(source: &str) -> Result<Vec<u8>, Error> {
+    let mut assembler = Assembler::new();
+    assembler.read_source(source, "<source>", Path::new(""))?;
+    assembler.finish()
+}
+
+/// Line of source producing bytes in the ROM
+#[derive(Debug)]
+struct Statement {
+    location: String,
+    kind: StatementKind,
+}
+
+#[derive(Debug)]
+enum StatementKind {
+    Instruction { mnemonic: String, operands: Vec<String> },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+#[derive(Debug)]
+enum Symbol {
+    Label(u32),
+    /// Expression and the location it was defined at
+    Constant(String, String),
+}
+
+/// Two pass assembler. The first pass reads the source and assigns addresses
+/// to labels, the second encodes the statements once every symbol is known
+#[derive(Debug)]
+struct Assembler {
+    statements: Vec<Statement>,
+    symbols: HashMap<String, Symbol>,
+    // Address of the next statement
+    address: u32,
+    // Files currently being read, to catch files including themselves
+    includes: Vec<PathBuf>,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Self {
+            statements: Vec::new(),
+            symbols: HashMap::new(),
+            address: START_ADDRESS,
+            includes: Vec::new(),
+        }
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<(), Error> {
+        let read_error =
+            |e: std::io::Error| Error::FileReadError(format!("{}: {e}", path.display()));
+        let canonical = fs::canonicalize(path).map_err(read_error)?;
+        if self.includes.contains(&canonical) {
+            return Err(Error::RecursiveIncludeError(path.display().to_string()));
+        }
+        let source = fs::read_to_string(path).map_err(read_error)?;
+
+        self.includes.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        self.read_source(&source, &path.display().to_string(), dir)?;
+        self.includes.pop();
+        Ok(())
+    }
+
+    fn read_source(&mut self, source: &str, name: &str, dir: &Path) -> Result<(), Error> {
+        for (i, line) in source.lines().enumerate() {
+            let location = format!("{name}:{}", i + 1);
+            self.read_line(line, &location, dir).map_err(|e| match e {
+                // Errors in included files already carry their location
+                Error::LineError(..) => e,
+                _ => Error::LineError(location, Box::new(e)),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn read_line(&mut self, line: &str, location: &str, dir: &Path) -> Result<(), Error> {
+        let mut line = strip_comment(line).trim();
+
+        // Labels and the address column printed by the disassembler
+        while let Some((name, rest)) = line.split_once(':')
+            && !name.is_empty()
+            && !name.contains(|c: char| c.is_whitespace() || c == '"')
+        {
+            if !is_address_column(name) {
+                self.define(name, Symbol::Label(self.address))?;
+            }
+            line = rest.trim_start();
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (word, rest) = split_word(line);
+        let (next, value) = split_word(rest);
+        if next.eq_ignore_ascii_case("equ") {
+            return self.define(word, Symbol::Constant(value.into(), location.into()));
+        }
+
+        let operands = split_operands(rest)?;
+        let (kind, size) = match word.to_lowercase().as_str() {
+            "include" => {
+                let [file] = operand_array(&operands)?;
+                let file = parse_string(file)
+                    .ok_or_else(|| Error::SyntaxError(format!("Expected file name: {file}")))?;
+                return self.read_file(&dir.join(file));
+            }
+            "db" => {
+                let size = operands
+                    .iter()
+                    .map(|operand| parse_string(operand).map_or(1, str::len) as u32)
+                    .sum();
+                (StatementKind::Bytes(operands), size)
+            }
+            "dw" => {
+                let size = 2 * operands.len() as u32;
+                (StatementKind::Words(operands), size)
+            }
+            _ => {
+                let mnemonic = word.to_uppercase();
+                let size = if mnemonic == Instruction::LoadLongI.mnemonic() {
+                    Instruction::LoadLongI.size() as u32
+                } else {
+                    2
+                };
+                (StatementKind::Instruction { mnemonic, operands }, size)
+            }
+        };
+
+        self.statements.push(Statement {
+            location: location.into(),
+            kind,
+        });
+        self.address += size;
+        if self.address > MEMORY_SIZE {
+            return Err(Error::ProgramSizeError);
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), Error> {
+        if !is_identifier(name) {
+            return Err(Error::SyntaxError(format!("Invalid symbol name: {name}")));
+        }
+        if self.symbols.contains_key(name) {
+            return Err(Error::DuplicateSymbolError(name.into()));
+        }
+        self.symbols.insert(name.into(), symbol);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<u8>, Error> {
+        let mut rom = Vec::new();
+        for statement in &self.statements {
+            let bytes = self.encode(&statement.kind).map_err(|e| match e {
+                // Errors in constants carry the location of their definition
+                Error::LineError(..) => e,
+                _ => Error::LineError(statement.location.clone(), Box::new(e)),
+            })?;
+            rom.extend(bytes);
+        }
+        Ok(rom)
+    }
+
+    fn encode(&self, kind: &StatementKind) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        match kind {
+            StatementKind::Bytes(operands) => {
+                for operand in operands {
+                    match parse_string(operand) {
+                        Some(text) => bytes.extend(text.bytes()),
+                        None => bytes.push(self.value(operand, 8)? as u8),
+                    }
+                }
+            }
+            StatementKind::Words(operands) => {
+                for operand in operands {
+                    bytes.extend(self.value(operand, 16)?.to_be_bytes());
+                }
+            }
+            StatementKind::Instruction { mnemonic, operands } => {
+                bytes.extend(self.encode_instruction(mnemonic, operands)?);
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn encode_instruction(&self, mnemonic: &str, operands: &[String]) -> Result<Vec<u8>, Error> {
+        // Operand shapes shared by several instructions
+        let none = || operand_array::<0>(operands).map(|_| ());
+        let address = || {
+            let [address] = operand_array(operands)?;
+            self.value(address, 12)
+        };
+        let nibble = || {
+            let [n] = operand_array(operands)?;
+            Ok::<_, Error>(self.value(n, 4)? as u8)
+        };
+        let register = || {
+            let [x] = operand_array(operands)?;
+            parse_register(x)
+        };
+        let register_byte = || {
+            let [x, value] = operand_array(operands)?;
+            Ok::<_, Error>((parse_register(x)?, self.value(value, 8)? as u8))
+        };
+        let registers = || {
+            let [x, y] = operand_array(operands)?;
+            Ok::<_, Error>((parse_register(x)?, parse_register(y)?))
+        };
+
+        let instruction = match mnemonic {
+            "SCD" => Instruction::ScrollDown(nibble()?),
+            "SCU" => Instruction::ScrollUp(nibble()?),
+            "CLS" => none().map(|_| Instruction::ClearScreen)?,
+            "RTS" => none().map(|_| Instruction::Return)?,
+            "SCR" => none().map(|_| Instruction::ScrollRight)?,
+            "SCL" => none().map(|_| Instruction::ScrollLeft)?,
+            "EXIT" => none().map(|_| Instruction::Exit)?,
+            "LOW" => none().map(|_| Instruction::LowRes)?,
+            "HIGH" => none().map(|_| Instruction::HighRes)?,
+            "SYS" => Instruction::System(address()?),
+            "JUMP" => Instruction::Jump(address()?),
+            "CALL" => Instruction::Call(address()?),
+            "SKE" => register_byte().map(|(x, value)| Instruction::SkipEqual { x, value })?,
+            "SKNE" => register_byte().map(|(x, value)| Instruction::SkipNotEqual { x, value })?,
+            "SKRE" => registers().map(|(x, y)| Instruction::SkipRegistersEqual { x, y })?,
+            "SAVER" => registers().map(|(x, y)| Instruction::SaveRange { x, y })?,
+            "LOADR" => registers().map(|(x, y)| Instruction::LoadRange { x, y })?,
+            "LOAD" => register_byte().map(|(x, value)| Instruction::Load { x, value })?,
+            "ADD" => register_byte().map(|(x, value)| Instruction::Add { x, value })?,
+            "MOVE" => registers().map(|(x, y)| Instruction::Move { x, y })?,
+            "OR" => registers().map(|(x, y)| Instruction::Or { x, y })?,
+            "AND" => registers().map(|(x, y)| Instruction::And { x, y })?,
+            "XOR" => registers().map(|(x, y)| Instruction::Xor { x, y })?,
+            "ADDR" => registers().map(|(x, y)| Instruction::AddRegisters { x, y })?,
+            "SUB" => registers().map(|(x, y)| Instruction::Sub { x, y })?,
+            "SHR" => registers().map(|(x, y)| Instruction::ShiftRight { x, y })?,
+            "SUBR" => registers().map(|(x, y)| Instruction::SubReversed { x, y })?,
+            "SHL" => registers().map(|(x, y)| Instruction::ShiftLeft { x, y })?,
+            "SKRNE" => registers().map(|(x, y)| Instruction::SkipRegistersNotEqual { x, y })?,
+            "LOADI" => Instruction::LoadI(address()?),
+            "JUMPI" => Instruction::JumpPlus(address()?),
+            "RAND" => register_byte().map(|(x, mask)| Instruction::Random { x, mask })?,
+            "DRAW" => {
+                let [x, y, n] = operand_array(operands)?;
+                Instruction::Draw {
+                    x: parse_register(x)?,
+                    y: parse_register(y)?,
+                    n: self.value(n, 4)? as u8,
+                }
+            }
+            "SKEYD" => Instruction::SkipKeyDown(register()?),
+            "SKEYU" => Instruction::SkipKeyUp(register()?),
+            "LOADL" => {
+                // The address is stored in the word following the opcode
+                let [address] = operand_array(operands)?;
+                let address = self.value(address, 16)?;
+                let opcode = Instruction::LoadLongI.encode();
+                return Ok([opcode.to_be_bytes(), address.to_be_bytes()].concat());
+            }
+            "PLANE" => Instruction::SelectPlanes(nibble()?),
+            "AUDIO" => none().map(|_| Instruction::LoadAudioPattern)?,
+            "MOVEDT" => Instruction::MoveDelayTimer(register()?),
+            "KEYW" => Instruction::WaitKey(register()?),
+            "LOADD" => Instruction::SetDelayTimer(register()?),
+            "LOADS" => Instruction::SetSoundTimer(register()?),
+            "ADDI" => Instruction::AddI(register()?),
+            "LDCHR" => Instruction::Character(register()?),
+            "LDHCHR" => Instruction::BigCharacter(register()?),
+            "BCDI" => Instruction::Bcd(register()?),
+            "PITCH" => Instruction::SetPitch(register()?),
+            "STORE" => Instruction::StoreRegisters(register()?),
+            "READ" => Instruction::ReadRegisters(register()?),
+            "SAVEF" => Instruction::SaveFlags(register()?),
+            "LOADF" => Instruction::LoadFlags(register()?),
+            _ => return Err(Error::UnknownMnemonicError(mnemonic.into())),
+        };
+
+        Ok(instruction.encode().to_be_bytes().to_vec())
+    }
+
+    /// Evaluate an expression that must fit in the given number of bits.
+    /// Negative values are stored as two's complement
+    fn value(&self, expression: &str, bits: u32) -> Result<u16, Error> {
+        let value = self.evaluate(expression, 0)?;
+        if value < -(1 << (bits - 1)) || value >= 1 << bits {
+            return Err(Error::OperandRangeError(value, bits));
+        }
+        Ok((value & ((1 << bits) - 1)) as u16)
+    }
+
+    /// Evaluate terms separated by + and -
+    fn evaluate(&self, expression: &str, depth: usize) -> Result<i64, Error> {
+        let mut rest = expression.trim();
+        let mut sign = 1;
+        if let Some(negated) = rest.strip_prefix('-') {
+            sign = -1;
+            rest = negated;
+        }
+
+        let mut total = 0;
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            total += sign * self.term(rest[..end].trim(), depth)?;
+            if end == rest.len() {
+                return Ok(total);
+            }
+            sign = if rest[end..].starts_with('+') { 1 } else { -1 };
+            rest = &rest[end + 1..];
+        }
+    }
+
+    fn term(&self, term: &str, depth: usize) -> Result<i64, Error> {
+        if let Some(number) = parse_number(term)? {
+            return Ok(number);
+        }
+
+        match self.symbols.get(term) {
+            Some(Symbol::Label(address)) => Ok(*address as i64),
+            Some(Symbol::Constant(expression, location)) => {
+                // Any chain of constants longer than the symbol table is a cycle
+                if depth > self.symbols.len() {
+                    return Err(Error::RecursiveSymbolError(term.into()));
+                }
+                self.evaluate(expression, depth + 1).map_err(|e| match e {
+                    Error::LineError(..) => e,
+                    _ => Error::LineError(location.clone(), Box::new(e)),
+                })
+            }
+            None if term.is_empty() => Err(Error::SyntaxError("Missing value".into())),
+            None => Err(Error::UndefinedSymbolError(term.into())),
+        }
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// The disassembler prefixes each line with its address in uppercase hexadecimal
+fn is_address_column(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && parse_register(name).is_err()
+}
+
+/// Split off the first whitespace separated word
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// Split comma separated operands. Commas in string literals are kept
+fn split_operands(text: &str) -> Result<Vec<String>, Error> {
+    if text.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut operands = Vec::new();
+    let mut operand = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            ',' if !quoted => operands.push(std::mem::take(&mut operand)),
+            '"' => {
+                quoted = !quoted;
+                operand.push(c);
+            }
+            _ => operand.push(c),
+        }
+    }
+    if quoted {
+        return Err(Error::SyntaxError("Unterminated string".into()));
+    }
+    operands.push(operand);
+
+    let operands: Vec<String> = operands.iter().map(|o| o.trim().to_string()).collect();
+    if operands.iter().any(String::is_empty) {
+        return Err(Error::SyntaxError("Missing operand".into()));
+    }
+    Ok(operands)
+}
+
+fn operand_array<const N: usize>(operands: &[String]) -> Result<&[String; N], Error> {
+    operands.try_into().map_err(|_| Error::OperandCountError {
+        expected: N,
+        found: operands.len(),
+    })
+}
+
+pub(crate) fn parse_register(operand: &str) -> Result<u8, Error> {
+    operand
+        .strip_prefix(['V', 'v'])
+        .filter(|digit| digit.len() == 1)
+        .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+        .ok_or_else(|| Error::InvalidRegisterError(operand.into()))
+}
+
+fn parse_string(operand: &str) -> Option<&str> {
+    operand
+        .strip_prefix('"')
+        .and_then(|operand| operand.strip_suffix('"'))
+}
+
+/// Parse a decimal, 0x hexadecimal or 0b binary number. Returns None if the
+/// term is not a number
+pub(crate) fn parse_number(term: &str) -> Result<Option<i64>, Error> {
+    if !term.starts_with(|c: char| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+
+    let lower = term.to_lowercase();
+    let number = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2)
+    } else {
+        lower.parse()
+    };
+    number
+        .map(Some)
+        .map_err(|_| Error::SyntaxError(format!("Invalid number: {term}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disassembler::disassembler::disassemble;
+    use disassembler::output::OutputFormat;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn round_trip_every_instruction() {
+        let opcodes = (0..=u16::MAX).filter(|&opcode| Instruction::decode(opcode).is_some());
+        for opcode in opcodes {
+            // Each instruction starts its own ROM so that tracing reaches it.
+            // The bytes after it are the address of F000 NNNN or further code
+            let [high, low] = opcode.to_be_bytes();
+            let rom = [high, low, 0x12, 0x34];
+
+            let assembly = disassemble(&rom, BTreeMap::new(), OutputFormat::Native).listing;
+            assert!(
+                !assembly.lines().any(|line| line.starts_with("200: db")),
+                "{opcode:04X} was not disassembled as code:\n{assembly}"
+            );
+            assert_eq!(assemble(&assembly).unwrap(), rom, "{assembly}");
+        }
+
+        // Odd trailing byte
+        let rom = [0x00, 0xE0, 0xAB];
+        let assembly = disassemble(&rom, BTreeMap::new(), OutputFormat::Native).listing;
+        assert_eq!(assemble(&assembly).unwrap(), rom);
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = r#"
+            SPEED equ 3
+            start:  LOAD   V0, SPEED + 1   ; comment
+                    LOADI  sprite
+            loop:   ADD    V0, -1
+                    SKE    v0, 0
+                    JUMP   loop
+                    LOADL  end
+            sprite: db     0b11110000, 0x90, "A;B"
+                    dw     start, 0xBEEF
+            end:
+        "#;
+
+        let rom = assemble(source).unwrap();
+        let expected = [
+            0x60, 0x04, 0xA2, 0x0E, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x04, 0xF0, 0x00, 0x02, 0x17,
+            0xF0, 0x90, b'A', b';', b'B', 0x02, 0x00, 0xBE, 0xEF,
+        ];
+        assert_eq!(rom, expected);
+    }
+
+    #[test]
+    fn include_files() {
+        let dir = std::env::temp_dir().join(format!("assembler-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        let main = "JUMP main\ninclude \"lib/data.asm\"\nmain: RTS\n";
+        fs::write(dir.join("main.asm"), main).unwrap();
+        fs::write(dir.join("lib/data.asm"), "data: db 1, 2\n").unwrap();
+        fs::write(dir.join("loop.asm"), "include \"loop.asm\"\n").unwrap();
+
+        let rom = assemble_file(&dir.join("main.asm"));
+        let recursive = assemble_file(&dir.join("loop.asm"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(rom.unwrap(), [0x12, 0x04, 0x01, 0x02, 0x00, 0xEE]);
+        let Err(Error::LineError(_, error)) = recursive else {
+            panic!("Expected recursive include to fail");
+        };
+        assert!(matches!(*error, Error::RecursiveIncludeError(_)));
+    }
+
+    #[test]
+    fn errors_report_line() {
+        let cases = [
+            ("CLS\nJUMP nowhere", "<source>:2: Undefined symbol: nowhere"),
+            ("LOAD V0, 256", "<source>:1: Value 256 does not fit in 8 bits"),
+            ("DRAW V0, V1", "<source>:1: Expected 3 operands but found 2"),
+            ("MOVE V0, VG", "<source>:1: Expected register V0-VF but found: VG"),
+            ("FOO V0", "<source>:1: Unknown mnemonic: FOO"),
+            ("a: CLS\na: CLS", "<source>:2: Symbol defined more than once: a"),
+            ("a equ b\nb equ a\nJUMP a", "<source>:1: Symbol defined in terms of itself: b"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(assemble(source).unwrap_err().to_string(), message, "{source}");
+        }
+    }
+}
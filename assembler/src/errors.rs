@@ -0,0 +1,31 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to read source file: {0}")]
+    FileReadError(String),
+    #[error("Failed to write ROM file: {0}")]
+    FileWriteError(String),
+    #[error("{0}: {1}")]
+    LineError(String, Box<Error>),
+    #[error("Syntax error: {0}")]
+    SyntaxError(String),
+    #[error("Unknown mnemonic: {0}")]
+    UnknownMnemonicError(String),
+    #[error("Expected {expected} operands but found {found}")]
+    OperandCountError { expected: usize, found: usize },
+    #[error("Expected register V0-VF but found: {0}")]
+    InvalidRegisterError(String),
+    #[error("Value {0} does not fit in {1} bits")]
+    OperandRangeError(i64, u32),
+    #[error("Undefined symbol: {0}")]
+    UndefinedSymbolError(String),
+    #[error("Symbol defined more than once: {0}")]
+    DuplicateSymbolError(String),
+    #[error("Symbol defined in terms of itself: {0}")]
+    RecursiveSymbolError(String),
+    #[error("File includes itself: {0}")]
+    RecursiveIncludeError(String),
+    #[error("Program does not fit in memory")]
+    ProgramSizeError,
+}
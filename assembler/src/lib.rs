@@ -0,0 +1,3 @@
+pub mod assembler;
+pub mod errors;
+pub mod octo;
\ No newline at end of file
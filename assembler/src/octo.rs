@@ -0,0 +1,800 @@
+use std::collections::{HashMap, VecDeque};
+
+use disassembler::instruction::Instruction;
+
+use crate::assembler::{MEMORY_SIZE, START_ADDRESS, parse_number, parse_register};
+use crate::errors::Error;
+
+/// Compile a program written in Octo, the language of the Octo CHIP-8 IDE.
+/// Execution starts at the `main` label. The :calc, :stringmode and :assert
+/// directives are not supported
+pub fn compile_octo(source: &str) -> Result<Vec<u8>, Error> {
+    let mut compiler = Compiler::new(tokenize(source));
+    while let Some(token) = compiler.tokens.pop_front() {
+        compiler.line = token.line;
+        compiler
+            .statement(&token.text)
+            .map_err(|e| Error::LineError(format!("<source>:{}", token.line), Box::new(e)))?;
+    }
+    compiler.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// Address operand whose label was not defined yet when it was compiled
+#[derive(Debug)]
+struct Fixup {
+    address: u32,
+    name: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    /// Low 12 bits of the instruction
+    Address,
+    /// Whole word following a long load
+    Long,
+    /// Byte of a load holding the nibble and the high bits of the address
+    UnpackHigh(u8),
+    /// Byte of a load holding the low bits of the address
+    UnpackLow,
+}
+
+#[derive(Debug)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+// Open control flow statements with the jumps still to be patched
+#[derive(Debug)]
+enum Flow {
+    Loop { start: u32, exits: Vec<u32> },
+    If { jump: u32 },
+    Else { jump: u32 },
+}
+
+#[derive(Debug)]
+struct Compiler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    // Memory from the start address
+    memory: Vec<u8>,
+    address: u32,
+    labels: HashMap<String, u32>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>,
+    // Label placed on the operand byte of the next instruction by :next
+    next_label: Option<String>,
+}
+
+impl Compiler {
+    fn new(tokens: VecDeque<Token>) -> Self {
+        let mut compiler = Self {
+            tokens,
+            line: 0,
+            memory: Vec::new(),
+            address: START_ADDRESS,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            flow: Vec::new(),
+            next_label: None,
+        };
+        // Programs start with a jump to main, removed again if main comes first
+        compiler.fixup(START_ADDRESS, "main", FixupKind::Address);
+        compiler.memory.extend(Instruction::Jump(0).encode().to_be_bytes());
+        compiler.address += 2;
+        compiler
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), Error> {
+        match token {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(&name, self.address)?;
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                let value = self.value(&value)?;
+                self.define(&name)?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.next()?;
+                let register = self.register(&register)?;
+                self.define(&name)?;
+                self.aliases.insert(name, register);
+            }
+            ":unpack" => {
+                let nibble = self.next()?;
+                let nibble = self.value(&nibble)?;
+                if !(0..16).contains(&nibble) {
+                    return Err(Error::OperandRangeError(nibble, 4));
+                }
+                let name = self.next()?;
+                let address = self.address;
+                let high = self.operand(&name, address, FixupKind::UnpackHigh(nibble as u8))?;
+                self.instruction(Instruction::Load {
+                    x: 0,
+                    value: (nibble as u8) << 4 | (high >> 8) as u8,
+                })?;
+                let low = self.operand(&name, self.address, FixupKind::UnpackLow)?;
+                self.instruction(Instruction::Load {
+                    x: 1,
+                    value: low as u8,
+                })?;
+            }
+            ":next" => self.next_label = Some(self.next()?),
+            ":org" => {
+                let address = self.next()?;
+                let address = self.value(&address)?;
+                if !(START_ADDRESS as i64..MEMORY_SIZE as i64).contains(&address) {
+                    return Err(Error::OperandRangeError(address, 16));
+                }
+                self.address = address as u32;
+            }
+            ":byte" => {
+                let value = self.next()?;
+                let value = self.byte(&value)?;
+                self.emit(&[value])?;
+            }
+            ":pointer" => {
+                let name = self.next()?;
+                let address = self.operand(&name, self.address, FixupKind::Long)?;
+                self.emit(&address.to_be_bytes())?;
+            }
+            ":call" => {
+                let name = self.next()?;
+                self.call(&name)?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ":macro" => self.define_macro()?,
+            ":calc" | ":stringmode" | ":assert" => {
+                return Err(Error::UnknownMnemonicError(format!("{token} is not supported")));
+            }
+            ";" | "return" => self.instruction(Instruction::Return)?,
+            "clear" => self.instruction(Instruction::ClearScreen)?,
+            "hires" => self.instruction(Instruction::HighRes)?,
+            "lores" => self.instruction(Instruction::LowRes)?,
+            "exit" => self.instruction(Instruction::Exit)?,
+            "scroll-left" => self.instruction(Instruction::ScrollLeft)?,
+            "scroll-right" => self.instruction(Instruction::ScrollRight)?,
+            "audio" => self.instruction(Instruction::LoadAudioPattern)?,
+            "scroll-down" | "scroll-up" | "plane" => {
+                let n = self.next()?;
+                let n = self.nibble(&n)?;
+                self.instruction(match token {
+                    "scroll-down" => Instruction::ScrollDown(n),
+                    "scroll-up" => Instruction::ScrollUp(n),
+                    _ => Instruction::SelectPlanes(n),
+                })?;
+            }
+            "bcd" | "saveflags" | "loadflags" => {
+                let x = self.next_register()?;
+                self.instruction(match token {
+                    "bcd" => Instruction::Bcd(x),
+                    "saveflags" => Instruction::SaveFlags(x),
+                    _ => Instruction::LoadFlags(x),
+                })?;
+            }
+            "save" | "load" => {
+                let x = self.next_register()?;
+                let range = self.tokens.front().is_some_and(|token| token.text == "-");
+                let instruction = match (token, range) {
+                    ("save", false) => Instruction::StoreRegisters(x),
+                    (_, false) => Instruction::ReadRegisters(x),
+                    (_, true) => {
+                        self.next()?;
+                        let y = self.next_register()?;
+                        match token {
+                            "save" => Instruction::SaveRange { x, y },
+                            _ => Instruction::LoadRange { x, y },
+                        }
+                    }
+                };
+                self.instruction(instruction)?;
+            }
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let n = self.next()?;
+                let n = self.nibble(&n)?;
+                self.instruction(Instruction::Draw { x, y, n })?;
+            }
+            "jump" | "jump0" | "native" => {
+                let name = self.next()?;
+                let target = self.operand(&name, self.address, FixupKind::Address)?;
+                self.instruction(match token {
+                    "jump" => Instruction::Jump(target),
+                    "jump0" => Instruction::JumpPlus(target),
+                    _ => Instruction::System(target),
+                })?;
+            }
+            "i" => self.i_register()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.next_register()?;
+                self.instruction(match token {
+                    "delay" => Instruction::SetDelayTimer(x),
+                    "buzzer" => Instruction::SetSoundTimer(x),
+                    _ => Instruction::SetPitch(x),
+                })?;
+            }
+            "loop" => self.flow.push(Flow::Loop {
+                start: self.address,
+                exits: Vec::new(),
+            }),
+            "while" => {
+                let jump = self.skip_unless()?;
+                match self.flow.iter_mut().rev().find(|flow| matches!(flow, Flow::Loop { .. })) {
+                    Some(Flow::Loop { exits, .. }) => exits.push(jump),
+                    _ => return Err(Error::SyntaxError("while outside of loop".into())),
+                }
+            }
+            "again" => match self.flow.pop() {
+                Some(Flow::Loop { start, exits }) => {
+                    self.instruction(Instruction::Jump(start as u16))?;
+                    for exit in exits {
+                        self.patch_jump(exit, self.address);
+                    }
+                }
+                _ => return Err(Error::SyntaxError("again without loop".into())),
+            },
+            "if" => self.conditional()?,
+            "else" => match self.flow.pop() {
+                Some(Flow::If { jump }) => {
+                    let end = self.address;
+                    self.instruction(Instruction::Jump(0))?;
+                    self.patch_jump(jump, self.address);
+                    self.flow.push(Flow::Else { jump: end });
+                }
+                _ => return Err(Error::SyntaxError("else without begin".into())),
+            },
+            "end" => match self.flow.pop() {
+                Some(Flow::If { jump } | Flow::Else { jump }) => {
+                    self.patch_jump(jump, self.address)
+                }
+                _ => return Err(Error::SyntaxError("end without begin".into())),
+            },
+            _ if self.is_register(token) => self.register_statement(token)?,
+            _ if self.macros.contains_key(token) => self.expand_macro(token)?,
+            _ if token.starts_with(|c: char| c.is_ascii_digit() || c == '-') => {
+                let value = self.byte(token)?;
+                self.emit(&[value])?;
+            }
+            _ if is_identifier(token) => self.call(token)?,
+            _ => return Err(Error::SyntaxError(format!("Unexpected {token}"))),
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, Error> {
+        if !self.flow.is_empty() {
+            return Err(Error::SyntaxError("Missing end or again at end of file".into()));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let located = |e| match fixup.line {
+                0 => e,
+                line => Error::LineError(format!("<source>:{line}"), Box::new(e)),
+            };
+            let value = self.symbol(&fixup.name).map_err(located)?;
+            let offset = (fixup.address - START_ADDRESS) as usize;
+            match fixup.kind {
+                FixupKind::Address => {
+                    let address = fit(value, 12).map_err(located)?;
+                    self.memory[offset] |= (address >> 8) as u8;
+                    self.memory[offset + 1] = address as u8;
+                }
+                FixupKind::Long => {
+                    let address = fit(value, 16).map_err(located)?;
+                    self.memory[offset..offset + 2].copy_from_slice(&address.to_be_bytes());
+                }
+                FixupKind::UnpackHigh(nibble) => {
+                    let address = fit(value, 12).map_err(located)?;
+                    self.memory[offset + 1] = nibble << 4 | (address >> 8) as u8;
+                }
+                FixupKind::UnpackLow => {
+                    self.memory[offset + 1] = fit(value, 12).map_err(located)? as u8;
+                }
+            }
+        }
+        Ok(self.memory)
+    }
+
+    fn next(&mut self) -> Result<String, Error> {
+        self.tokens
+            .pop_front()
+            .map(|token| token.text)
+            .ok_or_else(|| Error::SyntaxError("Unexpected end of file".into()))
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), Error> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(Error::SyntaxError(format!("Expected {expected} but found {token}")));
+        }
+        Ok(())
+    }
+
+    fn define(&self, name: &str) -> Result<(), Error> {
+        if !is_identifier(name) || self.is_register(name) {
+            return Err(Error::SyntaxError(format!("Invalid name: {name}")));
+        }
+        if self.labels.contains_key(name)
+            || self.constants.contains_key(name)
+            || self.aliases.contains_key(name)
+            || self.macros.contains_key(name)
+        {
+            return Err(Error::DuplicateSymbolError(name.into()));
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: &str, address: u32) -> Result<(), Error> {
+        self.define(name)?;
+        // Nothing but the jump to main comes before it, so the jump is not needed
+        if name == "main" && address == START_ADDRESS + 2 && self.memory.len() == 2 {
+            self.memory.clear();
+            self.fixups.retain(|fixup| fixup.address != START_ADDRESS);
+            self.address = START_ADDRESS;
+            self.labels.insert(name.into(), START_ADDRESS);
+            return Ok(());
+        }
+        self.labels.insert(name.into(), address);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), Error> {
+        let name = self.next()?;
+        self.define(&name)?;
+        let mut parameters = Vec::new();
+        loop {
+            match self.next()?.as_str() {
+                "{" => break,
+                parameter => parameters.push(parameter.to_string()),
+            }
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| Error::SyntaxError(format!("Unterminated macro {name}")))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { parameters, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), Error> {
+        let count = self.macros[name].parameters.len();
+        let arguments = (0..count).map(|_| self.next()).collect::<Result<Vec<_>, _>>()?;
+        let definition = &self.macros[name];
+        for token in definition.body.iter().rev() {
+            let text = match definition.parameters.iter().position(|p| *p == token.text) {
+                Some(i) => arguments[i].clone(),
+                None => token.text.clone(),
+            };
+            self.tokens.push_front(Token {
+                text,
+                line: self.line,
+            });
+        }
+        Ok(())
+    }
+
+    fn fixup(&mut self, address: u32, name: &str, kind: FixupKind) {
+        self.fixups.push(Fixup {
+            address,
+            name: name.into(),
+            kind,
+            line: self.line,
+        });
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let offset = (self.address - START_ADDRESS) as usize;
+        let end = offset + bytes.len();
+        if end > (MEMORY_SIZE - START_ADDRESS) as usize {
+            return Err(Error::ProgramSizeError);
+        }
+        if self.memory.len() < end {
+            self.memory.resize(end, 0);
+        }
+        self.memory[offset..end].copy_from_slice(bytes);
+        self.address += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: Instruction) -> Result<(), Error> {
+        if let Some(name) = self.next_label.take() {
+            self.define_label(&name, self.address + 1)?;
+        }
+        self.emit(&instruction.encode().to_be_bytes())
+    }
+
+    fn patch_jump(&mut self, address: u32, target: u32) {
+        let offset = (address - START_ADDRESS) as usize;
+        let jump = Instruction::Jump(target as u16).encode().to_be_bytes();
+        self.memory[offset..offset + 2].copy_from_slice(&jump);
+    }
+
+    fn call(&mut self, name: &str) -> Result<(), Error> {
+        let target = self.operand(name, self.address, FixupKind::Address)?;
+        self.instruction(Instruction::Call(target))
+    }
+
+    fn i_register(&mut self) -> Result<(), Error> {
+        let operator = self.next()?;
+        let operand = self.next()?;
+        let instruction = match (operator.as_str(), operand.as_str()) {
+            (":=", "long") => {
+                let name = self.next()?;
+                let address = self.operand(&name, self.address + 2, FixupKind::Long)?;
+                self.instruction(Instruction::LoadLongI)?;
+                return self.emit(&address.to_be_bytes());
+            }
+            (":=", "hex") => Instruction::Character(self.next_register()?),
+            (":=", "bighex") => Instruction::BigCharacter(self.next_register()?),
+            (":=", _) => {
+                Instruction::LoadI(self.operand(&operand, self.address, FixupKind::Address)?)
+            }
+            ("+=", _) => Instruction::AddI(self.register(&operand)?),
+            _ => return Err(Error::SyntaxError(format!("Unexpected i {operator}"))),
+        };
+        self.instruction(instruction)
+    }
+
+    fn register_statement(&mut self, token: &str) -> Result<(), Error> {
+        let x = self.register(token)?;
+        let operator = self.next()?;
+        let operand = self.next()?;
+        let register = self.register(&operand).ok();
+
+        let instruction = match (operator.as_str(), operand.as_str(), register) {
+            (":=", "random", _) => {
+                let mask = self.next()?;
+                Instruction::Random {
+                    x,
+                    mask: self.byte(&mask)?,
+                }
+            }
+            (":=", "key", _) => Instruction::WaitKey(x),
+            (":=", "delay", _) => Instruction::MoveDelayTimer(x),
+            (":=", _, Some(y)) => Instruction::Move { x, y },
+            (":=", _, None) => Instruction::Load {
+                x,
+                value: self.byte(&operand)?,
+            },
+            ("+=", _, Some(y)) => Instruction::AddRegisters { x, y },
+            ("+=", _, None) => Instruction::Add {
+                x,
+                value: self.byte(&operand)?,
+            },
+            ("-=", _, Some(y)) => Instruction::Sub { x, y },
+            ("-=", _, None) => Instruction::Add {
+                x,
+                value: self.byte(&operand)?.wrapping_neg(),
+            },
+            ("=-", _, Some(y)) => Instruction::SubReversed { x, y },
+            ("|=", _, Some(y)) => Instruction::Or { x, y },
+            ("&=", _, Some(y)) => Instruction::And { x, y },
+            ("^=", _, Some(y)) => Instruction::Xor { x, y },
+            (">>=", _, Some(y)) => Instruction::ShiftRight { x, y },
+            ("<<=", _, Some(y)) => Instruction::ShiftLeft { x, y },
+            _ => return Err(Error::SyntaxError(format!("Unexpected {operator} {operand}"))),
+        };
+        self.instruction(instruction)
+    }
+
+    /// Compile an if statement, either guarding the next statement with then
+    /// or a block ending with end
+    fn conditional(&mut self) -> Result<(), Error> {
+        let position = self.tokens.iter().position(|t| t.text == "then" || t.text == "begin");
+        let block = match position.map(|i| self.tokens[i].text.as_str()) {
+            Some("then") => false,
+            Some(_) => true,
+            None => return Err(Error::SyntaxError("if without then or begin".into())),
+        };
+
+        if block {
+            let jump = self.skip_unless()?;
+            self.expect("begin")?;
+            self.flow.push(Flow::If { jump });
+        } else {
+            for instruction in self.condition(false)? {
+                self.instruction(instruction)?;
+            }
+            self.expect("then")?;
+        }
+        Ok(())
+    }
+
+    /// Compile a condition followed by a jump taken when it does not hold.
+    /// Returns the address of the jump
+    fn skip_unless(&mut self) -> Result<u32, Error> {
+        for instruction in self.condition(true)? {
+            self.instruction(instruction)?;
+        }
+        let jump = self.address;
+        self.instruction(Instruction::Jump(0))?;
+        Ok(jump)
+    }
+
+    /// Instructions after which the next one only runs if the condition holds.
+    /// Comparisons other than equality use VF
+    fn condition(&mut self, negate: bool) -> Result<Vec<Instruction>, Error> {
+        let x = self.next_register()?;
+        let operator = self.next()?;
+        let operator = match (operator.as_str(), negate) {
+            (operator, false) => operator,
+            ("==", true) => "!=",
+            ("!=", true) => "==",
+            ("key", true) => "-key",
+            ("-key", true) => "key",
+            ("<", true) => ">=",
+            (">=", true) => "<",
+            (">", true) => "<=",
+            ("<=", true) => ">",
+            (operator, true) => operator,
+        };
+        if operator == "key" {
+            return Ok(vec![Instruction::SkipKeyUp(x)]);
+        } else if operator == "-key" {
+            return Ok(vec![Instruction::SkipKeyDown(x)]);
+        }
+
+        let operand = self.next()?;
+        let y = self.register(&operand).ok();
+        let into_vf = match y {
+            Some(y) => Instruction::Move { x: 0xF, y },
+            None => Instruction::Load {
+                x: 0xF,
+                value: self.byte(&operand)?,
+            },
+        };
+        let instructions = match (operator, y) {
+            ("==", Some(y)) => vec![Instruction::SkipRegistersNotEqual { x, y }],
+            ("==", None) => vec![Instruction::SkipNotEqual {
+                x,
+                value: self.byte(&operand)?,
+            }],
+            ("!=", Some(y)) => vec![Instruction::SkipRegistersEqual { x, y }],
+            ("!=", None) => vec![Instruction::SkipEqual {
+                x,
+                value: self.byte(&operand)?,
+            }],
+            // VF is set when VX is at least the operand
+            ("<" | ">=", _) => vec![
+                into_vf,
+                Instruction::SubReversed { x: 0xF, y: x },
+                Instruction::SkipNotEqual {
+                    x: 0xF,
+                    value: (operator == ">=") as u8,
+                },
+            ],
+            // VF is set when VX is at most the operand
+            (">" | "<=", _) => vec![
+                into_vf,
+                Instruction::Sub { x: 0xF, y: x },
+                Instruction::SkipNotEqual {
+                    x: 0xF,
+                    value: (operator == "<=") as u8,
+                },
+            ],
+            _ => return Err(Error::SyntaxError(format!("Unknown comparison {operator}"))),
+        };
+        Ok(instructions)
+    }
+
+    fn is_register(&self, token: &str) -> bool {
+        self.register(token).is_ok()
+    }
+
+    fn register(&self, token: &str) -> Result<u8, Error> {
+        match self.aliases.get(token) {
+            Some(register) => Ok(*register),
+            None => parse_register(token),
+        }
+    }
+
+    fn next_register(&mut self) -> Result<u8, Error> {
+        let token = self.next()?;
+        self.register(&token)
+    }
+
+    /// Value of a number, constant or label defined so far
+    fn value(&self, token: &str) -> Result<i64, Error> {
+        let (sign, unsigned) = match token.strip_prefix('-') {
+            Some(unsigned) => (-1, unsigned),
+            None => (1, token),
+        };
+        match parse_number(unsigned)? {
+            Some(number) => Ok(sign * number),
+            None if sign == 1 => self.symbol(token),
+            None => Err(Error::SyntaxError(format!("Invalid number: {token}"))),
+        }
+    }
+
+    fn symbol(&self, name: &str) -> Result<i64, Error> {
+        self.constants
+            .get(name)
+            .copied()
+            .or_else(|| self.labels.get(name).map(|&address| address as i64))
+            .ok_or_else(|| Error::UndefinedSymbolError(name.into()))
+    }
+
+    fn byte(&self, token: &str) -> Result<u8, Error> {
+        Ok(fit(self.value(token)?, 8)? as u8)
+    }
+
+    fn nibble(&self, token: &str) -> Result<u8, Error> {
+        let value = self.value(token)?;
+        if !(0..16).contains(&value) {
+            return Err(Error::OperandRangeError(value, 4));
+        }
+        Ok(value as u8)
+    }
+
+    /// Address operand of the instruction at the address. Labels that are not
+    /// defined yet are filled in at the end
+    fn operand(&mut self, token: &str, address: u32, kind: FixupKind) -> Result<u16, Error> {
+        match self.value(token) {
+            Ok(value) => {
+                let bits = match kind {
+                    FixupKind::Long => 16,
+                    _ => 12,
+                };
+                fit(value, bits)
+            }
+            Err(Error::UndefinedSymbolError(_)) => {
+                self.fixup(address, token, kind);
+                Ok(0)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Octo tokens are separated by whitespace. Comments start with #
+fn tokenize(source: &str) -> VecDeque<Token> {
+    source
+        .lines()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace().map(move |text| Token {
+                text: text.into(),
+                line: i + 1,
+            })
+        })
+        .collect()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '-')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Negative values are stored as two's complement
+fn fit(value: i64, bits: u32) -> Result<u16, Error> {
+    if value < -(1 << (bits - 1)) || value >= 1 << bits {
+        return Err(Error::OperandRangeError(value, bits));
+    }
+    Ok((value & ((1 << bits) - 1)) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disassembler::disassembler::disassemble;
+    use disassembler::output::OutputFormat;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn round_trip_every_instruction() {
+        let opcodes = (0..=u16::MAX).filter(|&opcode| Instruction::decode(opcode).is_some());
+        for opcode in opcodes {
+            // Each instruction starts its own ROM so that tracing reaches it.
+            // The bytes after it are the address of F000 NNNN or further code
+            let [high, low] = opcode.to_be_bytes();
+            let rom = [high, low, 0x12, 0x34];
+
+            // Octo writes machine code calls as data, but nothing else
+            let source = disassemble(&rom, BTreeMap::new(), OutputFormat::Octo).listing;
+            let first = source.lines().find(|line| line.contains("# 200")).unwrap();
+            let data_bytes = first
+                .split_whitespace()
+                .take_while(|word| word.starts_with("0x"))
+                .count();
+            let expected = match Instruction::decode(opcode) {
+                Some(Instruction::System(_)) => 2,
+                _ => 0,
+            };
+            assert_eq!(
+                data_bytes, expected,
+                "{opcode:04X} was not disassembled as code:\n{source}"
+            );
+            assert_eq!(compile_octo(&source).unwrap(), rom, "{source}");
+        }
+
+        // Odd trailing byte
+        let rom = [0x00, 0xE0, 0xAB];
+        let source = disassemble(&rom, BTreeMap::new(), OutputFormat::Octo).listing;
+        assert_eq!(compile_octo(&source).unwrap(), rom);
+    }
+
+    #[test]
+    fn control_flow_and_directives() {
+        let source = "
+            :alias x v3
+            :const SPEED 2
+            :macro step r { r += SPEED }
+            : main
+                loop
+                    step x
+                    while x != 10
+                    if x < v4 begin
+                        clear
+                    else
+                        i := long sprite
+                    end
+                again
+                :unpack 0xA sprite
+                :next patched
+                v5 := 0
+                jump patched
+            : sprite 0xFF -1
+        ";
+        let rom = compile_octo(source).unwrap();
+        let expected = [
+            0x73, 0x02, 0x43, 0x0A, 0x12, 0x18, 0x8F, 0x40, 0x8F, 0x37, 0x4F, 0x01, 0x12, 0x12,
+            0x00, 0xE0, 0x12, 0x16, 0xF0, 0x00, 0x02, 0x20, 0x12, 0x00, 0x60, 0xA2, 0x61, 0x20,
+            0x65, 0x00, 0x12, 0x1D, 0xFF, 0xFF,
+        ];
+        assert_eq!(rom, expected);
+    }
+
+    #[test]
+    fn main_jump_kept_when_main_is_not_first() {
+        let rom = compile_octo(": data 1 2 : main jump data").unwrap();
+        assert_eq!(rom, [0x12, 0x04, 0x01, 0x02, 0x12, 0x02]);
+
+        let error = compile_octo("clear\nloop\nv0 := 1").unwrap_err();
+        assert_eq!(error.to_string(), "Syntax error: Missing end or again at end of file");
+        let error = compile_octo(": main\njump nowhere").unwrap_err();
+        assert_eq!(error.to_string(), "<source>:2: Undefined symbol: nowhere");
+    }
+}
@@ -1,23 +1,116 @@
-use core::emulator::Emulator;
+use core::cartridge::{export_cartridge, load_rom};
+use core::debugger::Debugger;
+use core::emulator::{Emulator, EmulatorConfig};
+use core::platform::Platform;
+use core::quirks::QuirkPreset;
+use core::trace::{TraceFilter, TraceFormat, TraceOptions};
+use std::fs;
+use std::ops::RangeInclusive;
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{bail, Result};
+use assembler::assembler::assembler;
+use clap::{Args, Parser, Subcommand};
 use disassembler::disassembler::disassembler;
+use disassembler::graph::cfg;
+use disassembler::instruction::Extension;
+use disassembler::output::OutputFormat;
+use disassembler::quirks::{detect_quirks, detect_quirks_file, Profile, QuirkReport};
 
 const DEFAULT_SCALE: u32 = 20;
+const DEFAULT_REWIND_SECONDS: u32 = 10;
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
     match cli.command {
         Commands::Run {
-            rom_path,
-            window_scale,
+            machine,
+            load_state,
+            rewind,
+            vip_rng,
+            record,
+            replay,
+            gdb,
+            trace,
+            trace_format,
+            trace_range,
+            trace_start,
+            trace_limit,
         } => {
-            let mut emulator = Emulator::try_new(&rom_path, window_scale)?;
+            let config = EmulatorConfig {
+                rewind_seconds: rewind,
+                vip_interpreter: vip_rng.map(fs::read).transpose()?,
+                record,
+                replay,
+                gdb_port: gdb,
+                trace: trace.map(|path| TraceOptions {
+                    path,
+                    format: trace_format,
+                    filter: TraceFilter {
+                        range: trace_range,
+                        start: trace_start,
+                        limit: trace_limit,
+                    },
+                }),
+                ..machine.config()?
+            };
+            let mut emulator = Emulator::try_new(&machine.rom_path, config)?;
+            if let Some(state_path) = load_state {
+                emulator.load_state(&state_path)?;
+            }
             emulator.run()?;
         },
-        Commands::Disassemble { rom_path, output } => {
-            disassembler(&rom_path, output)?;
+        Commands::Debug { machine } => {
+            let emulator = Emulator::try_new(&machine.rom_path, machine.config()?)?;
+            Debugger::new(emulator).run()?;
+        }
+        Commands::Disassemble {
+            rom_path,
+            output,
+            symbols,
+            strict,
+            format,
+        } => {
+            let disassembly = disassembler(&rom_path, symbols.as_deref(), format)?;
+            for warning in &disassembly.warnings {
+                eprintln!("Warning: {warning}");
+            }
+            if strict && !disassembly.warnings.is_empty() {
+                bail!("{} warnings while disassembling", disassembly.warnings.len());
+            }
+            disassembly.write(output)?;
+        }
+        Commands::Cfg {
+            rom_path,
+            output,
+            symbols,
+        } => {
+            cfg(&rom_path, symbols.as_deref(), output)?;
+        }
+        Commands::Assemble {
+            source_path,
+            output,
+        } => {
+            assembler(&source_path, output)?;
+        }
+        Commands::ExportCart {
+            rom_path,
+            output,
+            platform,
+            quirks,
+        } => {
+            export_cartridge(&rom_path, output, platform, quirks.map(QuirkPreset::quirks))?;
+        }
+        Commands::Info { rom_path } => {
+            let report = detect_quirks_file(&rom_path)?;
+            println!("Platform: {}", report.platform);
+            println!("Quirks: {}", report.profile);
+            println!("Confidence: {}", report.confidence);
+            if !report.evidence.is_empty() {
+                println!("Reasons:");
+                for evidence in &report.evidence {
+                    println!("  {evidence}");
+                }
+            }
         }
     }
 
@@ -36,14 +129,194 @@ struct Cli {
 enum Commands {
     /// Run rom in emulator
     Run {
-        rom_path: String,
-        #[arg(short, long, default_value_t = DEFAULT_SCALE)]
-        window_scale: u32,
+        #[command(flatten)]
+        machine: MachineArgs,
+        /// Save state file to resume from
+        #[arg(long, conflicts_with_all = ["record", "replay"])]
+        load_state: Option<String>,
+        /// Seconds of gameplay kept for rewinding with backspace
+        #[arg(long, default_value_t = DEFAULT_REWIND_SECONDS)]
+        rewind: u32,
+        /// Emulate the COSMAC VIP random routine using this interpreter image
+        #[arg(long, conflicts_with_all = ["record", "replay"])]
+        vip_rng: Option<String>,
+        /// Record key input to a movie file
+        #[arg(long, conflicts_with = "replay")]
+        record: Option<String>,
+        /// Play back a movie file recorded with --record
+        #[arg(long)]
+        replay: Option<String>,
+        /// Wait for a GDB client to connect to this local port
+        #[arg(long)]
+        gdb: Option<u16>,
+        /// Write every executed instruction to a trace file
+        #[arg(long)]
+        trace: Option<String>,
+        /// Trace file format: text or json
+        #[arg(long, default_value = "text", requires = "trace")]
+        trace_format: TraceFormat,
+        /// Only trace instructions in this hexadecimal address range, e.g. 200-2FF
+        #[arg(long, value_parser = parse_address_range, requires = "trace")]
+        trace_range: Option<RangeInclusive<u16>>,
+        /// Number of instructions to execute before tracing starts
+        #[arg(long, default_value_t = 0, requires = "trace")]
+        trace_start: u64,
+        /// Maximum number of instructions to trace
+        #[arg(long, requires = "trace")]
+        trace_limit: Option<u64>,
+    },
+    /// Run rom in the interactive debugger
+    Debug {
+        #[command(flatten)]
+        machine: MachineArgs,
     },
     /// Disassemble ROM
     Disassemble {
         rom_path: String,
         #[arg(short, long)]
         output: Option<String>,
+        /// File naming addresses, one hexadecimal address and name per line
+        #[arg(long)]
+        symbols: Option<String>,
+        /// Fail instead of writing the listing if there are any warnings
+        #[arg(long)]
+        strict: bool,
+        /// Listing syntax: native, cowgod, octo or json
+        #[arg(short, long, default_value = "native")]
+        format: OutputFormat,
+    },
+    /// Write the control flow graph of a ROM as Graphviz DOT
+    Cfg {
+        rom_path: String,
+        #[arg(short, long)]
+        output: Option<String>,
+        /// File naming addresses, one hexadecimal address and name per line
+        #[arg(long)]
+        symbols: Option<String>,
     },
+    /// Guess the platform and quirk profile a ROM needs
+    Info { rom_path: String },
+    /// Assemble source file into ROM
+    Assemble {
+        source_path: String,
+        /// ROM file to write. Defaults to the source path with a .ch8 extension
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Write ROM as an Octo cartridge GIF holding its disassembly and settings
+    ExportCart {
+        rom_path: String,
+        /// GIF file to write. Defaults to the ROM path with a .gif extension
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Platform stored in the cartridge. Defaults to the ROM database
+        /// entry, then chip8
+        #[arg(short, long)]
+        platform: Option<Platform>,
+        /// Quirk profile stored in the cartridge. Defaults to the ROM database
+        /// entry, then the platform's quirks
+        #[arg(short, long)]
+        quirks: Option<QuirkPreset>,
+    },
+}
+
+/// Options describing the emulated machine
+#[derive(Args)]
+struct MachineArgs {
+    rom_path: String,
+    #[arg(short, long, default_value_t = DEFAULT_SCALE)]
+    window_scale: u32,
+    /// Platform to emulate: chip8, schip or xochip. Defaults to the
+    /// cartridge options, then the ROM database entry, then chip8
+    #[arg(short, long)]
+    platform: Option<Platform>,
+    /// Quirk profile: vip, chip48, schip or xochip. Defaults to the
+    /// cartridge options, then the ROM database entry, then the platform's quirks
+    #[arg(short, long)]
+    quirks: Option<QuirkPreset>,
+    /// Seed for the random number generator used by CXNN
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Choose the platform and quirk profile by analysing the ROM. An explicit
+    /// quirk profile still takes precedence
+    #[arg(long, conflicts_with = "platform")]
+    detect: bool,
+    /// Keyboard layout (qwerty, qwertz, azerty, dvorak or colemak) or keymap
+    /// file. Defaults to keymap.json in the configuration directory with
+    /// the ROM database bindings applied, then qwerty
+    #[arg(long)]
+    keymap: Option<String>,
+    /// Gamepad profile file mapping SDL button names to keys. Defaults to
+    /// gamepad.json in the configuration directory, then the ROM database key
+    /// hints
+    #[arg(long)]
+    gamepad: Option<String>,
+    /// Colour palette: classic, amber, white, lcd, octo, a palette from
+    /// palettes.json in the configuration directory, or up to four comma
+    /// separated RRGGBB colours starting with the background. F9 cycles
+    /// through the palettes while running
+    #[arg(long)]
+    palette: Option<String>,
+}
+
+impl MachineArgs {
+    /// Emulator settings with rewinding and movies disabled
+    fn config(&self) -> Result<EmulatorConfig> {
+        let (platform, detected_quirks) = if self.detect {
+            // Cartridges are detected from the ROM compiled from their program
+            let (rom, _) = load_rom(&self.rom_path)?;
+            let report = detect_quirks(&rom);
+            eprintln!(
+                "Detected platform {} with {} quirks ({} confidence)",
+                report.platform, report.profile, report.confidence
+            );
+            let (platform, quirks) = detected_machine(&report);
+            (Some(platform), Some(quirks))
+        } else {
+            (self.platform, None)
+        };
+        Ok(EmulatorConfig {
+            window_scale: self.window_scale,
+            platform,
+            quirks: self.quirks.or(detected_quirks).map(QuirkPreset::quirks),
+            rewind_seconds: 0,
+            seed: self.seed,
+            vip_interpreter: None,
+            record: None,
+            replay: None,
+            gdb_port: None,
+            trace: None,
+            keymap: self.keymap.clone(),
+            gamepad: self.gamepad.clone(),
+            palette: self.palette.clone(),
+        })
+    }
+}
+
+/// Emulated platform and quirk profile matching a detection report
+fn detected_machine(report: &QuirkReport) -> (Platform, QuirkPreset) {
+    let platform = match report.platform {
+        Extension::Chip8 => Platform::Chip8,
+        Extension::SuperChip => Platform::SuperChip,
+        Extension::XoChip => Platform::XoChip,
+    };
+    let quirks = match report.profile {
+        Profile::CosmacVip => QuirkPreset::CosmacVip,
+        Profile::Chip48 => QuirkPreset::Chip48,
+        Profile::SuperChip => QuirkPreset::SuperChip11,
+        Profile::XoChip => QuirkPreset::XoChip,
+    };
+    (platform, quirks)
+}
+
+/// Parse hexadecimal address range written as start-end
+fn parse_address_range(range: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| "Expected range as start-end".to_string())?;
+    let parse = |address: &str| {
+        u16::from_str_radix(address.trim_start_matches("0x"), 16)
+            .map_err(|_| format!("Invalid address: {address}"))
+    };
+    Ok(parse(start)?..=parse(end)?)
 }
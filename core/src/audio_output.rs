@@ -1,13 +1,24 @@
-use rodio::{source::SineWave, OutputStream, Sink};
+use std::time::Duration;
+
+use rodio::{source::SineWave, OutputStream, Sink, Source};
 
 use crate::errors::{Error, Result};
+use crate::helpers::bit_to_bool;
 
 const BEEP_FREQ: f32 = 550.0;
 
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+// XO-CHIP pattern playback rate in bits per second at the default pitch of 64
+const PATTERN_BASE_RATE: f32 = 4000.0;
+const PATTERN_SAMPLE_RATE: u32 = 48000;
+const PATTERN_AMPLITUDE: f32 = 0.3;
+
 pub struct AudioOutput {
     _stream: OutputStream,
     sink: Sink,
     enabled: bool,
+    // XO-CHIP audio pattern and pitch replacing the beep when set
+    pattern: Option<([u8; AUDIO_PATTERN_SIZE], u8)>,
 }
 
 impl AudioOutput {
@@ -16,17 +27,24 @@ impl AudioOutput {
             OutputStream::try_default().map_err(|e| Error::AudioOutputError(e.to_string()))?;
         let sink =
             Sink::try_new(&stream_handle).map_err(|e| Error::AudioOutputError(e.to_string()))?;
-        
+
         Ok(Self {
             _stream,
             sink,
             enabled: false,
+            pattern: None,
         })
     }
 
     pub fn start(&mut self) {
-        let beep = SineWave::new(BEEP_FREQ);
-        self.sink.append(beep);
+        if self.enabled {
+            return;
+        }
+
+        match self.pattern {
+            Some((pattern, pitch)) => self.sink.append(PatternWave::new(pattern, pitch)),
+            None => self.sink.append(SineWave::new(BEEP_FREQ)),
+        }
         self.enabled = true;
     }
 
@@ -36,4 +54,68 @@ impl AudioOutput {
         }
         self.enabled = false;
     }
+
+    /// Play the given 128-bit XO-CHIP pattern instead of the beep.
+    /// Restarts playback if the pattern changes while sound is on
+    pub fn set_pattern(&mut self, pattern: [u8; AUDIO_PATTERN_SIZE], pitch: u8) {
+        if self.pattern == Some((pattern, pitch)) {
+            return;
+        }
+
+        self.pattern = Some((pattern, pitch));
+        if self.enabled {
+            self.stop();
+            self.start();
+        }
+    }
+}
+
+/// Infinite source looping over the bits of an XO-CHIP audio pattern
+struct PatternWave {
+    pattern: [u8; AUDIO_PATTERN_SIZE],
+    // Bits advanced per output sample
+    step: f32,
+    position: f32,
+}
+
+impl PatternWave {
+    fn new(pattern: [u8; AUDIO_PATTERN_SIZE], pitch: u8) -> Self {
+        let rate = PATTERN_BASE_RATE * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+        Self {
+            pattern,
+            step: rate / PATTERN_SAMPLE_RATE as f32,
+            position: 0.0,
+        }
+    }
+}
+
+impl Iterator for PatternWave {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let bit = self.position as usize;
+        let on = bit_to_bool(self.pattern[bit / 8], (bit % 8) as u8);
+
+        self.position = (self.position + self.step) % (AUDIO_PATTERN_SIZE * 8) as f32;
+
+        Some(if on { PATTERN_AMPLITUDE } else { -PATTERN_AMPLITUDE })
+    }
+}
+
+impl Source for PatternWave {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        PATTERN_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
 }
@@ -0,0 +1,115 @@
+use crate::errors::{Error, Result};
+
+/// Little-endian binary writer
+pub(crate) struct BinaryWriter {
+    buffer: Vec<u8>,
+}
+
+impl BinaryWriter {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.buffer.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u128(&mut self, value: u128) {
+        self.bytes(&value.to_le_bytes());
+    }
+}
+
+/// Little-endian binary reader failing on truncated input
+pub(crate) struct BinaryReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    // Constructs the error reported for malformed input
+    error: fn(String) -> Error,
+}
+
+impl<'a> BinaryReader<'a> {
+    pub fn new(bytes: &'a [u8], error: fn(String) -> Error) -> Self {
+        Self {
+            bytes,
+            position: 0,
+            error,
+        }
+    }
+
+    /// Get error of the reader's kind with the given message
+    pub fn error(&self, message: &str) -> Error {
+        (self.error)(message.into())
+    }
+
+    /// Fail if any input is left after the last value read
+    pub fn finish(&self) -> Result<()> {
+        if self.position != self.bytes.len() {
+            return Err(self.error("Unexpected data at end of file"));
+        }
+        Ok(())
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self.position + length;
+        if end > self.bytes.len() {
+            return Err(self.error("Unexpected end of file"));
+        }
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn u128(&mut self) -> Result<u128> {
+        Ok(u128::from_le_bytes(self.array()?))
+    }
+}
@@ -0,0 +1,345 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use assembler::octo::compile_octo;
+use disassembler::disassembler::disassemble;
+use disassembler::output::OutputFormat;
+use gif::{ColorOutput, DecodeOptions, Encoder, Frame};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::display::DEFAULT_PALETTE;
+use crate::emulator::CYCLES_PER_FRAME;
+use crate::errors::{Error, Result};
+use crate::helpers::rom_hash;
+use crate::palette::parse_color;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::rom_database::RomDatabase;
+
+const GIF_SIGNATURE: &[u8; 3] = b"GIF";
+// Pixels per row of exported cartridges
+const CARTRIDGE_WIDTH: usize = 128;
+// Payload bits held in the palette index of each pixel
+const BITS_PER_PIXEL: usize = 2;
+const PIXELS_PER_BYTE: usize = 8 / BITS_PER_PIXEL;
+// Shades of exported cartridges. The payload only changes pixels slightly
+const CARTRIDGE_PALETTE: [[u8; 3]; 4] = [
+    [0x1E, 0x1E, 0x2E],
+    [0x22, 0x22, 0x32],
+    [0x26, 0x26, 0x36],
+    [0x2A, 0x2A, 0x3A],
+];
+// Octo option names of each pixel colour, starting with the background
+const COLOR_OPTIONS: [&str; 4] = ["backgroundColor", "fillColor", "fillColor2", "blendColor"];
+// Program size limits Octo uses for each platform
+const MAX_SIZES: [(Platform, u32); 3] = [
+    (Platform::Chip8, 3232),
+    (Platform::SuperChip, 3583),
+    (Platform::XoChip, 65024),
+];
+
+/// Octo program and the settings it runs with, stored in a GIF image. The
+/// low bits of the pixels hold a JSON document, two bits per pixel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cartridge {
+    /// Octo source code
+    pub program: String,
+    pub options: CartridgeOptions,
+}
+
+/// JSON document stored in a cartridge
+#[derive(Serialize, Deserialize)]
+struct CartridgeJson {
+    #[serde(default)]
+    options: Map<String, Value>,
+    program: String,
+}
+
+/// Settings stored in a cartridge. Missing values are left to the emulator
+/// configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CartridgeOptions {
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    /// Instructions executed per frame
+    pub tickrate: Option<u32>,
+    /// RGB colour of each pixel value, starting with the background
+    pub colors: Vec<[u8; 3]>,
+}
+
+impl Cartridge {
+    /// Whether the file looks like a cartridge rather than a plain ROM
+    pub fn is_cartridge(bytes: &[u8]) -> bool {
+        bytes.starts_with(GIF_SIGNATURE)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let invalid_gif = |e: gif::DecodingError| Error::InvalidCartridgeError(e.to_string());
+        let mut options = DecodeOptions::new();
+        options.set_color_output(ColorOutput::Indexed);
+        let mut decoder = options.read_info(bytes).map_err(invalid_gif)?;
+        // Palette indices of every frame, row by row
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().map_err(invalid_gif)? {
+            frames.push(frame.buffer.to_vec());
+        }
+
+        let payload: Vec<u8> = frames
+            .iter()
+            .flat_map(|pixels| pixels.chunks(PIXELS_PER_BYTE))
+            .map(|pixels| {
+                (0..PIXELS_PER_BYTE).fold(0, |byte, i| {
+                    byte << BITS_PER_PIXEL | pixels.get(i).copied().unwrap_or(0) & 0x3
+                })
+            })
+            .collect();
+
+        let invalid = |message: &str| Error::InvalidCartridgeError(message.into());
+        let (length, json) = payload.split_first_chunk().ok_or_else(|| invalid("No data"))?;
+        let json = json
+            .get(..u32::from_be_bytes(*length) as usize)
+            .ok_or_else(|| invalid("Data is truncated"))?;
+        // Each byte is a character code
+        let json: String = json.iter().map(|&byte| byte as char).collect();
+
+        let cartridge: CartridgeJson = serde_json::from_str(&json)
+            .map_err(|e| Error::InvalidCartridgeError(e.to_string()))?;
+        Ok(Self {
+            program: cartridge.program,
+            options: parse_options(&cartridge.options)?,
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let cartridge = CartridgeJson {
+            options: options_json(&self.options),
+            program: self.program.clone(),
+        };
+        let json = serde_json::to_string(&cartridge).expect("Cartridge JSON is serializable");
+        // Characters are stored as single bytes, so others are escaped
+        let mut payload = Vec::new();
+        for c in json.chars() {
+            match u8::try_from(c) {
+                Ok(byte) if byte.is_ascii() => payload.push(byte),
+                _ => {
+                    for unit in c.encode_utf16(&mut [0; 2]) {
+                        payload.extend(format!("\\u{unit:04x}").bytes());
+                    }
+                }
+            }
+        }
+        payload.splice(0..0, (payload.len() as u32).to_be_bytes());
+
+        let mut pixels: Vec<u8> = payload
+            .iter()
+            .flat_map(|byte| {
+                (0..PIXELS_PER_BYTE)
+                    .rev()
+                    .map(move |i| byte >> (i * BITS_PER_PIXEL) & 0x3)
+            })
+            .collect();
+        let height = pixels.len().div_ceil(CARTRIDGE_WIDTH);
+        pixels.resize(height * CARTRIDGE_WIDTH, 0);
+        let height = u16::try_from(height)
+            .map_err(|_| Error::CartridgeFileError("Program is too large".into()))?;
+
+        let invalid_gif = |e: gif::EncodingError| Error::CartridgeFileError(e.to_string());
+        let mut bytes = Vec::new();
+        let mut encoder = Encoder::new(
+            &mut bytes,
+            CARTRIDGE_WIDTH as u16,
+            height,
+            CARTRIDGE_PALETTE.as_flattened(),
+        )
+        .map_err(invalid_gif)?;
+        let frame = Frame {
+            width: CARTRIDGE_WIDTH as u16,
+            height,
+            buffer: Cow::Owned(pixels),
+            ..Frame::default()
+        };
+        encoder.write_frame(&frame).map_err(invalid_gif)?;
+        drop(encoder);
+        Ok(bytes)
+    }
+
+    /// Compile the program into a ROM
+    pub fn rom(&self) -> Result<Vec<u8>> {
+        compile_octo(&self.program).map_err(|e| Error::CartridgeProgramError(e.to_string()))
+    }
+}
+
+/// ROM held in a ROM file or cartridge, with the settings stored in the
+/// cartridge
+pub fn load_rom(path: &str) -> Result<(Vec<u8>, CartridgeOptions)> {
+    let file = fs::read(path)?;
+    if Cartridge::is_cartridge(&file) {
+        let cartridge = Cartridge::decode(&file)?;
+        Ok((cartridge.rom()?, cartridge.options))
+    } else {
+        Ok((file, CartridgeOptions::default()))
+    }
+}
+
+/// Write a ROM file as a cartridge holding its Octo disassembly. Settings not
+/// given are taken from the ROM database or the defaults of the platform
+pub fn export_cartridge(
+    rom_path: &str,
+    output: Option<String>,
+    platform: Option<Platform>,
+    quirks: Option<Quirks>,
+) -> Result<()> {
+    let rom = fs::read(rom_path)?;
+    let database = RomDatabase::load()?;
+    let info = database.lookup(&rom_hash(&rom)).cloned().unwrap_or_default();
+
+    let platform = platform.or(info.platform).unwrap_or_default();
+    let quirks = quirks
+        .or(info.quirks.map(|preset| preset.quirks()))
+        .unwrap_or_else(|| platform.default_quirks());
+    let colors = if info.colors.is_empty() {
+        DEFAULT_PALETTE.to_vec()
+    } else {
+        info.colors
+    };
+    let cartridge = Cartridge {
+        program: disassemble(&rom, BTreeMap::new(), OutputFormat::Octo).listing,
+        options: CartridgeOptions {
+            platform: Some(platform),
+            quirks: Some(quirks),
+            tickrate: Some(info.tickrate.unwrap_or(CYCLES_PER_FRAME)),
+            colors,
+        },
+    };
+
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(rom_path).with_extension("gif"),
+    };
+    fs::write(output, cartridge.encode()?).map_err(|e| Error::CartridgeFileError(e.to_string()))
+}
+
+// Octo names quirks after the behaviour that differs from the COSMAC VIP
+fn parse_options(fields: &Map<String, Value>) -> Result<CartridgeOptions> {
+    let flag = |name: &str| matches!(fields.get(name), Some(Value::Bool(true)));
+
+    let tickrate = fields
+        .get("tickrate")
+        .and_then(Value::as_f64)
+        .filter(|&tickrate| tickrate >= 1.)
+        .map(|tickrate| tickrate as u32);
+    let platform = fields.get("maxSize").and_then(Value::as_f64).and_then(|size| {
+        MAX_SIZES
+            .iter()
+            .find(|(_, max_size)| size as u32 == *max_size)
+            .map(|(platform, _)| *platform)
+    });
+    let quirks = fields.get("shiftQuirks").map(|_| Quirks {
+        shift: flag("shiftQuirks"),
+        memory_increment: !flag("loadStoreQuirks"),
+        jump_with_vx: flag("jumpQuirks"),
+        vf_reset: flag("logicQuirks"),
+        clipping: flag("clipQuirks"),
+        display_wait: flag("vBlankQuirks"),
+    });
+
+    let mut colors = Vec::new();
+    for (name, default) in COLOR_OPTIONS.iter().zip(DEFAULT_PALETTE) {
+        match fields.get(*name).and_then(Value::as_str) {
+            Some(color) => colors.push(
+                parse_color(color)
+                    .ok_or_else(|| Error::InvalidCartridgeError(format!("Invalid {name}")))?,
+            ),
+            None => colors.push(default),
+        }
+    }
+    if COLOR_OPTIONS.iter().all(|name| !fields.contains_key(*name)) {
+        colors.clear();
+    }
+
+    Ok(CartridgeOptions {
+        platform,
+        quirks,
+        tickrate,
+        colors,
+    })
+}
+
+fn options_json(options: &CartridgeOptions) -> Map<String, Value> {
+    let mut fields = Map::new();
+    if let Some(tickrate) = options.tickrate {
+        fields.insert("tickrate".into(), json!(tickrate));
+    }
+    for (name, [r, g, b]) in COLOR_OPTIONS.iter().zip(&options.colors) {
+        fields.insert(name.to_string(), json!(format!("#{r:02X}{g:02X}{b:02X}")));
+    }
+    if let Some(quirks) = options.quirks {
+        let flags = [
+            ("shiftQuirks", quirks.shift),
+            ("loadStoreQuirks", !quirks.memory_increment),
+            ("jumpQuirks", quirks.jump_with_vx),
+            ("logicQuirks", quirks.vf_reset),
+            ("clipQuirks", quirks.clipping),
+            ("vBlankQuirks", quirks.display_wait),
+        ];
+        fields.extend(flags.map(|(name, value)| (name.to_string(), json!(value))));
+    }
+    if let Some((_, max_size)) = MAX_SIZES.iter().find(|(p, _)| Some(*p) == options.platform) {
+        fields.insert("maxSize".into(), json!(max_size));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cartridge_round_trip() {
+        let cartridge = Cartridge {
+            program: ": main\n  v0 := 1 # \"quoted\" \u{e9} \u{1f3ae}\n  loop again\n".repeat(200),
+            options: CartridgeOptions {
+                platform: Some(Platform::SuperChip),
+                quirks: Some(Quirks {
+                    shift: true,
+                    memory_increment: false,
+                    jump_with_vx: true,
+                    vf_reset: false,
+                    clipping: true,
+                    display_wait: false,
+                }),
+                tickrate: Some(30),
+                colors: vec![[0, 0, 0], [0xFF, 0xCC, 0], [0xFF, 0x66, 0], [0x66, 0x22, 0]],
+            },
+        };
+
+        let bytes = cartridge.encode().unwrap();
+        assert!(Cartridge::is_cartridge(&bytes));
+        assert_eq!(Cartridge::decode(&bytes).unwrap(), cartridge);
+    }
+
+    #[test]
+    fn oversized_images_are_refused() {
+        // Header of a 65535 by 65535 image followed by two bytes of pixels
+        let mut bytes = b"GIF89a\xFF\xFF\xFF\xFF\x80\x00\x00".to_vec();
+        bytes.extend([0; 6]);
+        bytes.extend(b"\x2C\x00\x00\x00\x00\xFF\xFF\xFF\xFF\x00\x02\x02\x4C\x01\x00\x3B");
+        assert!(Cartridge::is_cartridge(&bytes));
+        assert!(Cartridge::decode(&bytes).is_err());
+        assert!(Cartridge::decode(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn compiles_program() {
+        let cartridge = Cartridge {
+            program: ": main\n  clear\n  jump main\n".into(),
+            options: CartridgeOptions::default(),
+        };
+        let decoded = Cartridge::decode(&cartridge.encode().unwrap()).unwrap();
+        assert_eq!(decoded.options, CartridgeOptions::default());
+        assert_eq!(decoded.rom().unwrap(), [0x00, 0xE0, 0x12, 0x00]);
+    }
+}
@@ -0,0 +1,619 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use disassembler::instruction::Instruction;
+
+use crate::emulator::{Emulator, StopReason};
+use crate::errors::{Error, Result};
+use crate::processor::{Processor, NUM_REGS};
+use crate::watchpoint::{WatchKind, Watchpoint};
+
+const PROMPT: &str = "(chip8) ";
+// Instructions shown before and after the address by `list`
+const LIST_CONTEXT: u16 = 5;
+const DEFAULT_DUMP_LENGTH: usize = 64;
+const DUMP_ROW_SIZE: usize = 16;
+
+const HELP: &str = "\
+break <addr>      Set breakpoint at address
+delete [addr]     Remove breakpoint at address, or all breakpoints
+breakpoints       List breakpoints
+watch <addr>[-<end>] [kind]
+                  Stop on read, write, change or access of memory (default write)
+unwatch <n>       Remove watchpoint number n
+watchpoints       List watchpoints
+step [n]          Execute n instructions
+next              Execute one instruction, stepping over subroutine calls
+finish            Run until the current subroutine returns
+continue          Run until a breakpoint or watchpoint is hit or escape is pressed
+regs              Show registers, timers and stack
+mem <addr> [len]  Dump memory
+list [addr]       Disassemble code around address or PC
+help              Show this help
+quit              Exit the debugger
+Addresses are hexadecimal. An empty line repeats the last command.";
+
+/// Line-oriented debugger controlling the emulator from the terminal
+pub struct Debugger {
+    emulator: Emulator,
+    breakpoints: BTreeSet<u16>,
+}
+
+impl Debugger {
+    pub fn new(emulator: Emulator) -> Self {
+        Self {
+            emulator,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Read and execute commands from standard input until quit
+    pub fn run(&mut self) -> Result<()> {
+        let mut lines = io::stdin().lock().lines();
+        let mut last_command = String::new();
+
+        self.emulator.render()?;
+        self.print_location();
+
+        loop {
+            print!("{PROMPT}");
+            io::stdout()
+                .flush()
+                .map_err(|e| Error::DebuggerIoError(e.to_string()))?;
+
+            let line = match lines.next() {
+                Some(line) => line.map_err(|e| Error::DebuggerIoError(e.to_string()))?,
+                None => break,
+            };
+            if !line.trim().is_empty() {
+                last_command = line;
+            }
+
+            // Errors from the emulator are reported without leaving the debugger
+            match self.execute(&last_command.clone()) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => println!("Error: {e}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Execute a single command. Returns false when quitting
+    fn execute(&mut self, line: &str) -> Result<bool> {
+        let command = match parse_command(line) {
+            Ok(Some(command)) => command,
+            Ok(None) => return Ok(true),
+            Err(message) => {
+                println!("{message}");
+                return Ok(true);
+            }
+        };
+
+        match command {
+            Command::Break(address) => {
+                self.breakpoints.insert(address);
+                println!("Breakpoint at {address:03X}");
+            }
+            Command::Delete(Some(address)) => {
+                if self.breakpoints.remove(&address) {
+                    println!("Deleted breakpoint at {address:03X}");
+                } else {
+                    println!("No breakpoint at {address:03X}");
+                }
+            }
+            Command::Delete(None) => {
+                self.breakpoints.clear();
+                println!("Deleted all breakpoints");
+            }
+            Command::Breakpoints => {
+                if self.breakpoints.is_empty() {
+                    println!("No breakpoints");
+                }
+                for address in &self.breakpoints {
+                    println!("  {}", self.disassemble(*address));
+                }
+            }
+            Command::Watch(watchpoint) => {
+                self.emulator.processor_mut().add_watchpoint(watchpoint);
+                println!("Watching {}", format_watchpoint(&watchpoint));
+            }
+            Command::Unwatch(index) => {
+                let watchpoints = self.emulator.processor().watchpoints();
+                match watchpoints.get(index).copied() {
+                    Some(watchpoint) => {
+                        self.emulator.processor_mut().remove_watchpoint(&watchpoint);
+                        println!("Deleted watchpoint on {}", format_watchpoint(&watchpoint));
+                    }
+                    None => println!("No watchpoint {index}"),
+                }
+            }
+            Command::Watchpoints => {
+                let watchpoints = self.emulator.processor().watchpoints();
+                if watchpoints.is_empty() {
+                    println!("No watchpoints");
+                }
+                for (i, watchpoint) in watchpoints.iter().enumerate() {
+                    println!("  {i}: {}", format_watchpoint(watchpoint));
+                }
+            }
+            Command::Step(count) => self.step(count)?,
+            Command::Next => self.step_over()?,
+            Command::Finish => self.step_out()?,
+            Command::Continue => self.resume(|_| false)?,
+            Command::Registers => self.print_registers(),
+            Command::Memory(address, length) => self.dump_memory(address, length),
+            Command::List(address) => {
+                self.list(address.unwrap_or_else(|| self.emulator.processor().pc()))
+            }
+            Command::Help => println!("{HELP}"),
+            Command::Quit => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Check that there is a program left to execute
+    fn can_run(&self) -> bool {
+        if self.emulator.processor().halted() {
+            println!("The program has exited");
+            return false;
+        }
+        true
+    }
+
+    fn step(&mut self, count: u32) -> Result<()> {
+        for _ in 0..count {
+            if !self.can_run() {
+                break;
+            }
+            self.emulator.step()?;
+            if self.emulator.processor().watch_hit().is_some() {
+                break;
+            }
+        }
+        self.report(StopReason::Condition)
+    }
+
+    /// Step, running called subroutines to completion
+    fn step_over(&mut self) -> Result<()> {
+        match StopPoint::step_over(self.emulator.processor()) {
+            Some(stop) => self.resume(|p| stop.reached(p)),
+            None => self.step(1),
+        }
+    }
+
+    /// Run until the current subroutine returns
+    fn step_out(&mut self) -> Result<()> {
+        match StopPoint::step_out(self.emulator.processor()) {
+            Some(stop) => self.resume(|p| stop.reached(p)),
+            None => {
+                println!("Not in a subroutine");
+                Ok(())
+            }
+        }
+    }
+
+    /// Run until a breakpoint or watchpoint is hit or the condition holds.
+    /// The current instruction is always executed so a breakpoint at the
+    /// PC is left
+    fn resume<F: FnMut(&Processor) -> bool>(&mut self, mut condition: F) -> Result<()> {
+        if !self.can_run() {
+            return Ok(());
+        }
+
+        self.emulator.step()?;
+        let reason = if self.emulator.processor().halted() {
+            StopReason::Halted
+        } else {
+            let breakpoints = &self.breakpoints;
+            self.emulator.run_until(|p| {
+                breakpoints.contains(&p.pc()) || p.watch_hit().is_some() || condition(p)
+            })?
+        };
+
+        self.report(reason)
+    }
+
+    fn report(&mut self, reason: StopReason) -> Result<()> {
+        self.emulator.render()?;
+
+        let pc = self.emulator.processor().pc();
+        match reason {
+            StopReason::Quit => println!("Interrupted"),
+            StopReason::Halted => println!("The program has exited"),
+            StopReason::Condition if self.breakpoints.contains(&pc) => {
+                println!("Breakpoint at {pc:03X}")
+            }
+            StopReason::Condition => {}
+        }
+        if let Some(hit) = self.emulator.processor().watch_hit() {
+            let access = hit.access;
+            println!(
+                "Watchpoint: {} of {:03X} ({:02X} -> {:02X}) by {}",
+                access.kind,
+                access.address,
+                access.old_value,
+                access.new_value,
+                self.disassemble(hit.pc)
+            );
+        }
+        self.print_location();
+
+        Ok(())
+    }
+
+    fn print_location(&self) {
+        println!("=> {}", self.disassemble(self.emulator.processor().pc()));
+    }
+
+    fn print_registers(&self) {
+        let state = self.emulator.processor().snapshot();
+
+        println!(
+            "PC: {:03X}  I: {:03X}  DT: {:02X}  ST: {:02X}  SP: {:X}",
+            state.pc, state.i_reg, state.dt, state.st, state.sp
+        );
+        for row in (0..NUM_REGS).step_by(NUM_REGS / 2) {
+            let registers: Vec<String> = (row..row + NUM_REGS / 2)
+                .map(|i| format!("V{i:X}: {:02X}", state.v_reg[i]))
+                .collect();
+            println!("{}", registers.join("  "));
+        }
+
+        let stack: Vec<String> = state.stack[..state.sp as usize]
+            .iter()
+            .map(|address| format!("{address:03X}"))
+            .collect();
+        println!("Stack: [{}]", stack.join(", "));
+    }
+
+    fn dump_memory(&self, address: u16, length: usize) {
+        let ram = self.emulator.processor().ram();
+        let start = address as usize;
+        if start >= ram.len() {
+            println!("Address {address:03X} is outside RAM");
+            return;
+        }
+        let end = (start + length).min(ram.len());
+
+        for (i, row) in ram[start..end].chunks(DUMP_ROW_SIZE).enumerate() {
+            let bytes: Vec<String> = row.iter().map(|byte| format!("{byte:02X}")).collect();
+            println!("{:03X}: {}", start + i * DUMP_ROW_SIZE, bytes.join(" "));
+        }
+    }
+
+    /// Disassemble the instructions around the address
+    fn list(&self, address: u16) {
+        let pc = self.emulator.processor().pc();
+        let start = address.saturating_sub(LIST_CONTEXT * 2);
+        let end = address.saturating_add(LIST_CONTEXT * 2);
+
+        let mut address = start;
+        while address <= end {
+            let marker = if address == pc { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&address) { '*' } else { ' ' };
+            let (text, size) = disassemble(self.emulator.processor(), address);
+            println!("{marker}{breakpoint} {text}");
+            match address.checked_add(size) {
+                Some(next) => address = next,
+                None => break,
+            }
+        }
+    }
+
+    fn disassemble(&self, address: u16) -> String {
+        disassemble(self.emulator.processor(), address).0
+    }
+}
+
+/// Debugger command with its arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Break(u16),
+    // Without an address all breakpoints are deleted
+    Delete(Option<u16>),
+    Breakpoints,
+    Watch(Watchpoint),
+    Unwatch(usize),
+    Watchpoints,
+    Step(u32),
+    Next,
+    Finish,
+    Continue,
+    Registers,
+    Memory(u16, usize),
+    // Without an address the code around the PC is listed
+    List(Option<u16>),
+    Help,
+    Quit,
+}
+
+/// Parse a command line. Returns None for an empty line and the message
+/// to show for invalid commands
+fn parse_command(line: &str) -> std::result::Result<Option<Command>, String> {
+    let mut words = line.split_whitespace();
+    let Some(name) = words.next() else {
+        return Ok(None);
+    };
+    let args: Vec<&str> = words.collect();
+    let address = |index: usize| args.get(index).map(|arg| parse_address(arg));
+    let usage = |usage: &str| format!("Usage: {usage}");
+
+    let command = match name {
+        "b" | "break" => match address(0) {
+            Some(Some(address)) => Command::Break(address),
+            _ => return Err(usage("break <addr>")),
+        },
+        "d" | "delete" => match address(0) {
+            Some(Some(address)) => Command::Delete(Some(address)),
+            None => Command::Delete(None),
+            Some(None) => return Err(usage("delete [addr]")),
+        },
+        "bl" | "breakpoints" => Command::Breakpoints,
+        "w" | "watch" => {
+            let range = args.first().and_then(|arg| parse_range(arg));
+            let kind = args.get(1).map_or(Ok(WatchKind::Write), |arg| arg.parse());
+            match (range, kind) {
+                (Some((start, end)), Ok(kind)) => Command::Watch(Watchpoint::new(start, end, kind)),
+                (_, Err(e)) => return Err(e.to_string()),
+                (None, _) => return Err(usage("watch <addr>[-<end>] [read|write|change|access]")),
+            }
+        }
+        "uw" | "unwatch" => match args.first().map(|arg| arg.parse::<usize>()) {
+            Some(Ok(index)) => Command::Unwatch(index),
+            _ => return Err(usage("unwatch <n>")),
+        },
+        "wl" | "watchpoints" => Command::Watchpoints,
+        "s" | "step" => match args.first().map(|arg| arg.parse::<u32>()).unwrap_or(Ok(1)) {
+            Ok(count) => Command::Step(count),
+            Err(_) => return Err(usage("step [n]")),
+        },
+        "n" | "next" => Command::Next,
+        "f" | "finish" => Command::Finish,
+        "c" | "continue" => Command::Continue,
+        "r" | "regs" => Command::Registers,
+        "x" | "mem" => {
+            let length = args.get(1).map(|arg| arg.parse::<usize>().ok());
+            match (address(0), length) {
+                (Some(Some(address)), None) => Command::Memory(address, DEFAULT_DUMP_LENGTH),
+                (Some(Some(address)), Some(Some(length))) => Command::Memory(address, length),
+                _ => return Err(usage("mem <addr> [len]")),
+            }
+        }
+        "l" | "list" => match address(0) {
+            None => Command::List(None),
+            Some(Some(address)) => Command::List(Some(address)),
+            Some(None) => return Err(usage("list [addr]")),
+        },
+        "h" | "help" => Command::Help,
+        "q" | "quit" => Command::Quit,
+        _ => {
+            return Err(format!(
+                "Unknown command '{name}'. Type 'help' for a list of commands"
+            ))
+        }
+    };
+    Ok(Some(command))
+}
+
+/// Where `next` and `finish` stop: once the stack is no deeper than the
+/// given depth and, for `next`, execution is back at the return address.
+/// The depth keeps recursive calls returning to the same address running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StopPoint {
+    pc: Option<u16>,
+    depth: usize,
+}
+
+impl StopPoint {
+    /// Stop after the subroutine called at the PC returns. None if the
+    /// instruction is not a call
+    fn step_over(processor: &Processor) -> Option<Self> {
+        let pc = processor.pc();
+        (read_opcode(processor, pc) & 0xF000 == 0x2000).then(|| Self {
+            pc: Some(pc + 2),
+            depth: processor.stack_depth(),
+        })
+    }
+
+    /// Stop after the current subroutine returns. None outside of subroutines
+    fn step_out(processor: &Processor) -> Option<Self> {
+        let depth = processor.stack_depth().checked_sub(1)?;
+        Some(Self { pc: None, depth })
+    }
+
+    fn reached(&self, processor: &Processor) -> bool {
+        self.pc.is_none_or(|pc| processor.pc() == pc) && processor.stack_depth() <= self.depth
+    }
+}
+
+/// Instruction at the address and its size in bytes. Unknown opcodes are
+/// shown as a data word
+fn disassemble(processor: &Processor, address: u16) -> (String, u16) {
+    if address as usize + 1 >= processor.ram().len() {
+        return (format!("{address:03X}: ??"), 2);
+    }
+
+    let opcode = read_opcode(processor, address);
+    match Instruction::decode(opcode) {
+        // The address is the word following the instruction
+        Some(Instruction::LoadLongI) => {
+            let long_address = read_opcode(processor, address.wrapping_add(2));
+            let mnemonic = Instruction::LoadLongI.mnemonic();
+            (format!("{address:03X}: {mnemonic:<6} {long_address:#X}"), 4)
+        }
+        Some(instruction) => (format!("{address:03X}: {instruction}"), instruction.size()),
+        None => (format!("{address:03X}: {opcode:04X}"), 2),
+    }
+}
+
+fn read_opcode(processor: &Processor, address: u16) -> u16 {
+    let ram = processor.ram();
+    let address = address as usize;
+    match ram.get(address..address + 2) {
+        Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+        None => 0,
+    }
+}
+
+/// Parse single address or range of addresses written as start-end
+fn parse_range(arg: &str) -> Option<(u16, u16)> {
+    match arg.split_once('-') {
+        Some((start, end)) => Some((parse_address(start)?, parse_address(end)?)),
+        None => parse_address(arg).map(|address| (address, address)),
+    }
+}
+
+fn format_watchpoint(watchpoint: &Watchpoint) -> String {
+    if watchpoint.start == watchpoint.end {
+        format!("{} of {:03X}", watchpoint.kind, watchpoint.start)
+    } else {
+        format!("{} of {:03X}-{:03X}", watchpoint.kind, watchpoint.start, watchpoint.end)
+    }
+}
+
+/// Parse hexadecimal address with optional 0x prefix
+fn parse_address(arg: &str) -> Option<u16> {
+    let digits = arg
+        .strip_prefix("0x")
+        .or_else(|| arg.strip_prefix("0X"))
+        .unwrap_or(arg);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::Display;
+    use crate::key_input::KeyInput;
+    use crate::platform::Platform;
+
+    // Call a subroutine calling another one, then loop
+    const NESTED_CALLS: [u8; 14] = [
+        0x22, 0x06, 0x60, 0x01, 0x12, 0x04, 0x22, 0x0A, 0x00, 0xEE, 0x71, 0x01, 0x00, 0xEE,
+    ];
+
+    fn processor(rom: &[u8]) -> Processor {
+        let platform = Platform::Chip8;
+        Processor::try_new(rom, platform, platform.default_quirks()).unwrap()
+    }
+
+    #[test]
+    fn long_loads_are_listed_as_one_instruction() {
+        let platform = Platform::XoChip;
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0];
+        let processor = Processor::try_new(&rom, platform, platform.default_quirks()).unwrap();
+
+        assert_eq!(disassemble(&processor, 0x200), ("200: LOADL  0x1234".into(), 4));
+        assert_eq!(disassemble(&processor, 0x204), ("204: CLS".into(), 2));
+        assert_eq!(disassemble(&processor, 0xFFFF), ("FFFF: ??".into(), 2));
+    }
+
+    /// Execute at least one instruction, then run until the stop point,
+    /// like `resume`
+    fn run_to(processor: &mut Processor, stop: StopPoint) {
+        let mut display = Display::headless();
+        let mut input = KeyInput::new();
+        for _ in 0..1000 {
+            processor.cycle(&mut display, &mut input).unwrap();
+            if stop.reached(processor) {
+                return;
+            }
+        }
+        panic!("{stop:?} not reached");
+    }
+
+    fn step_to(processor: &mut Processor, pc: u16) {
+        run_to(processor, StopPoint { pc: Some(pc), depth: usize::MAX });
+    }
+
+    #[test]
+    fn commands_parse() {
+        let cases = [
+            ("break 2A0", Command::Break(0x2A0)),
+            ("b 0x300", Command::Break(0x300)),
+            ("delete 2a0", Command::Delete(Some(0x2A0))),
+            ("d", Command::Delete(None)),
+            ("watch 300", Command::Watch(Watchpoint::new(0x300, 0x300, WatchKind::Write))),
+            ("w 310-300 change", Command::Watch(Watchpoint::new(0x300, 0x310, WatchKind::Change))),
+            ("w 300 a", Command::Watch(Watchpoint::new(0x300, 0x300, WatchKind::Access))),
+            ("step", Command::Step(1)),
+            ("s 10", Command::Step(10)),
+            ("next", Command::Next),
+            ("f", Command::Finish),
+            ("mem 200", Command::Memory(0x200, DEFAULT_DUMP_LENGTH)),
+            ("x 0x50 16", Command::Memory(0x50, 16)),
+            ("list", Command::List(None)),
+            ("l 23E", Command::List(Some(0x23E))),
+            ("  quit  ", Command::Quit),
+        ];
+        for (line, command) in cases {
+            assert_eq!(parse_command(line), Ok(Some(command)), "{line}");
+        }
+        assert_eq!(parse_command("   "), Ok(None));
+    }
+
+    #[test]
+    fn invalid_commands_show_usage() {
+        let cases = [
+            ("break", "Usage: break <addr>"),
+            ("break xyz", "Usage: break <addr>"),
+            ("delete 12345", "Usage: delete [addr]"),
+            ("step -1", "Usage: step [n]"),
+            ("mem", "Usage: mem <addr> [len]"),
+            ("mem 200 many", "Usage: mem <addr> [len]"),
+            ("list nowhere", "Usage: list [addr]"),
+            ("run", "Unknown command 'run'. Type 'help' for a list of commands"),
+        ];
+        for (line, message) in cases {
+            assert_eq!(parse_command(line), Err(message.into()), "{line}");
+        }
+        assert!(parse_command("watch").unwrap_err().starts_with("Usage: watch"));
+        assert!(parse_command("watch 300 sometimes").is_err());
+    }
+
+    #[test]
+    fn next_runs_nested_calls() {
+        let mut processor = processor(&NESTED_CALLS);
+
+        let stop = StopPoint::step_over(&processor).unwrap();
+        run_to(&mut processor, stop);
+        assert_eq!((processor.pc(), processor.stack_depth()), (0x202, 0));
+        assert_eq!(processor.snapshot().v_reg[1], 1);
+
+        // Only calls are stepped over
+        assert_eq!(StopPoint::step_over(&processor), None);
+    }
+
+    #[test]
+    fn next_over_recursive_call_stops_at_same_depth() {
+        // Count V0 down to zero with a subroutine calling itself at 20E
+        let mut processor = processor(&[
+            0x60, 0x03, 0x22, 0x06, 0x12, 0x04, 0x30, 0x00, 0x12, 0x0C, 0x00, 0xEE, 0x70, 0xFF,
+            0x22, 0x06, 0x00, 0xEE,
+        ]);
+        step_to(&mut processor, 0x20E);
+        assert_eq!(processor.stack_depth(), 1);
+
+        let stop = StopPoint::step_over(&processor).unwrap();
+        run_to(&mut processor, stop);
+        assert_eq!((processor.pc(), processor.stack_depth()), (0x210, 1));
+        assert_eq!(processor.snapshot().v_reg[0], 0);
+    }
+
+    #[test]
+    fn finish_returns_one_level() {
+        let mut processor = processor(&NESTED_CALLS);
+        assert_eq!(StopPoint::step_out(&processor), None);
+
+        step_to(&mut processor, 0x20A);
+        assert_eq!(processor.stack_depth(), 2);
+        for expected in [(0x208, 1), (0x202, 0)] {
+            let stop = StopPoint::step_out(&processor).unwrap();
+            run_to(&mut processor, stop);
+            assert_eq!((processor.pc(), processor.stack_depth()), expected);
+        }
+    }
+}
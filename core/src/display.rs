@@ -1,16 +1,35 @@
 use sdl2::{pixels::Color, rect::Rect, render::Canvas, video::Window, VideoSubsystem};
 
-use crate::{errors::Error, errors::Result, helpers::bit_to_bool};
+use crate::{errors::Error, errors::Result, helpers::bit_to_bool, save_state::DisplayState};
 
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
 
-const BACKGROUND_COLOR: Color = Color::RGB(0, 20, 0);
-const FOREGROUND_COLOR: Color = Color::RGB(0, 255, 0);
+pub(crate) const BUFFER_SIZE: usize = HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT;
+// Number of pixels scrolled by 00FB and 00FC
+const HORIZONTAL_SCROLL: usize = 4;
+
+// Number of XO-CHIP bit planes
+pub const NUM_PLANES: usize = 2;
+
+/// RGB colour of each pixel value indexed by its plane bits: background,
+/// first plane, second plane and both planes
+pub const DEFAULT_PALETTE: [[u8; 3]; 1 << NUM_PLANES] =
+    [[0, 20, 0], [0, 255, 0], [0, 110, 0], [180, 255, 180]];
 
 pub struct Display {
-    pixels: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
-    canvas: Canvas<Window>,
+    // Colour of each pixel value indexed by its plane bits
+    palette: [Color; 1 << NUM_PLANES],
+    // Pixels are indexed by the width of the current resolution.
+    // Each bit of a pixel is set if it is on in the corresponding plane
+    pixels: [u8; BUFFER_SIZE],
+    hires: bool,
+    // Bit mask of the planes affected by drawing, clearing and scrolling
+    selected_planes: u8,
+    // Missing when nothing is shown, e.g. in tests
+    canvas: Option<Canvas<Window>>,
     window_scale: u32,
     redraw_flag: bool,
 }
@@ -34,76 +53,277 @@ impl Display {
         canvas.clear();
         canvas.present();
 
-        Ok(Self {
-            pixels: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+        Ok(Self::with_canvas(Some(canvas), window_scale))
+    }
+
+    /// Display without a window
+    #[cfg(test)]
+    pub(crate) fn headless() -> Self {
+        Self::with_canvas(None, 1)
+    }
+
+    fn with_canvas(canvas: Option<Canvas<Window>>, window_scale: u32) -> Self {
+        Self {
+            palette: DEFAULT_PALETTE.map(|[r, g, b]| Color::RGB(r, g, b)),
+            pixels: [0; BUFFER_SIZE],
+            hires: false,
+            selected_planes: 0x1,
             canvas,
             window_scale,
             redraw_flag: false,
-        })
+        }
+    }
+
+    /// Replace the colours of the first pixel values, starting with the background
+    pub fn set_palette(&mut self, colors: &[[u8; 3]]) {
+        for (color, [r, g, b]) in self.palette.iter_mut().zip(colors) {
+            *color = Color::RGB(*r, *g, *b);
+        }
+        self.redraw_flag = true;
     }
 
     pub fn redraw_needed(&self) -> bool {
         self.redraw_flag
     }
 
-    pub fn draw(&mut self, sprite: &[u8], x_coord: u8, y_coord: u8) -> u8 {
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    /// Switch between 64x32 low resolution and 128x64 high resolution mode.
+    /// Switching resolution clears the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels = [0; BUFFER_SIZE];
+        self.redraw_flag = true;
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub fn snapshot(&self) -> DisplayState {
+        DisplayState {
+            pixels: self.pixels.to_vec(),
+            hires: self.hires,
+            selected_planes: self.selected_planes,
+        }
+    }
+
+    /// Restore a state checked by `MachineState::validate`
+    pub fn restore(&mut self, state: &DisplayState) {
+        self.pixels.copy_from_slice(&state.pixels);
+        self.hires = state.hires;
+        self.selected_planes = state.selected_planes;
+        self.redraw_flag = true;
+    }
+
+    /// Select the planes used by drawing, clearing and scrolling
+    pub fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & ((1 << NUM_PLANES) - 1) as u8;
+    }
+
+    /// Number of selected planes. Sprites hold data for each selected plane
+    pub fn selected_plane_count(&self) -> usize {
+        self.selected_planes.count_ones() as usize
+    }
+
+    /// Draw 8 pixel wide sprite at the given coordinates. Sprites are either
+    /// clipped at the screen edges or wrapped around to the opposite side
+    pub fn draw(&mut self, sprite: &[u8], x_coord: u8, y_coord: u8, clip: bool) -> u8 {
+        self.draw_planes(sprite, 1, x_coord, y_coord, clip)
+    }
+
+    /// Draw 16x16 pixel sprite stored as two bytes per row
+    pub fn draw_large(&mut self, sprite: &[u8], x_coord: u8, y_coord: u8, clip: bool) -> u8 {
+        self.draw_planes(sprite, 2, x_coord, y_coord, clip)
+    }
+
+    /// Draw sprite data into each selected plane in turn, starting with
+    /// the first plane
+    fn draw_planes(
+        &mut self,
+        sprite: &[u8],
+        row_bytes: usize,
+        x_coord: u8,
+        y_coord: u8,
+        clip: bool,
+    ) -> u8 {
         self.redraw_flag = true;
 
+        let plane_count = self.selected_plane_count();
+        // CHIP-8 DXY0 draws nothing
+        if plane_count == 0 || sprite.is_empty() {
+            return 0x0;
+        }
+
+        let mut carry_register: u8 = 0x0;
+        let plane_sprites = sprite.chunks(sprite.len() / plane_count);
+        let selected_planes = self.selected_planes;
+        let planes = (0..NUM_PLANES as u8)
+            .map(|plane| 1 << plane)
+            .filter(|plane_bit| selected_planes & plane_bit != 0);
+
+        for (plane_bit, plane_sprite) in planes.zip(plane_sprites) {
+            carry_register |=
+                self.draw_rows(plane_sprite, plane_bit, row_bytes, x_coord, y_coord, clip);
+        }
+        carry_register
+    }
+
+    fn draw_rows(
+        &mut self,
+        sprite: &[u8],
+        plane_bit: u8,
+        row_bytes: usize,
+        x_coord: u8,
+        y_coord: u8,
+        clip: bool,
+    ) -> u8 {
+        let width = self.width();
+        let height = self.height();
         let mut carry_register: u8 = 0x0;
 
-        for (k, sprite_byte) in sprite.iter().enumerate() {
-            let y_pos = y_coord + k as u8;
-            if y_pos as usize >= SCREEN_HEIGHT {
-                // If reaching bottom edge of display, break loop
-                break;
+        for (k, sprite_row) in sprite.chunks_exact(row_bytes).enumerate() {
+            let mut y_pos = y_coord as usize + k;
+            if y_pos >= height {
+                if clip {
+                    // If reaching bottom edge of display, break loop
+                    break;
+                }
+                y_pos %= height;
             }
 
-            for j in 0..8 {
-                let sprite_pixel = bit_to_bool(*sprite_byte, j);
+            for j in 0..8 * row_bytes {
+                let sprite_pixel = bit_to_bool(sprite_row[j / 8], (j % 8) as u8);
+                let mut x_pos = x_coord as usize + j;
+
+                if x_pos >= width {
+                    if clip {
+                        // If reaching right edge of screen, continue to next row
+                        break;
+                    }
+                    x_pos %= width;
+                }
+
                 // Index of pixel on screen
-                let x_pos = x_coord + j;
-                let pixel_index = y_pos as usize * SCREEN_WIDTH + x_pos as usize;
+                let pixel_index = y_pos * width + x_pos;
+                let screen_pixel = self.pixels[pixel_index] & plane_bit != 0;
 
-                if x_pos as usize >= SCREEN_WIDTH {
-                    // If reaching right edge of screen, continue to next row
-                    break;
-                } else if self.pixels[pixel_index] && sprite_pixel {
+                if screen_pixel && sprite_pixel {
                     // If the pixel on screen and in sprite
                     // are on then turn off screen pixel
-                    self.pixels[pixel_index] = false;
+                    self.pixels[pixel_index] &= !plane_bit;
                     carry_register = 0x1;
                 } else if sprite_pixel {
                     // Else if sprite pixel is on but screen pixel is not
                     // turn on screen pixel
-                    self.pixels[pixel_index] = true;
+                    self.pixels[pixel_index] |= plane_bit;
                 }
             }
         }
         carry_register
     }
 
-    pub fn clear(&mut self) {
-        self.pixels = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+    /// Scroll the screen down by the given number of pixels
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize);
     }
 
-    pub fn render(&mut self) -> Result<()> {
-        let scale_usize = self.window_scale as usize;
-
-        self.canvas.set_draw_color(BACKGROUND_COLOR);
-        self.canvas.clear();
-
-        self.canvas.set_draw_color(FOREGROUND_COLOR);
-        for (i, pixel) in self.pixels.iter().enumerate() {
-            if *pixel {
-                let x = (i % SCREEN_WIDTH * scale_usize) as i32;
-                let y = (i / SCREEN_WIDTH * scale_usize) as i32;
-                let rect = Rect::new(x, y, self.window_scale, self.window_scale);
-                self.canvas.fill_rect(rect).map_err(Error::SdlError)?;
+    /// Scroll the screen up by the given number of pixels
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize));
+    }
+
+    /// Scroll the screen right by 4 pixels
+    pub fn scroll_right(&mut self) {
+        self.scroll(HORIZONTAL_SCROLL as isize, 0);
+    }
+
+    /// Scroll the screen left by 4 pixels
+    pub fn scroll_left(&mut self) {
+        self.scroll(-(HORIZONTAL_SCROLL as isize), 0);
+    }
+
+    /// Move the selected planes by the given offset. Pixels moved in
+    /// from outside the screen are turned off
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.width() as isize;
+        let height = self.height() as isize;
+        let mask = self.selected_planes;
+        let source = self.pixels;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&src_x) && (0..height).contains(&src_y) {
+                    source[(src_y * width + src_x) as usize] & mask
+                } else {
+                    0
+                };
+
+                let pixel = &mut self.pixels[(y * width + x) as usize];
+                *pixel = (*pixel & !mask) | moved;
             }
         }
-        self.canvas.present();
+        self.redraw_flag = true;
+    }
+
+    /// Clear the selected planes
+    pub fn clear(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= !self.selected_planes;
+        }
+        self.redraw_flag = true;
+    }
 
+    pub fn render(&mut self) -> Result<()> {
         self.redraw_flag = false;
+        let width = self.width();
+        let height = self.height();
+        let Some(canvas) = self.canvas.as_mut() else {
+            return Ok(());
+        };
+
+        // Keep window size constant by halving pixel size in high resolution
+        let pixel_scale = self.window_scale * SCREEN_WIDTH as u32 / width as u32;
+        let scale_usize = pixel_scale as usize;
+
+        canvas.set_draw_color(self.palette[0]);
+        canvas.clear();
+
+        for (value, color) in self.palette.iter().enumerate().skip(1) {
+            canvas.set_draw_color(*color);
+            for (i, pixel) in self.pixels[..width * height].iter().enumerate() {
+                if *pixel as usize == value {
+                    let x = (i % width * scale_usize) as i32;
+                    let y = (i / width * scale_usize) as i32;
+                    let rect = Rect::new(x, y, pixel_scale, pixel_scale);
+                    canvas.fill_rect(rect).map_err(Error::SdlError)?;
+                }
+            }
+        }
+        canvas.present();
         Ok(())
     }
 }
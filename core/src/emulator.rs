@@ -1,90 +1,518 @@
-use sdl2::{event::Event, keyboard::Keycode, EventPump, Sdl};
+use log::{info, warn};
+use sdl2::{controller::Button, event::Event, keyboard::Keycode, EventPump, Sdl};
 use std::{
-    fs,
     thread::sleep,
     time::{Duration, Instant},
 };
 
 use crate::{
-    audio_output::AudioOutput, display::Display, errors::{Error, Result}, key_input::KeyInput, processor::Processor
+    audio_output::AudioOutput,
+    cartridge::load_rom,
+    display::Display,
+    errors::{Error, Result},
+    gamepad::{GamepadProfile, Gamepads},
+    gdb_stub::{GdbStub, Resume},
+    helpers::{rom_hash, ROM_HASH_SIZE},
+    key_input::{KeyInput, NUM_KEYS},
+    keymap::Keymap,
+    movie::{state_checksum, Movie, MovieFrame},
+    palette::Palettes,
+    platform::Platform,
+    processor::Processor,
+    quirks::{QuirkPreset, Quirks},
+    rewind::RewindBuffer,
+    rng::{RandomSource, SeededRng, VipRng},
+    rom_database::RomDatabase,
+    save_state::MachineState,
+    trace::{TraceOptions, TraceWriter},
 };
 
 const FRAME_RATE: u32 = 60;
 const CLOCK_SPEED: u32 = 600;
-const CYCLES_PER_FRAME: u32 = CLOCK_SPEED / FRAME_RATE + 1;
+/// Instructions executed per frame unless the ROM needs another speed
+pub const CYCLES_PER_FRAME: u32 = CLOCK_SPEED / FRAME_RATE + 1;
+
+// Save state slots bound to F1-F4 for saving and F5-F8 for loading
+const SAVE_SLOT_KEYS: [Keycode; 4] = [Keycode::F1, Keycode::F2, Keycode::F3, Keycode::F4];
+const LOAD_SLOT_KEYS: [Keycode; 4] = [Keycode::F5, Keycode::F6, Keycode::F7, Keycode::F8];
+// Hold to step backwards in time
+const REWIND_KEY: Keycode = Keycode::Backspace;
+// Switch to the next colour palette
+const PALETTE_KEY: Keycode = Keycode::F9;
+
+/// Settings for creating an emulator
+#[derive(Debug, Clone)]
+pub struct EmulatorConfig {
+    pub window_scale: u32,
+    /// Platform to emulate. Defaults to the ROM database entry, then CHIP-8
+    pub platform: Option<Platform>,
+    /// Quirks to emulate. Default to the ROM database entry, then the
+    /// quirks of the platform
+    pub quirks: Option<Quirks>,
+    /// Length of the rewind buffer in seconds. Zero disables rewinding
+    pub rewind_seconds: u32,
+    /// Seed of the random number generator. A random seed is chosen if not set
+    pub seed: Option<u64>,
+    /// Image of the COSMAC VIP interpreter used to emulate its random routine
+    pub vip_interpreter: Option<Vec<u8>>,
+    /// Record input to this movie file
+    pub record: Option<String>,
+    /// Play back input from this movie file. The platform, quirks, seed and
+    /// instructions per frame stored in the movie replace the ones above
+    pub replay: Option<String>,
+    /// Wait for a GDB client on this local port before running
+    pub gdb_port: Option<u16>,
+    /// Write executed instructions to a trace file
+    pub trace: Option<TraceOptions>,
+    /// Keyboard layout name or keymap file. Defaults to the user's keymap
+    /// file with the ROM database bindings applied
+    pub keymap: Option<String>,
+    /// Gamepad profile file. Defaults to the user's profile file, then the
+    /// key hints of the ROM database entry, then the default profile
+    pub gamepad: Option<String>,
+    /// Palette name or comma separated colours. Defaults to the colours of
+    /// the cartridge or ROM database entry, then the user's default palette
+    pub palette: Option<String>,
+}
+
+/// Why emulation stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The window was closed or escape was pressed
+    Quit,
+    /// The program ended with the exit instruction
+    Halted,
+    /// The stop condition was met
+    Condition,
+}
+
+/// Input movie being recorded or played back
+enum MovieMode {
+    Recording { path: String, movie: Movie },
+    Replaying { movie: Movie, frame: usize },
+}
 
 pub struct Emulator {
     processor: Processor,
     display: Display,
     input: KeyInput,
+    keymap: Keymap,
+    gamepads: Gamepads,
+    palettes: Palettes,
     audio: AudioOutput,
     _sdl_context: Sdl,
     event_pump: EventPump,
+    rom_path: String,
+    rom_hash: [u8; ROM_HASH_SIZE],
+    rewind: RewindBuffer,
+    rewinding: bool,
+    seed: u64,
+    movie: Option<MovieMode>,
+    // Cycles run so far in the current frame
+    frame_cycles: u32,
+    // Instructions executed per frame
+    cycles_per_frame: u32,
+    // Keys held at the start of the current frame
+    frame_keys: [bool; NUM_KEYS],
+    gdb_port: Option<u16>,
 }
 
 impl Emulator {
-    pub fn try_new(rom_path: &str, window_scale: u32) -> Result<Self> {
+    pub fn try_new(rom_path: &str, config: EmulatorConfig) -> Result<Self> {
         let sdl_context = sdl2::init().map_err(Error::SdlError)?;
         let video_subsystem = sdl_context.video().map_err(Error::SdlError)?;
 
-        let rom: Vec<u8> = fs::read(rom_path)?;
+        // Cartridges are compiled, and their options apply before the database's
+        let (rom, cartridge_options) = load_rom(rom_path)?;
 
         let event_pump = sdl_context.event_pump().map_err(Error::SdlError)?;
 
+        let rom_hash = rom_hash(&rom);
+        let rom_info = RomDatabase::load()?.lookup(&rom_hash).cloned().unwrap_or_default();
+        let tickrate = cartridge_options.tickrate.or(rom_info.tickrate);
+        let colors = if cartridge_options.colors.is_empty() {
+            &rom_info.colors
+        } else {
+            &cartridge_options.colors
+        };
+        if let Some(title) = &rom_info.title {
+            if rom_info.authors.is_empty() {
+                info!("Loaded {title}");
+            } else {
+                info!("Loaded {title} by {}", rom_info.authors.join(", "));
+            }
+        }
+        if !rom_info.keys.is_empty() {
+            let keys: Vec<String> = rom_info
+                .keys
+                .iter()
+                .map(|(action, key)| format!("{action} {key:X}"))
+                .collect();
+            info!("Keys: {}", keys.join(", "));
+        }
+
+        let keymap = Keymap::load(config.keymap.as_deref(), &rom_info.keymap)?;
+        let gamepad_profile = GamepadProfile::load(config.gamepad.as_deref(), &rom_info.keys)?;
+        let gamepads = Gamepads::try_new(&sdl_context, gamepad_profile)?;
+        let palettes = Palettes::load(config.palette.as_deref(), colors)?;
+
+        // A replayed movie decides how the machine is set up
+        let replay = config.replay.as_deref().map(Movie::load).transpose()?;
+        let (platform, quirks, seed, cycles_per_frame) = match &replay {
+            Some(movie) => {
+                if movie.rom_hash != rom_hash {
+                    return Err(Error::MovieRomMismatchError);
+                }
+                (movie.platform, movie.quirks, movie.seed, movie.cycles_per_frame)
+            }
+            None => {
+                let platform = config
+                    .platform
+                    .or(cartridge_options.platform)
+                    .or(rom_info.platform)
+                    .unwrap_or_default();
+                let quirks = config
+                    .quirks
+                    .or(cartridge_options.quirks)
+                    .or(rom_info.quirks.map(QuirkPreset::quirks))
+                    .unwrap_or_else(|| platform.default_quirks());
+                let seed = config.seed.unwrap_or_else(rand::random);
+                (platform, quirks, seed, tickrate.unwrap_or(CYCLES_PER_FRAME))
+            }
+        };
+        let movie = match (replay, config.record) {
+            (Some(movie), _) => Some(MovieMode::Replaying { movie, frame: 0 }),
+            (None, Some(path)) => Some(MovieMode::Recording {
+                path,
+                movie: Movie::new(rom_hash, platform, quirks, seed, cycles_per_frame),
+            }),
+            (None, None) => None,
+        };
+
+        let mut processor = Processor::try_new(&rom, platform, quirks)?;
+        info!("Random seed: {seed}");
+        let rng: Box<dyn RandomSource> = match &config.vip_interpreter {
+            Some(interpreter) => Box::new(VipRng::try_new(interpreter, seed as u16)?),
+            None => Box::new(SeededRng::new(seed)),
+        };
+        processor.set_rng(rng);
+        let rewind = RewindBuffer::new((config.rewind_seconds * FRAME_RATE) as usize);
+        if rewind.enabled() {
+            processor.start_undo_log();
+        }
+        if let Some(trace) = &config.trace {
+            processor.set_trace(TraceWriter::create(trace)?);
+        }
+
+        let mut display = Display::try_new(video_subsystem, config.window_scale)?;
+        display.set_palette(&palettes.current().colors);
+
         Ok(Self {
-            processor: Processor::try_new(&rom)?,
-            display: Display::try_new(video_subsystem, window_scale)?,
+            processor,
+            display,
             input: KeyInput::new(),
+            keymap,
+            gamepads,
+            palettes,
             audio: AudioOutput::try_new()?,
             _sdl_context: sdl_context,
             event_pump,
+            rom_path: rom_path.into(),
+            rom_hash,
+            rewind,
+            rewinding: false,
+            seed,
+            movie,
+            frame_cycles: 0,
+            cycles_per_frame,
+            frame_keys: [false; NUM_KEYS],
+            gdb_port: config.gdb_port,
         })
     }
 
+    /// Seed used by the random number generator
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn processor(&self) -> &Processor {
+        &self.processor
+    }
+
+    pub fn processor_mut(&mut self) -> &mut Processor {
+        &mut self.processor
+    }
+
+    /// Draw the display if it has changed
+    pub fn render(&mut self) -> Result<()> {
+        if self.display.redraw_needed() {
+            self.display.render()?;
+        }
+
+        Ok(())
+    }
+
+    /// Save the state of the whole machine to file
+    pub fn save_state(&self, path: &str) -> Result<()> {
+        self.machine_state().save(path, &self.rom_hash)
+    }
+
+    /// Restore the state of the whole machine from file. The state
+    /// must have been saved while running the same ROM
+    pub fn load_state(&mut self, path: &str) -> Result<()> {
+        let state = MachineState::load(path, &self.rom_hash)?;
+        self.restore_machine_state(&state)?;
+        // Recorded frames cannot be undone on top of the loaded RAM
+        self.rewind.clear();
+        Ok(())
+    }
+
+    fn machine_state(&self) -> MachineState {
+        MachineState {
+            processor: self.processor.snapshot(),
+            ram: self.processor.ram().to_vec(),
+            display: self.display.snapshot(),
+            keys: self.input.keys(),
+        }
+    }
+
+    fn restore_machine_state(&mut self, state: &MachineState) -> Result<()> {
+        // Check the whole state first so a bad state leaves the machine untouched
+        state.validate()?;
+        self.processor.restore(&state.processor)?;
+        self.processor.restore_ram(&state.ram);
+        self.display.restore(&state.display);
+        self.input.set_keys(state.keys);
+
+        Ok(())
+    }
+
+    fn slot_path(&self, slot: usize) -> String {
+        format!("{}.state{}", self.rom_path, slot + 1)
+    }
+
+    fn save_slot(&self, slot: usize) {
+        let path = self.slot_path(slot);
+        match self.save_state(&path) {
+            Ok(()) => info!("Saved state to {path}"),
+            Err(e) => warn!("Failed to save state to {path}: {e}"),
+        }
+    }
+
+    fn load_slot(&mut self, slot: usize) {
+        let path = self.slot_path(slot);
+        match self.load_state(&path) {
+            Ok(()) => info!("Loaded state from {path}"),
+            Err(e) => warn!("Failed to load state from {path}: {e}"),
+        }
+    }
+
+    fn replaying(&self) -> bool {
+        matches!(self.movie, Some(MovieMode::Replaying { .. }))
+    }
+
+    fn key_down(&mut self, keycode: Keycode) {
+        if keycode == PALETTE_KEY {
+            let palette = self.palettes.cycle();
+            info!("Palette: {}", palette.name);
+            self.display.set_palette(&palette.colors);
+            return;
+        }
+
+        // Hotkeys changing the machine state would break movies
+        if self.movie.is_none() {
+            if keycode == REWIND_KEY {
+                self.rewinding = true;
+                return;
+            } else if let Some(slot) = SAVE_SLOT_KEYS.iter().position(|k| *k == keycode) {
+                self.save_slot(slot);
+                return;
+            } else if let Some(slot) = LOAD_SLOT_KEYS.iter().position(|k| *k == keycode) {
+                self.load_slot(slot);
+                return;
+            }
+        }
+
+        // Input comes from the movie during replay
+        if let Some(key) = self.keymap.key(keycode).filter(|_| !self.replaying()) {
+            self.input.key_press(key);
+        }
+    }
+
+    fn key_up(&mut self, keycode: Keycode) {
+        if keycode == REWIND_KEY {
+            self.rewinding = false;
+        } else if let Some(key) = self.keymap.key(keycode).filter(|_| !self.replaying()) {
+            self.input.key_release(key);
+        }
+    }
+
+    fn button(&mut self, button: Button, pressed: bool) {
+        // Input comes from the movie during replay
+        let Some(key) = self.gamepads.key(button).filter(|_| !self.replaying()) else {
+            return;
+        };
+        if pressed {
+            self.input.key_press(key);
+        } else {
+            self.input.key_release(key);
+        }
+    }
+
+    /// Apply the recorded input of the current frame during replay.
+    /// Input is handed back to the keyboard when the movie ends
+    fn replay_input(&mut self) {
+        let keys = match &self.movie {
+            Some(MovieMode::Replaying { movie, frame }) => movie.frames.get(*frame).map(|f| f.keys),
+            _ => return,
+        };
+
+        match keys {
+            Some(keys) => self.input.set_keys(keys),
+            None => {
+                info!("Replay finished");
+                self.movie = None;
+            }
+        }
+    }
+
+    /// Record the frame or check it against the replayed movie
+    fn update_movie(&mut self, keys: [bool; NUM_KEYS]) -> Result<()> {
+        if self.movie.is_none() {
+            return Ok(());
+        }
+        let checksum = state_checksum(&self.machine_state(), &self.rom_hash);
+
+        match &mut self.movie {
+            Some(MovieMode::Recording { movie, .. }) => {
+                movie.frames.push(MovieFrame { keys, checksum });
+            }
+            Some(MovieMode::Replaying { movie, frame }) => {
+                movie.check_frame(*frame, checksum)?;
+                *frame += 1;
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
     pub fn run(&mut self) -> Result<()> {
+        let result = match self.gdb_port {
+            Some(port) => self.run_gdb(port),
+            None => self.run_until(|_| false).map(|_| ()),
+        };
+
+        // Save the recording even if emulation stopped with an error
+        if let Some(MovieMode::Recording { path, movie }) = &self.movie {
+            movie.save(path)?;
+            info!("Saved movie of {} frames to {path}", movie.frames.len());
+        }
+
+        result
+    }
+
+    /// Run under control of a GDB client. The program starts stopped
+    fn run_gdb(&mut self, port: u16) -> Result<()> {
+        info!("Waiting for GDB connection on port {port}");
+        let mut stub = GdbStub::listen(port)?;
+        info!("GDB connected");
+
         let frame_length = Duration::from_secs_f64(1. / FRAME_RATE as f64);
 
-        'main_loop: loop {
-            let frame_start = Instant::now();
+        loop {
+            let resume = match stub.process(&mut self.processor)? {
+                Some(resume) => resume,
+                None => {
+                    // Keep the window responsive while stopped
+                    if !self.handle_events() {
+                        return Ok(());
+                    }
+                    self.render()?;
+                    sleep(frame_length);
+                    continue;
+                }
+            };
 
-            for event in self.event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => {
-                        break 'main_loop;
-                    },
-
-                    Event::KeyDown {
-                        keycode: Some(keycode),
-                        ..
-                    } => {
-                        self.input.key_press(keycode);
-                    },
-
-                    Event::KeyUp {keycode: Some(keycode), .. } => self.input.key_release(keycode),
-
-                    _ => {}
+            let reason = match resume {
+                Resume::Step => {
+                    self.step()?;
+                    StopReason::Condition
                 }
-            }
+                Resume::Continue => {
+                    // Leave a breakpoint at the PC before checking for breakpoints
+                    self.step()?;
+                    if self.processor.halted() {
+                        StopReason::Halted
+                    } else {
+                        self.run_until(|p| {
+                            stub.breakpoint_at(p.pc())
+                                || p.watch_hit().is_some()
+                                || stub.interrupted()
+                        })?
+                    }
+                }
+                Resume::Detach => {
+                    info!("GDB detached");
+                    return self.run_until(|_| false).map(|_| ());
+                }
+                Resume::Kill => return Ok(()),
+            };
 
-            // Run CPU cycles
-            for _ in 0..CYCLES_PER_FRAME {
-                self.processor.cycle(&mut self.display, &mut self.input)?;
+            match reason {
+                StopReason::Quit => return Ok(()),
+                _ if !stub.connected() => {
+                    info!("GDB disconnected");
+                    return self.run_until(|_| false).map(|_| ());
+                }
+                _ if self.processor.halted() => {
+                    stub.report_exit()?;
+                    return Ok(());
+                }
+                _ => stub.report_stop(self.processor.watch_hit())?,
             }
+        }
+    }
 
-            if self.display.redraw_needed() {
-                self.display.render()?;
-            }
+    /// Run in real time until the stop condition holds for the
+    /// instruction about to be executed
+    pub fn run_until<F>(&mut self, mut stop: F) -> Result<StopReason>
+    where
+        F: FnMut(&Processor) -> bool,
+    {
+        let frame_length = Duration::from_secs_f64(1. / FRAME_RATE as f64);
 
-            self.processor.tick_timers();
+        loop {
+            let frame_start = Instant::now();
 
-            if self.processor.check_beep() {
-                self.audio.start()
-            } else {
+            if !self.handle_events() {
                 self.audio.stop();
+                return Ok(StopReason::Quit);
+            }
+
+            if self.rewinding {
+                // Step one frame back instead of running the CPU
+                // The keys currently held are kept rather than the recorded ones
+                self.rewind.rewind(&mut self.processor, &mut self.display)?;
+                self.end_frame()?;
+            } else {
+                // Run CPU cycles until the frame ends
+                loop {
+                    if stop(&self.processor) {
+                        self.audio.stop();
+                        return Ok(StopReason::Condition);
+                    }
+
+                    self.step()?;
+
+                    // End emulation when the program exits
+                    if self.processor.halted() {
+                        return Ok(StopReason::Halted);
+                    }
+                    if self.frame_cycles == 0 {
+                        break;
+                    }
+                }
             }
 
             // Frame timing
@@ -93,6 +521,81 @@ impl Emulator {
                 sleep(frame_length - elapsed);
             }
         }
+    }
+
+    /// Execute a single instruction, ending the frame after its last cycle
+    pub fn step(&mut self) -> Result<()> {
+        if self.frame_cycles == 0 {
+            self.replay_input();
+            self.frame_keys = self.input.keys();
+        }
+
+        self.processor.cycle(&mut self.display, &mut self.input)?;
+        self.frame_cycles += 1;
+
+        if self.frame_cycles == self.cycles_per_frame {
+            self.end_frame()?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle window and keyboard events. Returns false when quitting
+    fn handle_events(&mut self) -> bool {
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in events {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    return false;
+                },
+
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => self.key_down(keycode),
+
+                Event::KeyUp {keycode: Some(keycode), .. } => self.key_up(keycode),
+
+                Event::ControllerDeviceAdded { which, .. } => self.gamepads.connect(which),
+
+                Event::ControllerDeviceRemoved { which, .. } => self.gamepads.disconnect(which),
+
+                Event::ControllerButtonDown { button, .. } => self.button(button, true),
+
+                Event::ControllerButtonUp { button, .. } => self.button(button, false),
+
+                _ => {}
+            }
+        }
+
+        true
+    }
+
+    /// Render, tick timers and update audio at the vertical blank
+    fn end_frame(&mut self) -> Result<()> {
+        self.frame_cycles = 0;
+
+        self.render()?;
+
+        if !self.rewinding {
+            self.processor.tick_timers();
+            self.rewind.record(&mut self.processor, &self.display);
+            self.update_movie(self.frame_keys)?;
+        }
+
+        if let Some((pattern, pitch)) = self.processor.audio_pattern() {
+            self.audio.set_pattern(pattern, pitch);
+        }
+
+        if self.processor.check_beep() && !self.rewinding {
+            self.audio.start()
+        } else {
+            self.audio.stop();
+        }
 
         Ok(())
     }
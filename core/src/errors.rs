@@ -31,6 +31,75 @@ pub enum Error {
 
     #[error("Audio output failed:\n{0}")]
     AudioOutputError(String),
+
+    #[error("Unknown quirk preset: {0}")]
+    UnknownQuirkPresetError(String),
+
+    #[error("Unknown platform: {0}")]
+    UnknownPlatformError(String),
+
+    #[error("Invalid save state:\n{0}")]
+    InvalidSaveStateError(String),
+
+    #[error("Save state was created with a different ROM")]
+    SaveStateRomMismatchError,
+
+    #[error("Failed to access save state file:\n{0}")]
+    SaveStateFileError(String),
+
+    #[error("Interpreter image must contain the 512 byte COSMAC VIP interpreter")]
+    InvalidInterpreterImageError,
+
+    #[error("Invalid movie:\n{0}")]
+    InvalidMovieError(String),
+
+    #[error("Movie was recorded with a different ROM")]
+    MovieRomMismatchError,
+
+    #[error("Failed to access movie file:\n{0}")]
+    MovieFileError(String),
+
+    #[error("Replay desynced from the movie at frame {0}")]
+    MovieDesyncError(usize),
+
+    #[error("Debugger terminal I/O failed:\n{0}")]
+    DebuggerIoError(String),
+
+    #[error("Unknown watchpoint kind: {0}")]
+    UnknownWatchKindError(String),
+
+    #[error("Unknown trace format: {0}")]
+    UnknownTraceFormatError(String),
+
+    #[error("Failed to write trace file:\n{0}")]
+    TraceFileError(String),
+
+    #[error("GDB connection failed:\n{0}")]
+    GdbConnectionError(String),
+
+    #[error("Invalid ROM database:\n{0}")]
+    RomDatabaseError(String),
+
+    #[error("Invalid cartridge:\n{0}")]
+    InvalidCartridgeError(String),
+
+    #[error("Failed to compile cartridge program:\n{0}")]
+    CartridgeProgramError(String),
+
+    #[error("Failed to write cartridge file:\n{0}")]
+    CartridgeFileError(String),
+
+    #[error("Unknown keyboard layout: {0}")]
+    UnknownKeyLayoutError(String),
+
+    #[error("Invalid keymap:\n{0}")]
+    KeymapError(String),
+
+    #[error("Invalid gamepad profile:\n{0}")]
+    GamepadProfileError(String),
+
+    #[error("Invalid palette:\n{0}")]
+    PaletteError(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
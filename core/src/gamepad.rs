@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+use sdl2::controller::{Button, GameController};
+use sdl2::{GameControllerSubsystem, Sdl};
+
+use crate::errors::{Error, Result};
+use crate::helpers::config_path;
+use crate::key_input::NUM_KEYS;
+
+// User profile inside the configuration directory, used over ROM key hints
+const PROFILE_FILE: &str = "gamepad.json";
+// Moving with the keys around 5 is the most common layout
+const DEFAULT_BUTTONS: [(Button, u8); 10] = [
+    (Button::DPadUp, 0x5),
+    (Button::DPadDown, 0x8),
+    (Button::DPadLeft, 0x7),
+    (Button::DPadRight, 0x9),
+    (Button::A, 0x6),
+    (Button::B, 0x4),
+    (Button::X, 0x1),
+    (Button::Y, 0x3),
+    (Button::LeftShoulder, 0xA),
+    (Button::RightShoulder, 0xB),
+];
+// D-pad buttons of the direction actions in ROM key hints
+const DIRECTION_BUTTONS: [(&str, Button); 4] = [
+    ("up", Button::DPadUp),
+    ("down", Button::DPadDown),
+    ("left", Button::DPadLeft),
+    ("right", Button::DPadRight),
+];
+// Actions in ROM key hints bound to the start button
+const START_ACTIONS: [&str; 3] = ["start", "pause", "menu"];
+// Buttons given to the remaining actions in order
+const ACTION_BUTTONS: [Button; 6] = [
+    Button::A,
+    Button::B,
+    Button::X,
+    Button::Y,
+    Button::LeftShoulder,
+    Button::RightShoulder,
+];
+
+/// Keypad key pressed by each controller button
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GamepadProfile {
+    buttons: HashMap<Button, u8>,
+}
+
+impl Default for GamepadProfile {
+    fn default() -> Self {
+        Self {
+            buttons: HashMap::from(DEFAULT_BUTTONS),
+        }
+    }
+}
+
+impl GamepadProfile {
+    /// Profile mapping SDL button names to keys, e.g. `{"dpup": 5, "a": 6}`
+    pub fn parse(text: &str) -> Result<Self> {
+        let fields: BTreeMap<String, u8> = serde_json::from_str(text)
+            .map_err(|e| Error::GamepadProfileError(e.to_string()))?;
+
+        let mut buttons = HashMap::new();
+        for (name, key) in fields {
+            let button = Button::from_string(&name)
+                .ok_or_else(|| Error::GamepadProfileError(format!("Unknown button: {name}")))?;
+            if key as usize >= NUM_KEYS {
+                return Err(Error::GamepadProfileError(format!("Invalid key for {name}")));
+            }
+            buttons.insert(button, key);
+        }
+        Ok(Self { buttons })
+    }
+
+    /// Profile for the actions of a ROM's key hints. Directions go to the
+    /// D-pad and other actions to the face and shoulder buttons
+    pub fn from_hints(keys: &BTreeMap<String, u8>) -> Self {
+        let mut buttons = HashMap::new();
+        let mut action_buttons = ACTION_BUTTONS.iter();
+        for (action, &key) in keys {
+            let action = action.to_ascii_lowercase();
+            let direction = DIRECTION_BUTTONS.iter().find(|(name, _)| *name == action);
+            let button = match direction {
+                Some((_, button)) => Some(button),
+                None if START_ACTIONS.contains(&action.as_str()) => Some(&Button::Start),
+                None => action_buttons.next(),
+            };
+            if let Some(&button) = button {
+                buttons.insert(button, key);
+            }
+        }
+        Self { buttons }
+    }
+
+    /// Profile read from the given file. Without one, the user's profile
+    /// file is used, then the ROM's key hints, then the default profile
+    pub fn load(path: Option<&str>, rom_keys: &BTreeMap<String, u8>) -> Result<Self> {
+        if let Some(path) = path {
+            return Self::read(Path::new(path));
+        }
+
+        match config_path(PROFILE_FILE).filter(|path| path.exists()) {
+            Some(path) => Self::read(&path),
+            None if !rom_keys.is_empty() => Ok(Self::from_hints(rom_keys)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::GamepadProfileError(format!("{}: {e}", path.display())))?;
+        Self::parse(&text)
+    }
+
+    /// Keypad key pressed by a button
+    pub fn key(&self, button: Button) -> Option<u8> {
+        self.buttons.get(&button).copied()
+    }
+}
+
+/// Game controllers in use. Controllers plugged in while running are
+/// opened as they appear
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    // Open controllers by joystick instance id
+    controllers: HashMap<u32, GameController>,
+    profile: GamepadProfile,
+}
+
+impl Gamepads {
+    pub fn try_new(sdl_context: &Sdl, profile: GamepadProfile) -> Result<Self> {
+        let subsystem = sdl_context.game_controller().map_err(Error::SdlError)?;
+        let joysticks = subsystem.num_joysticks().map_err(Error::SdlError)?;
+        let mut gamepads = Self {
+            subsystem,
+            controllers: HashMap::new(),
+            profile,
+        };
+        for joystick_index in 0..joysticks {
+            gamepads.connect(joystick_index);
+        }
+        Ok(gamepads)
+    }
+
+    /// Open the joystick if SDL knows its controller mapping
+    pub fn connect(&mut self, joystick_index: u32) {
+        if !self.subsystem.is_game_controller(joystick_index) {
+            return;
+        }
+        match self.subsystem.open(joystick_index) {
+            Ok(controller) => {
+                let name = controller.name();
+                if self.controllers.insert(controller.instance_id(), controller).is_none() {
+                    info!("Connected controller {name}");
+                }
+            }
+            Err(e) => warn!("Failed to open controller {joystick_index}: {e}"),
+        }
+    }
+
+    pub fn disconnect(&mut self, instance_id: u32) {
+        if let Some(controller) = self.controllers.remove(&instance_id) {
+            info!("Disconnected controller {}", controller.name());
+        }
+    }
+
+    /// Keypad key pressed by a button
+    pub fn key(&self, button: Button) -> Option<u8> {
+        self.profile.key(button)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hints_fill_dpad_then_buttons() {
+        let keys = BTreeMap::from([
+            ("Left".into(), 0x7),
+            ("right".into(), 0x9),
+            ("rotate".into(), 0x4),
+            ("drop".into(), 0x6),
+            ("pause".into(), 0xF),
+        ]);
+        let profile = GamepadProfile::from_hints(&keys);
+
+        assert_eq!(profile.key(Button::DPadLeft), Some(0x7));
+        assert_eq!(profile.key(Button::DPadRight), Some(0x9));
+        assert_eq!(profile.key(Button::A), Some(0x6));
+        assert_eq!(profile.key(Button::B), Some(0x4));
+        assert_eq!(profile.key(Button::Start), Some(0xF));
+        assert_eq!(profile.key(Button::DPadUp), None);
+    }
+
+    #[test]
+    fn given_profile_is_used_over_hints() {
+        let path = std::env::temp_dir().join(format!("gamepad-test-{}.json", std::process::id()));
+        fs::write(&path, r#"{"a": 1}"#).unwrap();
+        let keys = BTreeMap::from([("up".into(), 0x2)]);
+
+        let profile = GamepadProfile::load(path.to_str(), &keys).unwrap();
+        assert_eq!(profile.key(Button::A), Some(0x1));
+        assert_eq!(profile.key(Button::DPadUp), None);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn profile_parses_button_names() {
+        let profile = GamepadProfile::parse(r#"{"dpup": 2, "a": 10}"#).unwrap();
+        assert_eq!(profile.key(Button::DPadUp), Some(0x2));
+        assert_eq!(profile.key(Button::A), Some(0xA));
+        assert_eq!(profile.key(Button::B), None);
+
+        for text in [r#"{"trigger": 1}"#, r#"{"a": 16}"#, r#"["a"]"#] {
+            assert!(GamepadProfile::parse(text).is_err(), "{text}");
+        }
+    }
+}
@@ -0,0 +1,606 @@
+use std::collections::BTreeSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use crate::errors::{Error, Result};
+use crate::processor::{Processor, NUM_REGS};
+use crate::save_state::ProcessorState;
+use crate::stack::STACK_SIZE;
+use crate::watchpoint::{WatchHit, WatchKind, Watchpoint};
+
+// Register numbers following V0-VF
+const I_REGISTER: usize = NUM_REGS;
+const PC_REGISTER: usize = NUM_REGS + 1;
+const SP_REGISTER: usize = NUM_REGS + 2;
+const DT_REGISTER: usize = NUM_REGS + 3;
+const ST_REGISTER: usize = NUM_REGS + 4;
+const NUM_GDB_REGS: usize = NUM_REGS + 5;
+
+const INTERRUPT: u8 = 0x03;
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const MAX_PACKET_SIZE: usize = 0x1000;
+
+/// How the client asked execution to resume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    Step,
+    /// Keep running without the debugger
+    Detach,
+    Kill,
+}
+
+/// Server for the GDB remote serial protocol. Registers are numbered
+/// V0-VF, I, PC, SP, DT, ST and sent in little-endian byte order
+pub struct GdbStub {
+    stream: TcpStream,
+    // Received bytes not yet handled
+    buffer: Vec<u8>,
+    breakpoints: BTreeSet<u16>,
+    interrupted: bool,
+    // Cleared once the client closes the connection
+    connected: bool,
+}
+
+impl GdbStub {
+    /// Wait for a client to connect to the local port
+    pub fn listen(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .map_err(|e| Error::GdbConnectionError(e.to_string()))?;
+        let (stream, _) = listener
+            .accept()
+            .map_err(|e| Error::GdbConnectionError(e.to_string()))?;
+        Self::new(stream)
+    }
+
+    /// Serve a connected client. The stream is read without blocking, so it
+    /// can be polled while the program runs
+    fn new(stream: TcpStream) -> Result<Self> {
+        let error = |e: std::io::Error| Error::GdbConnectionError(e.to_string());
+        stream.set_nodelay(true).map_err(error)?;
+        stream.set_nonblocking(true).map_err(error)?;
+
+        Ok(Self {
+            stream,
+            buffer: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            interrupted: false,
+            connected: true,
+        })
+    }
+
+    pub fn breakpoint_at(&self, address: u16) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    /// Handle the packets received so far. Returns how to resume once
+    /// the client asks for it, or None while the target stays stopped
+    pub fn process(&mut self, processor: &mut Processor) -> Result<Option<Resume>> {
+        if !self.receive()? {
+            // Connection closed by the client
+            return Ok(Some(Resume::Detach));
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            if let Some(resume) = self.handle_packet(&packet, processor)? {
+                return Ok(Some(resume));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Check if the client has asked to stop the running program. A closed
+    /// connection also stops it, and is then treated as a detach
+    pub fn interrupted(&mut self) -> bool {
+        match self.receive() {
+            Ok(true) if self.buffer.contains(&INTERRUPT) => {
+                self.buffer.retain(|byte| *byte != INTERRUPT);
+                self.interrupted = true;
+            }
+            Ok(true) => {}
+            Ok(false) | Err(_) => self.connected = false,
+        }
+        self.interrupted || !self.connected
+    }
+
+    /// Whether the client is still connected
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Tell the client that the target has stopped, and which
+    /// watchpoint stopped it
+    pub fn report_stop(&mut self, watch_hit: Option<WatchHit>) -> Result<()> {
+        let signal = if self.interrupted { SIGINT } else { SIGTRAP };
+        self.interrupted = false;
+
+        let reply = match watch_hit {
+            Some(hit) => {
+                // Named after the Z packet that set the watchpoint
+                let reason = match hit.watchpoint.kind {
+                    WatchKind::Write | WatchKind::Change => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                format!("T{signal:02x}{reason}:{:x};", hit.access.address)
+            }
+            None => format!("S{signal:02x}"),
+        };
+        self.send(&reply)
+    }
+
+    /// Tell the client that the program has ended
+    pub fn report_exit(&mut self) -> Result<()> {
+        self.send("W00")
+    }
+
+    /// Read available bytes without blocking. Returns false if the
+    /// connection has been closed
+    fn receive(&mut self) -> Result<bool> {
+        let mut chunk = [0; MAX_PACKET_SIZE];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(length) => self.buffer.extend_from_slice(&chunk[..length]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::GdbConnectionError(e.to_string())),
+            }
+        }
+    }
+
+    /// Take the next complete packet from the buffer and acknowledge it
+    fn next_packet(&mut self) -> Result<Option<String>> {
+        // Acknowledgements and interrupts while stopped are ignored
+        let start = match self.buffer.iter().position(|byte| *byte == b'$') {
+            Some(start) => start,
+            None => {
+                self.buffer.clear();
+                return Ok(None);
+            }
+        };
+        self.buffer.drain(..start);
+
+        // Packets are terminated by # and a two digit checksum
+        let end = match self.buffer.iter().position(|byte| *byte == b'#') {
+            Some(end) if end + 2 < self.buffer.len() => end,
+            _ => return Ok(None),
+        };
+        let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+        let data = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..])
+            .ok()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+
+        if checksum != Some(packet_checksum(data)) {
+            self.write(b"-")?;
+            return Ok(None);
+        }
+        self.write(b"+")?;
+
+        Ok(Some(String::from_utf8_lossy(data).into_owned()))
+    }
+
+    fn send(&mut self, data: &str) -> Result<()> {
+        let packet = format!("${data}#{:02x}", packet_checksum(data.as_bytes()));
+        self.write(packet.as_bytes())
+    }
+
+    // Replies are written blocking, as the client may read slower
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        let error = |e: std::io::Error| Error::GdbConnectionError(e.to_string());
+        self.stream.set_nonblocking(false).map_err(error)?;
+        self.stream.write_all(bytes).map_err(error)?;
+        self.stream.set_nonblocking(true).map_err(error)
+    }
+
+    /// Reply to a packet. Returns how to resume for packets resuming execution
+    fn handle_packet(
+        &mut self,
+        packet: &str,
+        processor: &mut Processor,
+    ) -> Result<Option<Resume>> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => format!("S{SIGTRAP:02x}"),
+            "g" => {
+                let state = processor.snapshot();
+                (0..NUM_GDB_REGS)
+                    .filter_map(|register| read_register(&state, register))
+                    .collect()
+            }
+            "G" => {
+                let mut state = processor.snapshot();
+                let mut values = args;
+                let mut valid = true;
+                for register in 0..NUM_GDB_REGS {
+                    let size = register_size(register) * 2;
+                    if values.len() < size {
+                        break;
+                    }
+                    let (value, rest) = values.split_at(size);
+                    valid &= write_register(&mut state, register, value);
+                    values = rest;
+                }
+                if valid && processor.restore(&state).is_ok() {
+                    "OK".into()
+                } else {
+                    "E01".into()
+                }
+            }
+            "p" => {
+                let state = processor.snapshot();
+                usize::from_str_radix(args, 16)
+                    .ok()
+                    .and_then(|register| read_register(&state, register))
+                    .unwrap_or_else(|| "E01".into())
+            }
+            "P" => {
+                let mut state = processor.snapshot();
+                let valid = match args.split_once('=') {
+                    Some((register, value)) => usize::from_str_radix(register, 16)
+                        .is_ok_and(|register| write_register(&mut state, register, value)),
+                    None => false,
+                };
+                if valid && processor.restore(&state).is_ok() {
+                    "OK".into()
+                } else {
+                    "E01".into()
+                }
+            }
+            "m" => parse_range(args)
+                .and_then(|(address, length)| {
+                    let end = (address as usize).checked_add(length)?;
+                    processor.ram().get(address as usize..end)
+                })
+                .map(encode_hex)
+                .unwrap_or_else(|| "E01".into()),
+            "M" => {
+                let written = args.split_once(':').and_then(|(range, data)| {
+                    let (address, length) = parse_range(range)?;
+                    let bytes = decode_hex(data).filter(|bytes| bytes.len() == length)?;
+                    processor.write_ram(address, &bytes).ok()
+                });
+                match written {
+                    Some(()) => "OK".into(),
+                    None => "E01".into(),
+                }
+            }
+            // Resuming at another address is not supported
+            "c" => return Ok(Some(Resume::Continue)),
+            "s" => return Ok(Some(Resume::Step)),
+            "D" => {
+                self.send("OK")?;
+                return Ok(Some(Resume::Detach));
+            }
+            "k" => return Ok(Some(Resume::Kill)),
+            "Z" | "z" => self.update_breakpoint(command == "Z", args, processor),
+            "H" => "OK".into(),
+            "q" => query(args),
+            _ => String::new(),
+        };
+
+        self.send(&reply)?;
+        Ok(None)
+    }
+
+    /// Insert or remove a breakpoint or watchpoint. Software and hardware
+    /// breakpoints are treated the same
+    fn update_breakpoint(
+        &mut self,
+        insert: bool,
+        args: &str,
+        processor: &mut Processor,
+    ) -> String {
+        let fields: Vec<&str> = args.split(',').collect();
+        let [kind, address, length] = fields[..] else {
+            return "E01".into();
+        };
+        let (Ok(address), Ok(length)) =
+            (u16::from_str_radix(address, 16), u16::from_str_radix(length, 16))
+        else {
+            return "E01".into();
+        };
+
+        let watch_kind = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                return "OK".into();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+
+        let end = address.saturating_add(length.max(1) - 1);
+        let watchpoint = Watchpoint::new(address, end, watch_kind);
+        if insert {
+            processor.add_watchpoint(watchpoint);
+        } else {
+            processor.remove_watchpoint(&watchpoint);
+        }
+        "OK".into()
+    }
+}
+
+/// Reply to a general query packet
+fn query(args: &str) -> String {
+    if args.starts_with("Supported") {
+        format!("PacketSize={MAX_PACKET_SIZE:x};qXfer:features:read+")
+    } else if let Some(request) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        let description = target_description();
+        let range = parse_range(request)
+            .and_then(|(offset, length)| Some((offset, (offset as usize).checked_add(length)?)));
+        match range {
+            Some((offset, end)) => {
+                let start = (offset as usize).min(description.len());
+                let end = end.min(description.len());
+                let prefix = if end == description.len() { 'l' } else { 'm' };
+                format!("{prefix}{}", &description[start..end])
+            }
+            None => "E01".into(),
+        }
+    } else if args == "Attached" {
+        "1".into()
+    } else if args == "C" {
+        "QC1".into()
+    } else if args == "fThreadInfo" {
+        "m1".into()
+    } else if args == "sThreadInfo" {
+        "l".into()
+    } else {
+        String::new()
+    }
+}
+
+/// XML description of the registers for the client
+fn target_description() -> String {
+    let mut registers: Vec<String> = (0..NUM_REGS)
+        .map(|i| format!("<reg name=\"v{i:x}\" bitsize=\"8\" type=\"uint8\"/>"))
+        .collect();
+    registers.push("<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>".into());
+    registers.push("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>".into());
+    registers.push("<reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>".into());
+    registers.push("<reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>".into());
+    registers.push("<reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>".into());
+
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.chip8emu.core\">{}</feature></target>",
+        registers.join("")
+    )
+}
+
+fn register_size(register: usize) -> usize {
+    match register {
+        I_REGISTER | PC_REGISTER => 2,
+        _ => 1,
+    }
+}
+
+/// Hex encoded value of the register
+fn read_register(state: &ProcessorState, register: usize) -> Option<String> {
+    let value = match register {
+        0..NUM_REGS => state.v_reg[register] as u16,
+        I_REGISTER => state.i_reg,
+        PC_REGISTER => state.pc,
+        SP_REGISTER => state.sp,
+        DT_REGISTER => state.dt as u16,
+        ST_REGISTER => state.st as u16,
+        _ => return None,
+    };
+    Some(encode_hex(&value.to_le_bytes()[..register_size(register)]))
+}
+
+/// Set the register from its hex encoded value. Returns false if the
+/// register or value is invalid
+fn write_register(state: &mut ProcessorState, register: usize, hex: &str) -> bool {
+    let Some(bytes) = decode_hex(hex).filter(|bytes| bytes.len() == register_size(register))
+    else {
+        return false;
+    };
+    let value = u16::from_le_bytes([bytes[0], bytes.get(1).copied().unwrap_or(0)]);
+
+    match register {
+        0..NUM_REGS => state.v_reg[register] = value as u8,
+        I_REGISTER => state.i_reg = value,
+        PC_REGISTER => state.pc = value,
+        SP_REGISTER if value as usize <= STACK_SIZE => state.sp = value,
+        DT_REGISTER => state.dt = value as u8,
+        ST_REGISTER => state.st = value as u8,
+        _ => return false,
+    }
+    true
+}
+
+/// Parse address and length given as "addr,length" in hex
+fn parse_range(range: &str) -> Option<(u16, usize)> {
+    let (address, length) = range.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn packet_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+    use crate::watchpoint::MemoryAccess;
+
+    fn stub() -> (GdbStub, TcpStream) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (GdbStub::new(stream).unwrap(), client)
+    }
+
+    /// Read bytes sent to the client up to the end of the next packet
+    fn read_reply(client: &mut TcpStream) -> String {
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        while reply.len() < 3 || reply[reply.len() - 3] != b'#' {
+            client.read_exact(&mut byte).unwrap();
+            reply.push(byte[0]);
+        }
+        String::from_utf8(reply).unwrap()
+    }
+
+    /// Data of the reply to the packet
+    fn request(
+        packet: &str,
+        stub: &mut GdbStub,
+        client: &mut TcpStream,
+        processor: &mut Processor,
+    ) -> String {
+        assert_eq!(stub.handle_packet(packet, processor).unwrap(), None, "{packet}");
+        let reply = read_reply(client);
+        let (data, checksum) = reply[1..].split_once('#').unwrap();
+        assert_eq!(checksum, format!("{:02x}", packet_checksum(data.as_bytes())));
+        data.into()
+    }
+
+    #[test]
+    fn helpers_encode_packets() {
+        assert_eq!(packet_checksum(b"OK"), 0x9A);
+        assert_eq!(packet_checksum(b""), 0);
+        assert_eq!(packet_checksum(&[0xFF, 0x02]), 0x01);
+
+        assert_eq!(decode_hex("0aFF"), Some(vec![0x0A, 0xFF]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+
+        assert_eq!(parse_range("200,10"), Some((0x200, 0x10)));
+        assert_eq!(parse_range("200,ffffffffffffffff"), Some((0x200, usize::MAX)));
+        assert_eq!(parse_range("10000,1"), None);
+        assert_eq!(parse_range("200"), None);
+    }
+
+    #[test]
+    fn packets_are_acknowledged() {
+        let (mut stub, mut client) = stub();
+        stub.buffer = b"+$g#67$g#00".to_vec();
+
+        assert_eq!(stub.next_packet().unwrap().as_deref(), Some("g"));
+        assert_eq!(stub.next_packet().unwrap(), None);
+        let mut acks = [0; 2];
+        client.read_exact(&mut acks).unwrap();
+        assert_eq!(&acks, b"+-");
+    }
+
+    #[test]
+    fn interrupts_and_disconnects_stop_the_program() {
+        let (mut stub, mut client) = stub();
+        // Data sent by the client arrives after a short while
+        let wait_for_stop = |stub: &mut GdbStub| {
+            for _ in 0..1000 {
+                if stub.interrupted() {
+                    return;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            panic!("Program was not stopped");
+        };
+
+        assert!(!stub.interrupted());
+        client.write_all(&[INTERRUPT]).unwrap();
+        wait_for_stop(&mut stub);
+        assert!(stub.connected());
+        stub.report_stop(None).unwrap();
+        assert_eq!(read_reply(&mut client), "$S02#b5");
+
+        drop(client);
+        wait_for_stop(&mut stub);
+        assert!(!stub.connected());
+    }
+
+    #[test]
+    fn packets_are_answered() {
+        let (mut stub, mut client) = stub();
+        let rom = [0x60, 0x2A, 0xA3, 0x00];
+        let platform = Platform::Chip8;
+        let mut processor = Processor::try_new(&rom, platform, platform.default_quirks()).unwrap();
+        let mut request = |packet: &str, processor: &mut Processor| {
+            request(packet, &mut stub, &mut client, processor)
+        };
+
+        assert_eq!(request("m200,4", &mut processor), "602aa300");
+        assert_eq!(request("m200,ffffffffffffffff", &mut processor), "E01");
+        assert_eq!(request("mfff,2", &mut processor), "E01");
+        assert_eq!(request("M300,2:abcd", &mut processor), "OK");
+        assert_eq!(request("m300,2", &mut processor), "abcd");
+        assert_eq!(request("P10=3412", &mut processor), "OK");
+        assert_eq!(request("p10", &mut processor), "3412");
+        assert_eq!(request("p11", &mut processor), "0002");
+        assert_eq!(request("Z2,300,2", &mut processor), "OK");
+        assert_eq!(processor.watchpoints(), [Watchpoint::new(0x300, 0x301, WatchKind::Write)]);
+
+        let xfer = "qXfer:features:read:target.xml:";
+        assert!(request(&format!("{xfer}0,10"), &mut processor).starts_with("m<?xml"));
+        assert_eq!(request(&format!("{xfer}10,ffffffffffffffff"), &mut processor), "E01");
+    }
+
+    #[test]
+    fn stops_name_the_watchpoint_kind() {
+        let (mut stub, mut client) = stub();
+        let access = MemoryAccess {
+            address: 0x300,
+            kind: WatchKind::Read,
+            old_value: 0,
+            new_value: 0,
+        };
+        for (kind, reply) in [
+            (WatchKind::Read, "T05rwatch:300;"),
+            (WatchKind::Write, "T05watch:300;"),
+            (WatchKind::Access, "T05awatch:300;"),
+        ] {
+            let hit = WatchHit {
+                pc: 0x200,
+                opcode: 0xF065,
+                watchpoint: Watchpoint::new(0x300, 0x300, kind),
+                access,
+            };
+            stub.report_stop(Some(hit)).unwrap();
+            assert!(read_reply(&mut client).starts_with(&format!("${reply}#")));
+        }
+    }
+
+    #[test]
+    fn resume_packets_resume() {
+        let (mut stub, _client) = stub();
+        let platform = Platform::Chip8;
+        let mut processor = Processor::try_new(&[], platform, platform.default_quirks()).unwrap();
+
+        let resumes = [("c", Resume::Continue), ("s", Resume::Step), ("k", Resume::Kill)];
+        for (packet, resume) in resumes {
+            assert_eq!(stub.handle_packet(packet, &mut processor).unwrap(), Some(resume));
+        }
+    }
+}
@@ -1,11 +1,13 @@
-use sdl2::keyboard::Keycode;
+use std::env;
+use std::path::PathBuf;
 
-/// Get the register numbers X and Y in an opcode: NXYN
-pub fn decode_middle_registers(opcode: u16) -> (u16, u16) {
-    let reg_x = (opcode & 0x0F00) >> 8;
-    let reg_y = (opcode & 0x00F0) >> 4;
-    (reg_x, reg_y)
-}
+use sha1::{Digest, Sha1};
+
+// Directory of the emulator's files inside the configuration directory
+const CONFIG_DIRECTORY: &str = "chip8emu";
+
+/// Size of the SHA-1 hash identifying a ROM
+pub const ROM_HASH_SIZE: usize = 20;
 
 /// Get the nth bit in a byte as a boolean starting
 /// with most significant bit and zero-based indexing
@@ -13,25 +15,16 @@ pub fn bit_to_bool(byte: u8, n: u8) -> bool {
     ((byte >> (7 - n)) & 0x1) != 0
 }
 
-/// Matches SDL keycode to corresponding key number
-pub fn keycode_to_button(keycode: Keycode) -> Option<usize> {
-    match keycode {
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0xC),
-        Keycode::Q => Some(0x4),
-        Keycode::W => Some(0x5),
-        Keycode::E => Some(0x6),
-        Keycode::R => Some(0xD),
-        Keycode::A => Some(0x7),
-        Keycode::S => Some(0x8),
-        Keycode::D => Some(0x9),
-        Keycode::F => Some(0xE),
-        Keycode::Z => Some(0xA),
-        Keycode::X => Some(0x0),
-        Keycode::C => Some(0xB),
-        Keycode::V => Some(0xF),
-        _ => None,
-    }
-}
\ No newline at end of file
+/// Location of a file in the user's configuration directory
+pub fn config_path(file: &str) -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(config.join(CONFIG_DIRECTORY).join(file))
+}
+
+/// SHA-1 hash identifying a ROM in the ROM database, save states and movies
+pub fn rom_hash(rom: &[u8]) -> [u8; ROM_HASH_SIZE] {
+    Sha1::digest(rom).into()
+}
@@ -1,26 +1,44 @@
-use sdl2::keyboard::Keycode;
+use std::collections::BTreeMap;
 
-use crate::helpers::keycode_to_button;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+pub const NUM_KEYS: usize = 16;
+
+/// Keypad keys by name, e.g. the keys of ROM actions or keyboard keys
+pub(crate) fn deserialize_keys<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, u8>, D::Error> {
+    let keys = BTreeMap::<String, u8>::deserialize(deserializer)?;
+    match keys.iter().find(|(_, &key)| key as usize >= NUM_KEYS) {
+        Some((name, _)) => Err(D::Error::custom(format!("Invalid key for {name}"))),
+        None => Ok(keys),
+    }
+}
 
 pub struct KeyInput {
-    keys: [bool; 16],
+    keys: [bool; NUM_KEYS],
 }
 
 impl KeyInput {
     pub fn new() -> Self {
-        Self { keys: [false; 16] }
+        Self { keys: [false; NUM_KEYS] }
     }
 
-    pub fn key_press(&mut self, keycode: Keycode) {
-        if let Some(key_number) = keycode_to_button(keycode) {
-            self.keys[key_number] = true;
-        }
+    pub fn keys(&self) -> [bool; NUM_KEYS] {
+        self.keys
     }
 
-    pub fn key_release(&mut self, keycode: Keycode) {
-        if let Some(key_number) = keycode_to_button(keycode) {
-            self.keys[key_number] = false;
-        }
+    pub fn set_keys(&mut self, keys: [bool; NUM_KEYS]) {
+        self.keys = keys;
+    }
+
+    pub fn key_press(&mut self, key_number: u8) {
+        self.keys[key_number as usize] = true;
+    }
+
+    pub fn key_release(&mut self, key_number: u8) {
+        self.keys[key_number as usize] = false;
     }
 
     pub fn check_key(&mut self, key_number: u8) -> bool {
@@ -39,6 +57,6 @@ impl KeyInput {
     }
 
     pub fn reset(&mut self) {
-        self.keys = [false; 16];
+        self.keys = [false; NUM_KEYS];
     }
 }
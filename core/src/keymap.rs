@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use sdl2::keyboard::Keycode;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+use crate::errors::{Error, Result};
+use crate::helpers::config_path;
+use crate::key_input::{deserialize_keys, NUM_KEYS};
+
+// Keymap used by default, inside the configuration directory
+const KEYMAP_FILE: &str = "keymap.json";
+// Keys in the order of the keypad rows 123C, 456D, 789E and A0BF
+const KEYPAD: [u8; NUM_KEYS] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// Keyboard layouts. Each binds the four keys below and including 1 to 4
+/// in the shape of the keypad, wherever the layout puts their letters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyLayout {
+    #[default]
+    Qwerty,
+    Qwertz,
+    Azerty,
+    Dvorak,
+    Colemak,
+}
+
+impl KeyLayout {
+    /// SDL names of the keys in keypad order
+    fn key_names(self) -> [&'static str; NUM_KEYS] {
+        match self {
+            KeyLayout::Qwerty => [
+                "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V",
+            ],
+            KeyLayout::Qwertz => [
+                "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Y", "X", "C", "V",
+            ],
+            // The number row gives symbols without shift
+            KeyLayout::Azerty => [
+                "&", "é", "\"", "'", "A", "Z", "E", "R", "Q", "S", "D", "F", "W", "X", "C", "V",
+            ],
+            KeyLayout::Dvorak => [
+                "1", "2", "3", "4", "'", ",", ".", "P", "A", "O", "E", "U", ";", "Q", "J", "K",
+            ],
+            KeyLayout::Colemak => [
+                "1", "2", "3", "4", "Q", "W", "F", "P", "A", "R", "S", "T", "Z", "X", "C", "V",
+            ],
+        }
+    }
+}
+
+impl FromStr for KeyLayout {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "qwerty" => Ok(KeyLayout::Qwerty),
+            "qwertz" => Ok(KeyLayout::Qwertz),
+            "azerty" => Ok(KeyLayout::Azerty),
+            "dvorak" => Ok(KeyLayout::Dvorak),
+            "colemak" => Ok(KeyLayout::Colemak),
+            _ => Err(Error::UnknownKeyLayoutError(s.into())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyLayout {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+/// Changes to a keymap, read from a keymap file or a ROM database entry
+/// written as `{"layout": "azerty", "keys": {"Up": 5, "Down": 8}}`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    /// Layout replacing all earlier bindings
+    pub layout: Option<KeyLayout>,
+    /// Key by SDL key name, added to the bindings of the layout
+    #[serde(deserialize_with = "deserialize_keys")]
+    pub keys: BTreeMap<String, u8>,
+}
+
+impl KeyBindings {
+    pub fn parse(text: &str) -> Result<Self> {
+        serde_json::from_str(text).map_err(|e| Error::KeymapError(e.to_string()))
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let invalid = |e: String| Error::KeymapError(format!("{}: {e}", path.display()));
+        let text = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+/// Translates keyboard keys to keypad keys. Several keyboard keys can press
+/// the same keypad key, e.g. to give each player their own side of the
+/// keyboard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: HashMap<Keycode, u8>,
+}
+
+impl Keymap {
+    pub fn new(layout: KeyLayout) -> Self {
+        let keys = layout
+            .key_names()
+            .iter()
+            .zip(KEYPAD)
+            .filter_map(|(name, key)| Some((Keycode::from_name(name)?, key)))
+            .collect();
+        Self { keys }
+    }
+
+    /// Keymap given as a layout name or keymap file. Without one, the user's
+    /// keymap file changed by the bindings of the ROM is used
+    pub fn load(keymap: Option<&str>, rom_bindings: &KeyBindings) -> Result<Self> {
+        let mut map = Self::new(KeyLayout::default());
+        if let Some(keymap) = keymap {
+            match keymap.parse() {
+                Ok(layout) => map = Self::new(layout),
+                Err(_) => map.apply(&KeyBindings::read(Path::new(keymap))?)?,
+            }
+            return Ok(map);
+        }
+
+        if let Some(path) = config_path(KEYMAP_FILE).filter(|path| path.exists()) {
+            map.apply(&KeyBindings::read(&path)?)?;
+        }
+        map.apply(rom_bindings)?;
+        Ok(map)
+    }
+
+    pub fn apply(&mut self, bindings: &KeyBindings) -> Result<()> {
+        if let Some(layout) = bindings.layout {
+            *self = Self::new(layout);
+        }
+        for (name, &key) in &bindings.keys {
+            let keycode = Keycode::from_name(name)
+                .ok_or_else(|| Error::KeymapError(format!("Unknown key: {name}")))?;
+            self.keys.insert(keycode, key);
+        }
+        Ok(())
+    }
+
+    /// Keypad key pressed by a keyboard key
+    pub fn key(&self, keycode: Keycode) -> Option<u8> {
+        self.keys.get(&keycode).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_bind_every_key() {
+        for layout in ["qwerty", "qwertz", "azerty", "dvorak", "colemak"] {
+            let keymap = Keymap::new(layout.parse().unwrap());
+            let mut keys: Vec<u8> = keymap.keys.values().copied().collect();
+            keys.sort();
+            assert_eq!(keys, (0..NUM_KEYS as u8).collect::<Vec<_>>(), "{layout}");
+        }
+    }
+
+    #[test]
+    fn bindings_add_keys() {
+        let bindings = KeyBindings::parse(r#"{"layout": "azerty", "keys": {"Up": 5}}"#).unwrap();
+        let mut keymap = Keymap::new(KeyLayout::Qwerty);
+        keymap.apply(&bindings).unwrap();
+
+        assert_eq!(keymap.key(Keycode::Z), Some(0x5));
+        assert_eq!(keymap.key(Keycode::Up), Some(0x5));
+        assert_eq!(keymap.key(Keycode::Q), Some(0x7));
+    }
+
+    #[test]
+    fn invalid_bindings_are_rejected() {
+        for text in [
+            r#"{"layout": "bépo"}"#,
+            r#"{"keys": {"Up": 16}}"#,
+            r#"{"keys": ["Up"]}"#,
+            r#"{"players": 2}"#,
+        ] {
+            assert!(KeyBindings::parse(text).is_err(), "{text}");
+        }
+        let bindings = KeyBindings::parse(r#"{"keys": {"NoSuchKey": 1}}"#).unwrap();
+        assert!(Keymap::new(KeyLayout::Qwerty).apply(&bindings).is_err());
+    }
+}
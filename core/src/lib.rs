@@ -1,11 +1,27 @@
 #![allow(clippy::new_without_default)]
 
+pub mod cartridge;
+pub mod debugger;
 pub mod emulator;
+pub mod gdb_stub;
+pub mod movie;
 pub mod processor;
+pub mod rewind;
+pub mod rng;
+pub mod rom_database;
+pub mod save_state;
+pub mod platform;
+pub mod quirks;
 pub mod memory;
 pub mod stack;
+pub mod trace;
+pub mod watchpoint;
 pub mod display;
+pub mod palette;
 pub mod key_input;
+pub mod keymap;
+pub mod gamepad;
 pub mod audio_output;
 pub mod helpers;
+mod binary;
 pub mod errors;
\ No newline at end of file
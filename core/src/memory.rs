@@ -1,7 +1,11 @@
+use std::cell::Cell;
+
 use crate::errors::Error;
 use crate::errors::Result;
+use crate::watchpoint::{MemoryAccess, WatchKind, Watchpoint};
 
 pub const RAM_SIZE: usize = 4096;
+pub const XO_RAM_SIZE: usize = 65536;
 pub const START_ADDR: u16 = 0x200;
 pub const MAX_ROM_SIZE: usize = RAM_SIZE - START_ADDR as usize;
 
@@ -26,29 +30,65 @@ pub const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+pub const BIG_FONTSET_SIZE: usize = 16 * 10;
+pub const BIG_FONTSET_ADDR: u16 = FONTSET_ADDR + FONTSET_SIZE as u16;
+pub const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
 #[derive(Debug)]
 pub struct Memory {
-    ram: [u8; RAM_SIZE],
+    ram: Vec<u8>,
     rom_loaded: bool,
+    watchpoints: Vec<Watchpoint>,
+    // First access triggering a watchpoint since the last check.
+    // Reads only borrow memory, so the hit is kept in a cell
+    watch_hit: Cell<Option<(Watchpoint, MemoryAccess)>>,
+    // Address and value of each write while logging for the trace
+    write_log: Option<Vec<(u16, u8)>>,
+    // Address and previous value of each write while logging for rewinding
+    undo_log: Option<Vec<(u16, u8)>>,
 }
 
 impl Memory {
-    pub fn new() -> Self {
+    pub fn new(size: usize) -> Self {
         let mut memory = Self {
-            ram: [0; RAM_SIZE],
+            ram: vec![0; size],
             rom_loaded: false,
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
+            write_log: None,
+            undo_log: None,
         };
 
         // Copying font set into ram from address 0x50 (80)
         // Get target location in RAM as slice and copy font set to it
         memory.ram[FONTSET_ADDR as usize..(FONTSET_ADDR as usize + FONTSET_SIZE)]
             .copy_from_slice(&FONTSET);
+        // The SUPER-CHIP big font follows directly after the small font
+        memory.ram[BIG_FONTSET_ADDR as usize..(BIG_FONTSET_ADDR as usize + BIG_FONTSET_SIZE)]
+            .copy_from_slice(&BIG_FONTSET);
 
         memory
     }
 
     pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
-        if rom.len() <= MAX_ROM_SIZE {
+        if rom.len() <= self.ram.len() - START_ADDR as usize {
             self.ram[START_ADDR as usize..START_ADDR as usize + rom.len()].copy_from_slice(rom);
         } else {
             return Err(Error::InvalidRomSizeError);
@@ -62,18 +102,57 @@ impl Memory {
         self.rom_loaded
     }
 
+    pub fn size(&self) -> usize {
+        self.ram.len()
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Replace the contents of RAM, resizing it if the platform changed
+    pub fn restore(&mut self, ram: &[u8]) {
+        self.ram.clear();
+        self.ram.extend_from_slice(ram);
+        self.rom_loaded = true;
+        // Earlier writes cannot be undone on top of the new contents
+        if let Some(log) = &mut self.undo_log {
+            log.clear();
+        }
+    }
+
     pub fn read(&self, address: u16) -> u8 {
-        self.ram[address as usize]
+        let value = self.ram[address as usize];
+        self.watch(address, WatchKind::Read, value, value);
+        value
+    }
+
+    /// Read big-endian instruction word. Instruction fetches do not
+    /// trigger watchpoints
+    pub fn read_word(&self, address: u16) -> u16 {
+        let high_byte = self.ram[address as usize] as u16;
+        let low_byte = self.ram[address as usize + 1] as u16;
+        (high_byte << 8) | low_byte
     }
 
     pub fn write(&mut self, address: u16, value: u8) {
+        self.watch(address, WatchKind::Write, self.ram[address as usize], value);
+        self.log_write(address, self.ram[address as usize], value);
         self.ram[address as usize] = value;
     }
 
-    pub fn read_slice(&self, address: u16, length: u16) -> &[u8] {
+    pub fn read_slice(&self, address: u16, length: u16) -> Result<&[u8]> {
         let address = address as usize;
         let length = length as usize;
-        &self.ram[address..address + length]
+        // Check if memory addresses are valid
+        let slice = self
+            .ram
+            .get(address..address + length)
+            .ok_or(Error::InvalidRamAddressError)?;
+        for (offset, value) in slice.iter().enumerate() {
+            self.watch((address + offset) as u16, WatchKind::Read, *value, *value);
+        }
+        Ok(slice)
     }
 
     pub fn write_slice(&mut self, slice: &[u8], address: u16) -> Result<()> {
@@ -83,13 +162,94 @@ impl Memory {
         if address + length > self.ram.len() {
             return Err(Error::InvalidRamAddressError);
         }
+        for (offset, value) in slice.iter().enumerate() {
+            let old_value = self.ram[address + offset];
+            self.watch((address + offset) as u16, WatchKind::Write, old_value, *value);
+            self.log_write((address + offset) as u16, old_value, *value);
+        }
         self.ram[address..address + length].copy_from_slice(slice);
 
         Ok(())
     }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Remove the watchpoint. Returns false if it was not set
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Get and clear the access that triggered a watchpoint, together
+    /// with the watchpoint
+    pub fn take_watch_hit(&self) -> Option<(Watchpoint, MemoryAccess)> {
+        self.watch_hit.take()
+    }
+
+    /// Start logging writes
+    pub fn start_write_log(&mut self) {
+        self.write_log = Some(Vec::new());
+    }
+
+    /// Stop logging writes and get the writes logged
+    pub fn take_write_log(&mut self) -> Vec<(u16, u8)> {
+        self.write_log.take().unwrap_or_default()
+    }
+
+    /// Start logging the previous values of written bytes
+    pub fn start_undo_log(&mut self) {
+        self.undo_log = Some(Vec::new());
+    }
+
+    /// Get the previous values of the bytes written since the last call,
+    /// oldest first. Logging continues
+    pub fn take_undo_log(&mut self) -> Vec<(u16, u8)> {
+        self.undo_log.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Put back previous values without logging or triggering watchpoints
+    pub fn undo(&mut self, undo_log: &[(u16, u8)]) {
+        for &(address, value) in undo_log.iter().rev() {
+            self.ram[address as usize] = value;
+        }
+    }
+
+    fn log_write(&mut self, address: u16, old_value: u8, new_value: u8) {
+        if let Some(log) = &mut self.write_log {
+            log.push((address, new_value));
+        }
+        if let Some(log) = &mut self.undo_log {
+            log.push((address, old_value));
+        }
+    }
+
+    /// Record the access if it triggers a watchpoint and nothing
+    /// has been recorded yet
+    fn watch(&self, address: u16, kind: WatchKind, old_value: u8, new_value: u8) {
+        if self.watchpoints.is_empty() || self.watch_hit.get().is_some() {
+            return;
+        }
+
+        let access = MemoryAccess {
+            address,
+            kind,
+            old_value,
+            new_value,
+        };
+        if let Some(watchpoint) = self.watchpoints.iter().find(|w| w.triggered_by(&access)) {
+            self.watch_hit.set(Some((*watchpoint, access)));
+        }
+    }
     
     pub fn reset(&mut self) {
-        self.ram = [0; RAM_SIZE];
+        self.ram.fill(0);
         self.rom_loaded = false;
     }
 }
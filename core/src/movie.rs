@@ -0,0 +1,247 @@
+use std::fs;
+
+use crate::binary::{BinaryReader, BinaryWriter};
+use crate::errors::{Error, Result};
+use crate::helpers::ROM_HASH_SIZE;
+use crate::key_input::NUM_KEYS;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::save_state::MachineState;
+
+const MOVIE_MAGIC: &[u8; 4] = b"C8MV";
+pub const MOVIE_VERSION: u16 = 2;
+
+/// Input and state checksum of a single frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieFrame {
+    pub keys: [bool; NUM_KEYS],
+    // Checksum of the machine state at the end of the frame
+    pub checksum: u32,
+}
+
+/// Recording of the key input of every frame from power on, together with
+/// everything else needed to play it back exactly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: [u8; ROM_HASH_SIZE],
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub seed: u64,
+    /// Instructions executed per frame
+    pub cycles_per_frame: u32,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(
+        rom_hash: [u8; ROM_HASH_SIZE],
+        platform: Platform,
+        quirks: Quirks,
+        seed: u64,
+        cycles_per_frame: u32,
+    ) -> Self {
+        Self {
+            rom_hash,
+            platform,
+            quirks,
+            seed,
+            cycles_per_frame,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_bytes()).map_err(|e| Error::MovieFileError(e.to_string()))
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let bytes = fs::read(path).map_err(|e| Error::MovieFileError(e.to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = BinaryWriter::new();
+
+        // Header
+        writer.bytes(MOVIE_MAGIC);
+        writer.u16(MOVIE_VERSION);
+        writer.bytes(&self.rom_hash);
+        writer.u8(self.platform.id());
+        writer.u8(self.quirks.bits());
+        writer.u64(self.seed);
+        writer.u32(self.cycles_per_frame);
+
+        // Frames with keys stored as a bit mask
+        writer.u32(self.frames.len() as u32);
+        for frame in &self.frames {
+            let keys = frame
+                .keys
+                .iter()
+                .enumerate()
+                .fold(0u16, |mask, (i, pressed)| mask | ((*pressed as u16) << i));
+            writer.u16(keys);
+            writer.u32(frame.checksum);
+        }
+
+        writer.into_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = BinaryReader::new(bytes, Error::InvalidMovieError);
+
+        // Header
+        if reader.bytes(MOVIE_MAGIC.len())? != MOVIE_MAGIC {
+            return Err(reader.error("Not a movie file"));
+        }
+        let version = reader.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(reader.error(&format!("Unsupported version {version}")));
+        }
+        let rom_hash = reader.array()?;
+        let platform = Platform::from_id(reader.u8()?).ok_or(reader.error("Unknown platform"))?;
+        let quirks = Quirks::from_bits(reader.u8()?);
+        let seed = reader.u64()?;
+        let cycles_per_frame = reader.u32()?;
+        if cycles_per_frame == 0 {
+            return Err(reader.error("Invalid cycles per frame"));
+        }
+
+        // Frames
+        // The count is not trusted for allocating, as the file may be truncated
+        let frame_count = reader.u32()?;
+        let mut frames = Vec::new();
+        for _ in 0..frame_count {
+            let mask = reader.u16()?;
+            let mut keys = [false; NUM_KEYS];
+            for (i, key) in keys.iter_mut().enumerate() {
+                *key = (mask >> i) & 0x1 != 0;
+            }
+            frames.push(MovieFrame {
+                keys,
+                checksum: reader.u32()?,
+            });
+        }
+
+        Ok(Self {
+            rom_hash,
+            platform,
+            quirks,
+            seed,
+            cycles_per_frame,
+            frames,
+        })
+    }
+
+    /// Check the state at the end of a replayed frame against the recording
+    pub fn check_frame(&self, frame: usize, checksum: u32) -> Result<()> {
+        match self.frames.get(frame) {
+            Some(recorded) if recorded.checksum != checksum => Err(Error::MovieDesyncError(frame)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// FNV-1a checksum of the machine state, used to detect replays
+/// diverging from the recording
+pub fn state_checksum(state: &MachineState, rom_hash: &[u8; ROM_HASH_SIZE]) -> u32 {
+    state
+        .to_bytes(rom_hash)
+        .iter()
+        .fold(0x811C9DC5u32, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x01000193))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::Display;
+    use crate::key_input::KeyInput;
+    use crate::processor::Processor;
+    use crate::rng::SeededRng;
+
+    const ROM_HASH: [u8; ROM_HASH_SIZE] = [0x42; ROM_HASH_SIZE];
+
+    // Get a random number in V0 forever
+    const ROM: [u8; 4] = [0xC0, 0xFF, 0x12, 0x00];
+
+    fn movie() -> Movie {
+        let platform = Platform::XoChip;
+        let mut movie = Movie::new(ROM_HASH, platform, platform.default_quirks(), 77, 30);
+        for i in 0..20 {
+            let mut keys = [false; NUM_KEYS];
+            keys[i % NUM_KEYS] = true;
+            keys[0xF] = i % 3 == 0;
+            movie.frames.push(MovieFrame {
+                keys,
+                checksum: i as u32 * 0x01020304,
+            });
+        }
+        movie
+    }
+
+    /// Checksum at the end of each frame when running with the seed
+    fn checksums(seed: u64, frames: usize) -> Vec<u32> {
+        let platform = Platform::Chip8;
+        let mut processor = Processor::try_new(&ROM, platform, platform.default_quirks()).unwrap();
+        processor.set_rng(Box::new(SeededRng::new(seed)));
+        let mut display = Display::headless();
+        let mut input = KeyInput::new();
+
+        (0..frames)
+            .map(|_| {
+                for _ in 0..ROM.len() / 2 {
+                    processor.cycle(&mut display, &mut input).unwrap();
+                }
+                let state = MachineState {
+                    processor: processor.snapshot(),
+                    ram: processor.ram().to_vec(),
+                    display: display.snapshot(),
+                    keys: input.keys(),
+                };
+                state_checksum(&state, &ROM_HASH)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn movies_round_trip() {
+        let movie = movie();
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+
+        let mut movie = movie;
+        movie.cycles_per_frame = 0;
+        assert!(Movie::from_bytes(&movie.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn truncated_movies_are_refused() {
+        let mut bytes = movie().to_bytes();
+        bytes.pop();
+        assert!(matches!(Movie::from_bytes(&bytes), Err(Error::InvalidMovieError(_))));
+
+        // A huge frame count must not be trusted
+        let mut bytes = Movie::new(ROM_HASH, Platform::Chip8, Quirks::default(), 0, 1).to_bytes();
+        let count = bytes.len() - 4;
+        bytes[count..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(Movie::from_bytes(&bytes), Err(Error::InvalidMovieError(_))));
+    }
+
+    #[test]
+    fn replay_with_other_seed_desyncs() {
+        let mut movie = Movie::new(ROM_HASH, Platform::Chip8, Quirks::default(), 1, 2);
+        for checksum in checksums(1, 10) {
+            movie.frames.push(MovieFrame {
+                keys: [false; NUM_KEYS],
+                checksum,
+            });
+        }
+
+        for (frame, checksum) in checksums(1, 10).into_iter().enumerate() {
+            movie.check_frame(frame, checksum).unwrap();
+        }
+        let desync = checksums(2, 10)
+            .into_iter()
+            .enumerate()
+            .find_map(|(frame, checksum)| movie.check_frame(frame, checksum).err());
+        assert!(matches!(desync, Some(Error::MovieDesyncError(0))));
+    }
+}
@@ -0,0 +1,300 @@
+use std::fmt;
+use std::fs;
+
+use serde::de::{Error as _, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::display::{DEFAULT_PALETTE, NUM_PLANES};
+use crate::errors::{Error, Result};
+use crate::helpers::config_path;
+
+// Custom palettes inside the configuration directory
+const PALETTES_FILE: &str = "palettes.json";
+// Name of palettes given as colours rather than by name
+const CUSTOM_NAME: &str = "custom";
+const ROM_NAME: &str = "rom";
+
+/// Palettes shipped with the emulator, cycled through in this order
+const BUILTIN_PALETTES: [(&str, Colors); 5] = [
+    ("classic", DEFAULT_PALETTE),
+    ("amber", [[26, 14, 0], [255, 176, 0], [153, 96, 0], [255, 224, 144]]),
+    ("white", [[0, 0, 0], [255, 255, 255], [170, 170, 170], [85, 85, 85]]),
+    ("lcd", [[155, 188, 15], [15, 56, 15], [48, 98, 48], [139, 172, 15]]),
+    ("octo", [[153, 102, 0], [255, 204, 0], [255, 102, 0], [102, 34, 0]]),
+];
+
+/// RGB colour of each pixel value indexed by its plane bits
+pub type Colors = [[u8; 3]; 1 << NUM_PLANES];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Colors,
+}
+
+impl Palette {
+    /// Palette from colours starting with the background. Colours for the
+    /// second plane and for both planes are derived if not given
+    pub fn from_colors(name: &str, colors: &[[u8; 3]]) -> Self {
+        let mut complete = DEFAULT_PALETTE;
+        if let [background, foreground, ..] = colors {
+            complete[2] = mix(*background, *foreground);
+            complete[3] = *foreground;
+        }
+        for (color, given) in complete.iter_mut().zip(colors) {
+            *color = *given;
+        }
+        Self {
+            name: name.into(),
+            colors: complete,
+        }
+    }
+}
+
+/// Palettes available for cycling with the current one first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palettes {
+    palettes: Vec<Palette>,
+    current: usize,
+}
+
+impl Palettes {
+    /// Built-in palettes and the user's custom palettes. The one used first
+    /// is the given palette name or list of colours, then the ROM's colours,
+    /// then the user's default palette, then the classic palette
+    pub fn load(palette: Option<&str>, rom_colors: &[[u8; 3]]) -> Result<Self> {
+        let (custom, default) = match config_path(PALETTES_FILE).filter(|path| path.exists()) {
+            Some(path) => {
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| Error::PaletteError(format!("{}: {e}", path.display())))?;
+                parse_palettes(&text)?
+            }
+            None => (Vec::new(), None),
+        };
+        Self::choose(custom, default, palette, rom_colors)
+    }
+
+    /// Built-in palettes followed by the custom palettes, starting with the
+    /// one chosen as described for `load`
+    fn choose(
+        custom: Vec<Palette>,
+        default: Option<String>,
+        palette: Option<&str>,
+        rom_colors: &[[u8; 3]],
+    ) -> Result<Self> {
+        let mut palettes: Vec<Palette> = BUILTIN_PALETTES
+            .iter()
+            .map(|(name, colors)| Palette::from_colors(name, colors))
+            .collect();
+        palettes.extend(custom);
+
+        let mut palettes = Self {
+            palettes,
+            current: 0,
+        };
+        match (palette, default) {
+            (Some(palette), _) => match palettes.position(palette) {
+                Some(position) => palettes.current = position,
+                None => palettes.select(Palette::from_colors(CUSTOM_NAME, &parse_colors(palette)?)),
+            },
+            (None, _) if !rom_colors.is_empty() => {
+                palettes.select(Palette::from_colors(ROM_NAME, rom_colors))
+            }
+            (None, Some(default)) => {
+                palettes.current = palettes.position(&default).ok_or_else(|| {
+                    Error::PaletteError(format!("Unknown default palette: {default}"))
+                })?;
+            }
+            (None, None) => {}
+        }
+        Ok(palettes)
+    }
+
+    pub fn current(&self) -> &Palette {
+        &self.palettes[self.current]
+    }
+
+    /// Switch to the next palette, wrapping around after the last one
+    pub fn cycle(&mut self) -> &Palette {
+        self.current = (self.current + 1) % self.palettes.len();
+        self.current()
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.palettes
+            .iter()
+            .position(|palette| palette.name.eq_ignore_ascii_case(name))
+    }
+
+    // Palettes not in the list are used first and cycled through last
+    fn select(&mut self, palette: Palette) {
+        self.palettes.insert(0, palette);
+        self.current = 0;
+    }
+}
+
+/// Colour written as RRGGBB, optionally starting with #
+pub(crate) fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 {
+        return None;
+    }
+    let [_, r, g, b] = u32::from_str_radix(hex, 16).ok()?.to_be_bytes();
+    Some([r, g, b])
+}
+
+/// Colours written as a list of RRGGBB strings
+pub(crate) fn deserialize_colors<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<[u8; 3]>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|color| {
+            parse_color(color).ok_or_else(|| D::Error::custom(format!("Invalid colour {color}")))
+        })
+        .collect()
+}
+
+/// Comma separated colours starting with the background
+fn parse_colors(text: &str) -> Result<Vec<[u8; 3]>> {
+    let colors = text
+        .split(',')
+        .map(|color| parse_color(color.trim()))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| Error::PaletteError(format!("Unknown palette: {text}")))?;
+    if colors.len() > 1 << NUM_PLANES {
+        return Err(Error::PaletteError(format!("Too many colours: {text}")));
+    }
+    Ok(colors)
+}
+
+// Name and colours of a custom palette
+type PaletteEntry = (String, Vec<[u8; 3]>);
+
+/// Contents of the palettes file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PalettesFile {
+    default: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_palettes")]
+    palettes: Vec<PaletteEntry>,
+}
+
+/// Custom palettes and the name of the default palette, written as
+/// `{"default": "sunset", "palettes": {"sunset": ["#200010", "#FF8040"]}}`
+fn parse_palettes(text: &str) -> Result<(Vec<Palette>, Option<String>)> {
+    let file: PalettesFile =
+        serde_json::from_str(text).map_err(|e| Error::PaletteError(e.to_string()))?;
+    let palettes = file
+        .palettes
+        .into_iter()
+        .map(|(name, colors)| {
+            if !(1..=1 << NUM_PLANES).contains(&colors.len()) {
+                return Err(Error::PaletteError(format!("Invalid palette {name}")));
+            }
+            Ok(Palette::from_colors(&name, &colors))
+        })
+        .collect::<Result<_>>()?;
+    Ok((palettes, file.default))
+}
+
+// Palettes keep the order they are written in, which is the order they are
+// cycled through
+fn deserialize_palettes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<PaletteEntry>, D::Error> {
+    struct PalettesVisitor;
+
+    impl<'de> Visitor<'de> for PalettesVisitor {
+        type Value = Vec<PaletteEntry>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an object of palettes")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(
+            self,
+            mut map: A,
+        ) -> std::result::Result<Self::Value, A::Error> {
+            let mut palettes = Vec::new();
+            while let Some(name) = map.next_key::<String>()? {
+                let colors = map.next_value::<ColorList>()?.0;
+                palettes.push((name, colors));
+            }
+            Ok(palettes)
+        }
+    }
+
+    deserializer.deserialize_map(PalettesVisitor)
+}
+
+// List of colours inside a map of palettes
+struct ColorList(Vec<[u8; 3]>);
+
+impl<'de> Deserialize<'de> for ColorList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserialize_colors(deserializer).map(ColorList)
+    }
+}
+
+/// Colour halfway between two colours
+fn mix(first: [u8; 3], second: [u8; 3]) -> [u8; 3] {
+    [0, 1, 2].map(|i| ((first[i] as u16 + second[i] as u16) / 2) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_colours_fill_the_other_planes() {
+        let palette = Palette::from_colors("mono", &[[0, 0, 0], [255, 255, 255]]);
+        assert_eq!(palette.colors, [[0, 0, 0], [255, 255, 255], [127, 127, 127], [255, 255, 255]]);
+    }
+
+    #[test]
+    fn palettes_are_chosen_and_cycled() {
+        let choose = |palette, rom_colors| Palettes::choose(Vec::new(), None, palette, rom_colors);
+        let mut palettes = choose(Some("Amber"), &[]).unwrap();
+        assert_eq!(palettes.current().name, "amber");
+        assert_eq!(palettes.cycle().name, "white");
+
+        let mut palettes = choose(Some("#000000, FF0000"), &[]).unwrap();
+        assert_eq!(palettes.current().colors[1], [255, 0, 0]);
+        assert_eq!(palettes.cycle().name, "classic");
+
+        let palettes = choose(None, &[[1, 2, 3], [4, 5, 6]]).unwrap();
+        assert_eq!(palettes.current().name, "rom");
+
+        assert!(choose(Some("purple"), &[]).is_err());
+        assert_eq!(choose(None, &[]).unwrap().current().name, "classic");
+    }
+
+    #[test]
+    fn custom_default_is_used_without_other_choices() {
+        let sunset = Palette::from_colors("sunset", &[[0x20, 0, 0x10], [0xFF, 0x80, 0x40]]);
+        let custom = vec![sunset.clone()];
+        let palettes = Palettes::choose(custom.clone(), Some("Sunset".into()), None, &[]).unwrap();
+        assert_eq!(palettes.current(), &sunset);
+
+        let palettes =
+            Palettes::choose(custom.clone(), Some("sunset".into()), Some("lcd"), &[]).unwrap();
+        assert_eq!(palettes.current().name, "lcd");
+
+        assert!(Palettes::choose(custom, Some("dusk".into()), None, &[]).is_err());
+    }
+
+    #[test]
+    fn custom_palettes_parse() {
+        let (palettes, default) = parse_palettes(
+            r##"{"default": "sunset", "palettes": {"sunset": ["#200010", "#FF8040"]}}"##,
+        )
+        .unwrap();
+        assert_eq!(default.as_deref(), Some("sunset"));
+        assert_eq!(palettes[0].colors[..2], [[0x20, 0, 0x10], [0xFF, 0x80, 0x40]]);
+
+        for text in [r#"{"palettes": {"a": []}}"#, r##"{"palettes": {"a": ["#12"]}}"##] {
+            assert!(parse_palettes(text).is_err(), "{text}");
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::str::FromStr;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+use crate::errors::Error;
+use crate::memory::{RAM_SIZE, XO_RAM_SIZE};
+use crate::quirks::{QuirkPreset, Quirks};
+
+/// Instruction set and display capabilities of the emulated machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    /// Quirks used when no quirk profile is chosen explicitly
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::default(),
+            Platform::SuperChip => QuirkPreset::SuperChip11.quirks(),
+            Platform::XoChip => QuirkPreset::XoChip.quirks(),
+        }
+    }
+
+    pub fn ram_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => RAM_SIZE,
+            Platform::XoChip => XO_RAM_SIZE,
+        }
+    }
+
+    /// Number identifying the platform in save states and movies
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Platform::Chip8),
+            1 => Some(Platform::SuperChip),
+            2 => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Ok(Platform::SuperChip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            _ => Err(Error::UnknownPlatformError(s.into())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Platform {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
@@ -1,15 +1,24 @@
-use log::debug;
-use rand::Rng;
-
-use crate::display::{Display, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::display::Display;
 use crate::errors::{Error, Result};
-use crate::helpers::decode_middle_registers;
 use crate::key_input::KeyInput;
-use crate::memory::{Memory, FONTSET_ADDR, RAM_SIZE, START_ADDR};
+use crate::audio_output::AUDIO_PATTERN_SIZE;
+use crate::memory::{Memory, BIG_FONTSET_ADDR, FONTSET_ADDR, START_ADDR};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::rng::{RandomSource, SeededRng};
+use crate::save_state::ProcessorState;
 use crate::stack::Stack;
+use disassembler::instruction::{Extension, Instruction};
+use crate::trace::{TraceEntry, TraceWriter};
+use crate::watchpoint::{WatchHit, Watchpoint};
 
 pub const NUM_REGS: usize = 16;
 pub const CARRY_REGISTER: usize = NUM_REGS - 1;
+// Number of SUPER-CHIP flag registers saved and loaded by FX75 and FX85.
+// XO-CHIP has one flag register for each general purpose register
+pub const NUM_FLAG_REGS: usize = 8;
+// Pitch at which XO-CHIP audio patterns play at 4000 bits per second
+pub const DEFAULT_PITCH: u8 = 64;
 
 #[derive(Debug)]
 pub struct Processor {
@@ -25,14 +34,29 @@ pub struct Processor {
     st: u8,
     // Delay timer
     dt: u8,
-    rng: rand::rngs::ThreadRng,
+    rng: Box<dyn RandomSource>,
+    platform: Platform,
+    quirks: Quirks,
+    // Set after drawing when DXYN has to wait for the vertical blank
+    vblank_wait: bool,
+    // SUPER-CHIP flag registers
+    flag_reg: [u8; NUM_REGS],
+    // XO-CHIP audio pattern buffer and playback pitch
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+    // Set by the SUPER-CHIP exit instruction
+    halted: bool,
+    // Watchpoint triggered by the last instruction
+    watch_hit: Option<WatchHit>,
+    trace: Option<TraceWriter>,
 }
 
 impl Processor {
-    pub fn try_new(rom: &[u8]) -> Result<Self> {
-        env_logger::init();
+    pub fn try_new(rom: &[u8], platform: Platform, quirks: Quirks) -> Result<Self> {
+        // Several processors may be created, e.g. in tests
+        let _ = env_logger::try_init();
 
-        let mut memory = Memory::new();
+        let mut memory = Memory::new(platform.ram_size());
         memory.load_rom(rom)?;
 
         Ok(Self {
@@ -43,7 +67,17 @@ impl Processor {
             stack: Stack::new(),
             st: 0,
             dt: 0,
-            rng: rand::rng(),
+            // Deterministic unless another source is set
+            rng: Box::new(SeededRng::new(0)),
+            platform,
+            quirks,
+            vblank_wait: false,
+            flag_reg: [0; NUM_REGS],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            halted: false,
+            watch_hit: None,
+            trace: None,
         })
     }
 
@@ -55,9 +89,108 @@ impl Processor {
         self.stack.reset();
         self.st = 0;
         self.dt = 0;
+        self.vblank_wait = false;
+        self.flag_reg = [0; NUM_REGS];
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.halted = false;
+        self.watch_hit = None;
+    }
+
+    /// Replace the source of random numbers used by CXNN
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Current subroutine nesting depth
+    pub fn stack_depth(&self) -> usize {
+        self.stack.depth()
+    }
+
+    /// Write every executed instruction to the trace
+    pub fn set_trace(&mut self, trace: TraceWriter) {
+        self.trace = Some(trace);
+    }
+
+    /// Get the state of everything but RAM
+    pub fn snapshot(&self) -> ProcessorState {
+        let (stack, sp) = self.stack.snapshot();
+        ProcessorState {
+            platform: self.platform,
+            quirks: self.quirks,
+            pc: self.pc,
+            v_reg: self.v_reg,
+            i_reg: self.i_reg,
+            stack,
+            sp,
+            st: self.st,
+            dt: self.dt,
+            flag_reg: self.flag_reg,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            vblank_wait: self.vblank_wait,
+            halted: self.halted,
+            rng: self.rng.state(),
+        }
+    }
+
+    /// Fails without changing anything if the state belongs to another
+    /// kind of random number generator
+    pub fn restore(&mut self, state: &ProcessorState) -> Result<()> {
+        self.rng.restore(&state.rng)?;
+        self.platform = state.platform;
+        self.quirks = state.quirks;
+        self.pc = state.pc;
+        self.v_reg = state.v_reg;
+        self.i_reg = state.i_reg;
+        self.stack.restore(state.stack, state.sp);
+        self.st = state.st;
+        self.dt = state.dt;
+        self.flag_reg = state.flag_reg;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        self.vblank_wait = state.vblank_wait;
+        self.halted = state.halted;
+        Ok(())
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        self.memory.ram()
     }
 
+    /// Replace RAM with RAM of the same size as the platform's
+    pub fn restore_ram(&mut self, ram: &[u8]) {
+        self.memory.restore(ram);
+    }
+
+    /// Start logging RAM writes so they can be undone when rewinding
+    pub fn start_undo_log(&mut self) {
+        self.memory.start_undo_log();
+    }
+
+    /// Address and previous value of each byte written since the last call
+    pub fn take_undo_log(&mut self) -> Vec<(u16, u8)> {
+        self.memory.take_undo_log()
+    }
+
+    /// Put back the previous values of logged writes, newest first
+    pub fn undo_ram_writes(&mut self, undo_log: &[(u16, u8)]) {
+        self.memory.undo(undo_log);
+    }
+
+    /// Write bytes to RAM starting at the address
+    pub fn write_ram(&mut self, address: u16, bytes: &[u8]) -> Result<()> {
+        self.memory.write_slice(bytes, address)
+    }
+
+    /// Called once per frame at the vertical blank
     pub fn tick_timers(&mut self) {
+        self.vblank_wait = false;
+
         if self.dt > 0 {
             self.dt -= 1;
         }
@@ -70,106 +203,214 @@ impl Processor {
         self.st > 0
     }
 
+    /// XO-CHIP audio pattern and pitch to play instead of the beep
+    pub fn audio_pattern(&self) -> Option<([u8; AUDIO_PATTERN_SIZE], u8)> {
+        self.audio_pattern.map(|pattern| (pattern, self.pitch))
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.memory.add_watchpoint(watchpoint);
+    }
+
+    /// Remove the watchpoint. Returns false if it was not set
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        self.memory.remove_watchpoint(watchpoint)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.memory.watchpoints()
+    }
+
+    /// Watchpoint triggered by the last executed instruction
+    pub fn watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit
+    }
+
+    /// Check if the program has ended with the exit instruction
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
     pub fn cycle(&mut self, display: &mut Display, input: &mut KeyInput) -> Result<()> {
+        // Forget accesses made outside of instructions, e.g. by debuggers
+        self.watch_hit = None;
+        self.memory.take_watch_hit();
+
         // Check if ROM as been loaded into RAM
         if !self.memory.rom_loaded() {
             return Err(Error::MissingRomError);
         }
 
-        // Check if the end of RAM is reached
-        if self.pc as usize >= RAM_SIZE {
+        // Check if the end of RAM is reached. The address after 0xFFFE does not fit in 16 bits
+        let pc = self.pc;
+        let next_pc = pc.checked_add(2).ok_or(Error::InvalidRamAddressError)?;
+        if pc as usize + 1 >= self.memory.size() {
             return Err(Error::InvalidRamAddressError);
         }
 
         // Get opcode as u16
-        let high_byte = self.memory.read(self.pc) as u16;
-        let low_byte = self.memory.read(self.pc + 1) as u16;
-        let opcode = (high_byte << 8) | low_byte;
-        self.pc += 2;
-
-        debug!("PC: {:x}, Opcode: 0x{:x}", self.pc - 2, opcode);
+        let opcode = self.read_word(pc);
+        self.pc = next_pc;
+
+        // Registers before the instruction, if it is traced
+        let traced = self.trace.as_mut().is_some_and(|trace| trace.next_cycle(pc));
+        let before = traced.then_some(self.v_reg);
+        if traced {
+            self.memory.start_write_log();
+        }
 
         // DECODE AND EXECUTE OPCODE
-        // Filter op code to match only the first half byte
-        match opcode & 0xF000 {
-            0x0000 => match opcode {
-                0x00E0 => display.clear(),
-                0x00EE => self.return_subroutine()?,
-                // If op code is 0NNN - call machine code subroutine,
-                // which isn't implemented.
-                _ => {
-                    return Err(Error::InvalidOpcodeError(
-                        "0NNN - Call machine code routine".into(),
-                    ))
-                }
-            },
-
-            0x1000 => self.jump(opcode),
-            0x2000 => self.call_subroutine(opcode)?,
-            0x3000 => self.skip_equal(opcode),
-            0x4000 => self.skip_not_equal(opcode),
-            0x5000 => self.skip_register_equal(opcode),
-            0x6000 => self.load_number(opcode),
-            0x7000 => self.add_number(opcode),
+        match self.decode(opcode)? {
+            Instruction::ScrollDown(n) => display.scroll_down(n as usize),
+            Instruction::ScrollUp(n) => display.scroll_up(n as usize),
+            Instruction::ClearScreen => display.clear(),
+            Instruction::Return => self.return_subroutine()?,
+            Instruction::ScrollRight => display.scroll_right(),
+            Instruction::ScrollLeft => display.scroll_left(),
+            Instruction::Exit => self.exit(),
+            Instruction::LowRes => display.set_hires(false),
+            Instruction::HighRes => display.set_hires(true),
+            // Rejected when decoding
+            Instruction::System(_) => unreachable!(),
+            Instruction::Jump(address) => self.jump(address),
+            Instruction::Call(address) => self.call_subroutine(address)?,
+            Instruction::SkipEqual { x, value } => self.skip_equal(x, value)?,
+            Instruction::SkipNotEqual { x, value } => self.skip_not_equal(x, value)?,
+            Instruction::SkipRegistersEqual { x, y } => self.skip_register_equal(x, y)?,
+            Instruction::SaveRange { x, y } => self.save_register_range(x, y)?,
+            Instruction::LoadRange { x, y } => self.load_register_range(x, y)?,
+            Instruction::Load { x, value } => self.load_number(x, value),
+            Instruction::Add { x, value } => self.add_number(x, value),
 
             // Register loading opcodes
-            0x8000 => match opcode & 0x000F {
-                // Simple load instruction
-                0x0 => self.load_register_op(opcode, |_, vy| vy),
-                // OR
-                0x1 => self.load_register_op(opcode, |vx, vy| vx | vy),
-                // AND
-                0x2 => self.load_register_op(opcode, |vx, vy| vx & vy),
-                // XOR
-                0x3 => self.load_register_op(opcode, |vx, vy| vx ^ vy),
-                0x4 => self.add_register_carry(opcode),
-                0x5 => self.sub_register(opcode),
-                0x6 => self.shift_right(opcode),
-                0x7 => self.sub_register_reversed(opcode),
-                0xE => self.shift_left(opcode),
-                _ => return Err(Error::UnknownOpcodeError(opcode)),
-            },
+            Instruction::Move { x, y } => self.load_register_op(x, y, false, |_, vy| vy),
+            Instruction::Or { x, y } => self.load_register_op(x, y, true, |vx, vy| vx | vy),
+            Instruction::And { x, y } => self.load_register_op(x, y, true, |vx, vy| vx & vy),
+            Instruction::Xor { x, y } => self.load_register_op(x, y, true, |vx, vy| vx ^ vy),
+            Instruction::AddRegisters { x, y } => self.add_register_carry(x, y),
+            Instruction::Sub { x, y } => self.sub_register(x, y),
+            Instruction::ShiftRight { x, y } => self.shift_right(x, y),
+            Instruction::SubReversed { x, y } => self.sub_register_reversed(x, y),
+            Instruction::ShiftLeft { x, y } => self.shift_left(x, y),
+
+            Instruction::SkipRegistersNotEqual { x, y } => self.skip_register_not_equal(x, y)?,
+            Instruction::LoadI(address) => self.load_i(address),
+            Instruction::JumpPlus(address) => self.jump_plus(address),
+            Instruction::Random { x, mask } => self.random_and(x, mask),
+            Instruction::Draw { x, y, n } => self.draw_sprite(x, y, n, display)?,
+            Instruction::SkipKeyDown(x) => self.skip_if_keypress(x, input)?,
+            Instruction::SkipKeyUp(x) => self.skip_if_not_keypress(x, input)?,
+
+            Instruction::LoadLongI => self.load_long_i()?,
+            Instruction::SelectPlanes(planes) => display.select_planes(planes),
+            Instruction::LoadAudioPattern => self.load_audio_pattern()?,
+            Instruction::MoveDelayTimer(x) => self.move_delay_timer(x),
+            Instruction::WaitKey(x) => self.wait_for_keypress(x, input),
+            Instruction::SetDelayTimer(x) => self.set_delay_timer(x),
+            Instruction::SetSoundTimer(x) => self.set_sound_timer(x),
+            Instruction::AddI(x) => self.load_add_i(x),
+            Instruction::Character(x) => self.find_character(x),
+            Instruction::BigCharacter(x) => self.find_big_character(x),
+            Instruction::Bcd(x) => self.store_bcd(x)?,
+            Instruction::SetPitch(x) => self.set_pitch(x),
+            Instruction::StoreRegisters(x) => self.dump_registers_to_ram(x)?,
+            Instruction::ReadRegisters(x) => self.load_registers_from_ram(x)?,
+            Instruction::SaveFlags(x) => self.save_flag_registers(x),
+            Instruction::LoadFlags(x) => self.load_flag_registers(x),
+        }
 
-            0x9000 => self.skip_register_not_equal(opcode),
-            0xA000 => self.load_i(opcode),
-            0xB000 => self.jump_plus(opcode),
-            0xC000 => self.random_and(opcode),
-            0xD000 => self.draw_sprite(opcode, display)?,
+        if let Some((watchpoint, access)) = self.memory.take_watch_hit() {
+            self.watch_hit = Some(WatchHit {
+                pc,
+                opcode,
+                watchpoint,
+                access,
+            });
+        }
 
-            0xE000 => match opcode & 0x00FF {
-                0x9E => self.skip_if_keypress(opcode, input),
-                0xA1 => self.skip_if_not_keypress(opcode, input),
-                _ => return Err(Error::UnknownOpcodeError(opcode)),
-            },
+        if let Some(before) = before {
+            self.write_trace(pc, opcode, &before)?;
+        }
 
-            0xF000 => match opcode & 0x00FF {
-                0x07 => self.move_delay_timer(opcode),
-                0x0A => self.wait_for_keypress(opcode, input),
-                0x15 => self.set_delay_timer(opcode),
-                0x18 => self.set_sound_timer(opcode),
-                0x1E => self.load_add_i(opcode),
-                0x29 => self.find_character(opcode),
-                0x33 => self.store_bcd(opcode),
-                0x55 => self.dump_registers_to_ram(opcode)?,
-                0x65 => self.load_registers_from_ram(opcode),
-                _ => return Err(Error::UnknownOpcodeError(opcode)),
-            }
+        Ok(())
+    }
 
-            _ => return Err(Error::UnknownOpcodeError(opcode)),
+    fn write_trace(&mut self, pc: u16, opcode: u16, before: &[u8; NUM_REGS]) -> Result<()> {
+        let entry = TraceEntry {
+            pc,
+            opcode,
+            changed_registers: (0..NUM_REGS)
+                .filter(|register| before[*register] != self.v_reg[*register])
+                .map(|register| (register, self.v_reg[register]))
+                .collect(),
+            i_reg: self.i_reg,
+            dt: self.dt,
+            st: self.st,
+            memory_writes: self.memory.take_write_log(),
+        };
+
+        match &mut self.trace {
+            Some(trace) => trace.write(&entry),
+            None => Ok(()),
         }
+    }
+
+    /// Decode opcode into an instruction available on the platform
+    fn decode(&self, opcode: u16) -> Result<Instruction> {
+        let instruction = Instruction::decode(opcode).ok_or(Error::UnknownOpcodeError(opcode))?;
+
+        let available = match instruction.extension() {
+            Extension::Chip8 => true,
+            Extension::SuperChip => self.super_chip(),
+            Extension::XoChip => self.xo_chip(),
+        };
+        match instruction {
+            // If op code is 0NNN - call machine code subroutine,
+            // which isn't implemented.
+            Instruction::System(_) => Err(Error::InvalidOpcodeError(
+                "0NNN - Call machine code routine".into(),
+            )),
+            _ if !available => Err(Error::UnknownOpcodeError(opcode)),
+            _ => Ok(instruction),
+        }
+    }
+
+    /// Check if the SUPER-CHIP instructions are available
+    fn super_chip(&self) -> bool {
+        self.platform != Platform::Chip8
+    }
+
+    /// Check if the XO-CHIP instructions are available
+    fn xo_chip(&self) -> bool {
+        self.platform == Platform::XoChip
+    }
+
+    /// Skip the next instruction. On XO-CHIP the four byte long
+    /// load instruction is skipped as a whole
+    fn skip_next(&mut self) -> Result<()> {
+        let in_ram = (self.pc as usize + 1) < self.memory.size();
+        if self.xo_chip() && in_ram && self.read_word(self.pc) == Instruction::LoadLongI.encode() {
+            self.pc = self.pc.checked_add(2).ok_or(Error::InvalidRamAddressError)?;
+        }
+        self.pc = self.pc.checked_add(2).ok_or(Error::InvalidRamAddressError)?;
 
         Ok(())
     }
 
+    /// Read big-endian 16-bit word from RAM
+    fn read_word(&self, address: u16) -> u16 {
+        self.memory.read_word(address)
+    }
+
     fn set_carry(&mut self, value: u8) {
         self.v_reg[CARRY_REGISTER] = value;
     }
 
-    fn set_reg(&mut self, register: u16, value: u8) {
+    fn set_reg(&mut self, register: u8, value: u8) {
         self.v_reg[register as usize] = value;
     }
 
-    fn get_reg(&self, register: u16) -> u8 {
+    fn get_reg(&self, register: u8) -> u8 {
         self.v_reg[register as usize]
     }
 
@@ -185,18 +426,23 @@ impl Processor {
         Ok(())
     }
 
+    /// Opcode 00FD
+    /// Exit the interpreter
+    fn exit(&mut self) {
+        self.halted = true;
+    }
+
     /// Opcode 1NNN
     /// Jump to address NNN
-    fn jump(&mut self, opcode: u16) {
-        self.pc = opcode & 0x0FFF;
+    fn jump(&mut self, address: u16) {
+        self.pc = address;
     }
 
     /// Opcode 2NNN
     /// Call subroutine at address NNN
-    fn call_subroutine(&mut self, opcode: u16) -> Result<()> {
+    fn call_subroutine(&mut self, address: u16) -> Result<()> {
         // PC is pushed to stack to remember where to return after subroutine
         self.stack.push(self.pc)?;
-        let address = opcode & 0x0FFF;
         self.pc = address;
 
         Ok(())
@@ -204,62 +450,93 @@ impl Processor {
 
     /// Opcode 3XNN
     /// Skip next instruction if VX == NN
-    fn skip_equal(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
-        let number = (opcode & 0x00FF) as u8;
+    fn skip_equal(&mut self, register: u8, number: u8) -> Result<()> {
         if number == self.get_reg(register) {
-            self.pc += 2;
+            self.skip_next()?;
         }
+
+        Ok(())
     }
 
     /// Opcode 4XNN
     /// Skip next instruction if VX != NN
-    fn skip_not_equal(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
-        let number = (opcode & 0x00FF) as u8;
+    fn skip_not_equal(&mut self, register: u8, number: u8) -> Result<()> {
         if number != self.get_reg(register) {
-            self.pc += 2;
+            self.skip_next()?;
         }
+
+        Ok(())
     }
 
     /// Opcode 5XY0
     /// Skip next instruction if VX == VY
-    fn skip_register_equal(&mut self, opcode: u16) {
-        let (reg_x, reg_y) = decode_middle_registers(opcode);
+    fn skip_register_equal(&mut self, reg_x: u8, reg_y: u8) -> Result<()> {
         if self.get_reg(reg_x) == self.get_reg(reg_y) {
-            self.pc += 2;
+            self.skip_next()?;
         }
+
+        Ok(())
+    }
+
+    /// Opcode 5XY2
+    /// Save VX through VY to RAM starting at the address in I register.
+    /// Registers are saved in reverse order if X is greater than Y
+    fn save_register_range(&mut self, reg_x: u8, reg_y: u8) -> Result<()> {
+        for (offset, register) in register_range(reg_x, reg_y).enumerate() {
+            let address = self.i_reg as usize + offset;
+            if address >= self.memory.size() {
+                return Err(Error::InvalidRamAddressError);
+            }
+            self.memory.write(address as u16, self.get_reg(register));
+        }
+
+        Ok(())
+    }
+
+    /// Opcode 5XY3
+    /// Load VX through VY from RAM starting at the address in I register.
+    /// Registers are loaded in reverse order if X is greater than Y
+    fn load_register_range(&mut self, reg_x: u8, reg_y: u8) -> Result<()> {
+        for (offset, register) in register_range(reg_x, reg_y).enumerate() {
+            let address = self.i_reg as usize + offset;
+            if address >= self.memory.size() {
+                return Err(Error::InvalidRamAddressError);
+            }
+            let value = self.memory.read(address as u16);
+            self.set_reg(register, value);
+        }
+
+        Ok(())
     }
 
     /// Opcode 6XNN
     /// Load NN into VX
-    fn load_number(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
-        let number = (opcode & 0x00FF) as u8;
+    fn load_number(&mut self, register: u8, number: u8) {
         self.set_reg(register, number);
     }
 
     /// Opcode 7XNN
     /// Add NN to VX (VX += NN)
-    fn add_number(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
-        let number = (opcode & 0x00FF) as u8;
+    fn add_number(&mut self, register: u8, number: u8) {
         let result = self.get_reg(register).wrapping_add(number);
         self.set_reg(register, result);
     }
 
-    /// Opcode 8XY1 to 8XY3
-    /// Load op(VX, VY) into VX
-    fn load_register_op<F: Fn(u8, u8) -> u8>(&mut self, opcode: u16, op: F) {
-        let (reg_x, reg_y) = decode_middle_registers(opcode);
+    /// Opcode 8XY0 to 8XY3
+    /// Load op(VX, VY) into VX. Logic operations are all but 8XY0
+    fn load_register_op<F: Fn(u8, u8) -> u8>(&mut self, reg_x: u8, reg_y: u8, logic: bool, op: F) {
         let result = op(self.get_reg(reg_x), self.get_reg(reg_y));
         self.set_reg(reg_x, result);
+
+        // Quirk the logic operations reset the carry register
+        if self.quirks.vf_reset && logic {
+            self.set_carry(0);
+        }
     }
 
     /// Opcode 8XY4
     /// Add value of VY to VX (VX += VY) and enable carry register if overflowing
-    fn add_register_carry(&mut self, opcode: u16) {
-        let (reg_x, reg_y) = decode_middle_registers(opcode);
+    fn add_register_carry(&mut self, reg_x: u8, reg_y: u8) {
         let result = self.get_reg(reg_x).wrapping_add(self.get_reg(reg_y));
         let carry = (result < self.get_reg(reg_x)) as u8;
         self.set_reg(reg_x, result);
@@ -271,8 +548,7 @@ impl Processor {
     /// Opcode 8XY5
     /// Subtract value of VY from VX (VX -= VY) and enable carry register
     /// if not borrowing
-    fn sub_register(&mut self, opcode: u16) {
-        let (reg_x, reg_y) = decode_middle_registers(opcode);
+    fn sub_register(&mut self, reg_x: u8, reg_y: u8) {
 
         // Enable carry register if subtraction borrows
         let not_borrow = (self.get_reg(reg_x) >= self.get_reg(reg_y)) as u8;
@@ -286,10 +562,11 @@ impl Processor {
     /// Opcode 8XY6
     /// Set carry register to least significant bit of VX
     /// and shift VX one bit right
-    fn shift_right(&mut self, opcode: u16) {
-        let (reg_x, reg_y) = decode_middle_registers(opcode);
-        // Quirk set VX to value of VY
-        self.set_reg(reg_x, self.get_reg(reg_y));
+    fn shift_right(&mut self, reg_x: u8, reg_y: u8) {
+        // Quirk set VX to value of VY unless shifting in place
+        if !self.quirks.shift {
+            self.set_reg(reg_x, self.get_reg(reg_y));
+        }
         let carry = self.get_reg(reg_x) & 0x1;
         self.set_reg(reg_x, self.get_reg(reg_x) >> 1);
         self.set_carry(carry);
@@ -298,8 +575,7 @@ impl Processor {
     /// Opcode 8XY7
     /// Subtract the value of VX from VY and load result into VX (VX = VY - VX)
     /// then enable carry register if not borrowing
-    fn sub_register_reversed(&mut self, opcode: u16) {
-        let (reg_x, reg_y) = decode_middle_registers(opcode);
+    fn sub_register_reversed(&mut self, reg_x: u8, reg_y: u8) {
 
         // Enable carry register if subtraction borrows
         let not_borrow = (self.get_reg(reg_y) >= self.get_reg(reg_x)) as u8;
@@ -313,10 +589,11 @@ impl Processor {
     /// Opcode 8XYE
     /// Set carry register to most significant bit of VX
     /// and shift VX one bit left
-    fn shift_left(&mut self, opcode: u16) {
-        let (reg_x, reg_y) = decode_middle_registers(opcode);
-        // Quirk set VX to value of VY
-        self.set_reg(reg_x, self.get_reg(reg_y));
+    fn shift_left(&mut self, reg_x: u8, reg_y: u8) {
+        // Quirk set VX to value of VY unless shifting in place
+        if !self.quirks.shift {
+            self.set_reg(reg_x, self.get_reg(reg_y));
+        }
         let carry = (self.get_reg(reg_x) & 0x80) >> 7;
         self.set_reg(reg_x, self.get_reg(reg_x) << 1);
         self.set_carry(carry);
@@ -324,54 +601,76 @@ impl Processor {
 
     /// Opcode 9XY0
     /// Skip next instruction if VX != VY
-    fn skip_register_not_equal(&mut self, opcode: u16) {
-        let (reg_x, reg_y) = decode_middle_registers(opcode);
+    fn skip_register_not_equal(&mut self, reg_x: u8, reg_y: u8) -> Result<()> {
         if self.get_reg(reg_x) != self.get_reg(reg_y) {
-            self.pc += 2;
+            self.skip_next()?;
         }
+
+        Ok(())
     }
 
     /// Opcode ANNN
     /// Set I register to NNN
-    fn load_i(&mut self, opcode: u16) {
-        self.i_reg = opcode & 0x0FFF;
+    fn load_i(&mut self, address: u16) {
+        self.i_reg = address;
     }
 
     /// Opcode BNNN
-    /// Jump to address at V0 + NNN
-    fn jump_plus(&mut self, opcode: u16) {
-        self.pc = self.get_reg(0) as u16 + (opcode & 0x0FFF);
+    /// Jump to address at V0 + NNN, or XNN + VX with the jump quirk
+    fn jump_plus(&mut self, address: u16) {
+        let register = if self.quirks.jump_with_vx {
+            (address >> 8) as u8
+        } else {
+            0
+        };
+        self.pc = self.get_reg(register) as u16 + address;
     }
 
     /// Opcode CXNN
     /// Generate random number, R, from 0 to 255 and add R AND NN to VX
-    fn random_and(&mut self, opcode: u16) {
-        let register = ((opcode & 0x0F00) >> 8) as usize;
-        let number = (opcode & 0x00FF) as u8;
-        let random: u8 = self.rng.random();
-        self.v_reg[register] = random & number;
+    fn random_and(&mut self, register: u8, number: u8) {
+        let random = self.rng.random_byte();
+        self.set_reg(register, random & number);
     }
 
     /// Opcode DXYN
     /// Draws N-byte (heigh of N pixels) on screen and enables
-    /// carry register if there is collision
-    fn draw_sprite(&mut self, opcode: u16, display: &mut Display) -> Result<()> {
-        let (reg_x, reg_y) = decode_middle_registers(opcode);
-        let rows = opcode & 0x000F;
+    /// carry register if there is collision. On SUPER-CHIP DXY0
+    /// draws a 16x16 sprite
+    fn draw_sprite(&mut self, reg_x: u8, reg_y: u8, n: u8, display: &mut Display) -> Result<()> {
+        // Quirk only draw once per frame and rerun opcode until the vertical blank
+        if self.quirks.display_wait {
+            if self.vblank_wait {
+                self.pc -= 2;
+                return Ok(());
+            }
+            self.vblank_wait = true;
+        }
+
+        let large = n == 0 && self.super_chip();
+        // Large sprites are 16 rows of two bytes
+        let length = if large { 32 } else { n as u16 };
+
+        // Sprite data is repeated for each selected XO-CHIP plane
+        let length = length * display.selected_plane_count() as u16;
 
         // Check if sprite bounds are within valid RAM addresses
-        if self.i_reg + rows > RAM_SIZE as u16 {
+        if self.i_reg as usize + length as usize > self.memory.size() {
             return Err(Error::InvalidRamAddressError);
         }
 
         // Set x and y coords to VX and VY with wrapping for the starting coord
-        let x_coord = self.get_reg(reg_x) % SCREEN_WIDTH as u8;
-        let y_coord = self.get_reg(reg_y) % SCREEN_HEIGHT as u8;
+        let x_coord = (self.get_reg(reg_x) as usize % display.width()) as u8;
+        let y_coord = (self.get_reg(reg_y) as usize % display.height()) as u8;
 
-        let sprite = self.memory.read_slice(self.i_reg, rows);
+        let sprite = self.memory.read_slice(self.i_reg, length)?;
 
         // Draw sprite on screen
-        let carry = display.draw(sprite, x_coord, y_coord);
+        let carry = if large {
+            display.draw_large(sprite, x_coord, y_coord, self.quirks.clipping)
+        } else {
+            display.draw(sprite, x_coord, y_coord, self.quirks.clipping)
+        };
         // Set carry register
         self.set_carry(carry);
         Ok(())
@@ -379,28 +678,52 @@ impl Processor {
 
     /// Opcode EX9E
     /// Skip next instruction if key VX is pressed down. Do not wait for input
-    fn skip_if_keypress(&mut self, opcode: u16, input: &mut KeyInput) {
-        let register = (opcode & 0x0F00) >> 8;
+    fn skip_if_keypress(&mut self, register: u8, input: &mut KeyInput) -> Result<()> {
         let key = self.get_reg(register);
         if input.check_key(key) {
-            self.pc += 2;
+            self.skip_next()?;
         }
+
+        Ok(())
     }
 
     /// Opcode EXA1
     /// Skip next instruction if key VX is *not* pressed down. Do not wait for input
-    fn skip_if_not_keypress(&mut self, opcode: u16, input: &mut KeyInput) {
-        let register = (opcode & 0x0F00) >> 8;
+    fn skip_if_not_keypress(&mut self, register: u8, input: &mut KeyInput) -> Result<()> {
         let key = self.get_reg(register);
         if !input.check_key(key) {
-            self.pc += 2;
+            self.skip_next()?;
         }
+
+        Ok(())
+    }
+
+    /// Opcode F000 NNNN
+    /// Load the 16-bit address following the instruction into I register
+    fn load_long_i(&mut self) -> Result<()> {
+        if self.pc as usize + 1 >= self.memory.size() {
+            return Err(Error::InvalidRamAddressError);
+        }
+        self.i_reg = self.read_word(self.pc);
+        self.pc = self.pc.checked_add(2).ok_or(Error::InvalidRamAddressError)?;
+
+        Ok(())
+    }
+
+    /// Opcode F002
+    /// Load 16 bytes from RAM starting at the address in I register
+    /// into the audio pattern buffer
+    fn load_audio_pattern(&mut self) -> Result<()> {
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        pattern.copy_from_slice(self.memory.read_slice(self.i_reg, AUDIO_PATTERN_SIZE as u16)?);
+        self.audio_pattern = Some(pattern);
+
+        Ok(())
     }
 
     /// Opcode FX07
     /// Load value of delay timer into VX
-    fn move_delay_timer(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
+    fn move_delay_timer(&mut self, register: u8) {
         let value = self.dt;
         self.set_reg(register, value);
     }
@@ -408,8 +731,7 @@ impl Processor {
     /// Opcode FX0A
     /// Wait for key press and store key value in VX. If no key is pressed
     /// the PC is decremented to rerun opcode
-    fn wait_for_keypress(&mut self, opcode: u16, input: &mut KeyInput) {
-        let register = (opcode & 0x0F00) >> 8;
+    fn wait_for_keypress(&mut self, register: u8, input: &mut KeyInput) {
         let key = match input.check_all_keys() {
             Some(key) => key,
             None => {
@@ -422,66 +744,457 @@ impl Processor {
 
     /// Opcode FX15
     /// Set delay timer to value of VX
-    fn set_delay_timer(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
+    fn set_delay_timer(&mut self, register: u8) {
         self.dt = self.get_reg(register);
     }
 
     /// Opcode FX18
     /// Set sound timer to value of VX
-    fn set_sound_timer(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
+    fn set_sound_timer(&mut self, register: u8) {
         self.st = self.get_reg(register);
     }
 
     /// Opcode FX1E
     /// Add I to VX and store in VX (I += VX)
-    fn load_add_i(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
-        self.i_reg += self.get_reg(register) as u16;
+    fn load_add_i(&mut self, register: u8) {
+        self.i_reg = self.i_reg.wrapping_add(self.get_reg(register) as u16);
     }
 
     /// Opcode FX29
     /// Set I register to the address of the character in font set
     /// corresponding to the value of VX
-    fn find_character(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
+    fn find_character(&mut self, register: u8) {
         let key_value = self.get_reg(register);
         self.i_reg = FONTSET_ADDR + 5 * key_value as u16;
     }
 
+    /// Opcode FX30
+    /// Set I register to the address of the big font character
+    /// corresponding to the value of VX
+    fn find_big_character(&mut self, register: u8) {
+        let key_value = self.get_reg(register) & 0xF;
+        self.i_reg = BIG_FONTSET_ADDR + 10 * key_value as u16;
+    }
+
+    /// Opcode FX3A
+    /// Set audio pattern playback pitch to value of VX
+    fn set_pitch(&mut self, register: u8) {
+        self.pitch = self.get_reg(register);
+    }
+
     /// Opcode FX33
     /// Store binary-coded decimal conversion of number in VX to
     /// RAM adresses I register, I + 1 and I + 2
-    fn store_bcd(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
+    fn store_bcd(&mut self, register: u8) -> Result<()> {
         let number = self.get_reg(register);
         let hundreds = number / 100;
         let tens = (number / 10) % 10;
         let ones = number % 10;
-        self.memory.write(self.i_reg, hundreds);
-        self.memory.write(self.i_reg + 1, tens);
-        self.memory.write(self.i_reg + 2, ones);
+        self.memory.write_slice(&[hundreds, tens, ones], self.i_reg)
     }
 
     /// Opcode FX55
     /// Dump registers from V0 through VX to RAM starting at the
     /// address in I register
-    fn dump_registers_to_ram(&mut self, opcode: u16) -> Result<()> {
-        let register = (opcode & 0x0F00) >> 8;
+    fn dump_registers_to_ram(&mut self, register: u8) -> Result<()> {
         let reg_slice = &self.v_reg[0..=register as usize];
         self.memory.write_slice(reg_slice, self.i_reg)?;
 
+        // Quirk leave I pointing past the last register written
+        if self.quirks.memory_increment {
+            self.i_reg = self.i_reg.wrapping_add(register as u16 + 1);
+        }
+
         Ok(())
     }
 
     /// Opcode FX65
     /// Load values from memory starting form address in I register
     /// into V0 through VX
-    fn load_registers_from_ram(&mut self, opcode: u16) {
-        let register = (opcode & 0x0F00) >> 8;
+    fn load_registers_from_ram(&mut self, register: u8) -> Result<()> {
         let address = self.i_reg;
-        let memory_slice = self.memory.read_slice(address, register + 1);
+        let memory_slice = self.memory.read_slice(address, register as u16 + 1)?;
         self.v_reg[..=register as usize].copy_from_slice(memory_slice);
+
+        // Quirk leave I pointing past the last register read
+        if self.quirks.memory_increment {
+            self.i_reg = self.i_reg.wrapping_add(register as u16 + 1);
+        }
+
+        Ok(())
+    }
+
+    /// Opcode FX75
+    /// Save V0 through VX to the flag registers
+    fn save_flag_registers(&mut self, register: u8) {
+        let register = self.flag_register_limit(register);
+        self.flag_reg[..=register].copy_from_slice(&self.v_reg[..=register]);
+    }
+
+    /// Opcode FX85
+    /// Load V0 through VX from the flag registers
+    fn load_flag_registers(&mut self, register: u8) {
+        let register = self.flag_register_limit(register);
+        self.v_reg[..=register].copy_from_slice(&self.flag_reg[..=register]);
+    }
+
+    /// Get X of FX75 and FX85 limited to the available flag registers
+    fn flag_register_limit(&self, register: u8) -> usize {
+        let register = register as usize;
+        if self.xo_chip() {
+            register
+        } else {
+            register.min(NUM_FLAG_REGS - 1)
+        }
+    }
+}
+
+/// Registers X through Y in ascending or descending order
+fn register_range(reg_x: u8, reg_y: u8) -> Box<dyn Iterator<Item = u8>> {
+    if reg_x <= reg_y {
+        Box::new(reg_x..=reg_y)
+    } else {
+        Box::new((reg_y..=reg_x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::watchpoint::{MemoryAccess, WatchKind};
+
+    fn processor(platform: Platform) -> Processor {
+        Processor::try_new(&[], platform, platform.default_quirks()).unwrap()
+    }
+
+    #[test]
+    fn decode_every_opcode_on_every_platform() {
+        for platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip] {
+            let processor = processor(platform);
+            let level = match platform {
+                Platform::Chip8 => Extension::Chip8,
+                Platform::SuperChip => Extension::SuperChip,
+                Platform::XoChip => Extension::XoChip,
+            };
+
+            for opcode in 0..=u16::MAX {
+                let decoded = processor.decode(opcode);
+                match Instruction::decode(opcode) {
+                    Some(Instruction::System(_)) => {
+                        assert!(matches!(decoded, Err(Error::InvalidOpcodeError(_))))
+                    }
+                    Some(instruction) if instruction.extension() <= level => {
+                        assert_eq!(decoded.unwrap(), instruction);
+                        assert_eq!(instruction.encode(), opcode);
+                    }
+                    _ => assert!(
+                        matches!(decoded, Err(Error::UnknownOpcodeError(o)) if o == opcode),
+                        "{opcode:04X} should not decode on {platform:?}"
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chip8_draws_nothing_for_zero_rows() {
+        let mut processor = processor(Platform::Chip8);
+        let mut display = Display::headless();
+        let mut input = KeyInput::new();
+        processor.memory.write_slice(&[0xD0, 0x10], START_ADDR).unwrap();
+
+        processor.cycle(&mut display, &mut input).unwrap();
+        assert_eq!(processor.v_reg[0xF], 0);
+        assert_eq!(display.snapshot().pixels.iter().filter(|&&p| p != 0).count(), 0);
+    }
+
+    #[test]
+    fn program_counter_stops_at_end_of_ram() {
+        let mut processor = processor(Platform::XoChip);
+        let mut display = Display::headless();
+        let mut input = KeyInput::new();
+        processor.pc = 0xFFFE;
+
+        assert!(matches!(
+            processor.cycle(&mut display, &mut input),
+            Err(Error::InvalidRamAddressError)
+        ));
+        assert_eq!(processor.pc, 0xFFFE);
+    }
+
+    #[test]
+    fn skips_stop_at_end_of_ram() {
+        let mut display = Display::headless();
+        let mut input = KeyInput::new();
+        // 3000 skips the next instruction and F000 NNNN reads past itself
+        for opcode in [[0x30, 0x00], [0xF0, 0x00]] {
+            let mut processor = processor(Platform::XoChip);
+            processor.memory.write_slice(&opcode, 0xFFFC).unwrap();
+            processor.pc = 0xFFFC;
+
+            assert!(matches!(
+                processor.cycle(&mut display, &mut input),
+                Err(Error::InvalidRamAddressError)
+            ));
+        }
+    }
+
+    #[test]
+    fn memory_instructions_stay_in_ram() {
+        let mut display = Display::headless();
+        let mut input = KeyInput::new();
+        // BCD, register load and register range load at the end of RAM
+        let cases = [
+            (Platform::Chip8, 0xFFE, [0xF0, 0x33]),
+            (Platform::Chip8, 0xFFF, [0xF1, 0x65]),
+            (Platform::XoChip, 0xFFFF, [0xF0, 0x33]),
+            (Platform::XoChip, 0xFFFF, [0xF1, 0x65]),
+            (Platform::XoChip, 0xFFFF, [0x50, 0x13]),
+        ];
+        for (platform, i_reg, opcode) in cases {
+            let mut processor = processor(platform);
+            processor.memory.write_slice(&opcode, START_ADDR).unwrap();
+            processor.i_reg = i_reg;
+
+            assert!(
+                matches!(
+                    processor.cycle(&mut display, &mut input),
+                    Err(Error::InvalidRamAddressError)
+                ),
+                "{:02X}{:02X} at {i_reg:X}",
+                opcode[0],
+                opcode[1]
+            );
+        }
+    }
+
+    #[test]
+    fn watch_hits_record_the_instruction() {
+        // Store 66 as BCD at 300, then V0 and V1 at 302
+        let rom = [0x60, 0x42, 0xA3, 0x00, 0xF0, 0x33, 0x61, 0x07, 0xA3, 0x02, 0xF1, 0x55];
+        let mut processor = Processor::try_new(&rom, Platform::Chip8, Quirks::default()).unwrap();
+        let mut display = Display::headless();
+        let mut input = KeyInput::new();
+        let bcd_watch = Watchpoint::new(0x301, 0x301, WatchKind::Write);
+        let store_watch = Watchpoint::new(0x303, 0x310, WatchKind::Change);
+        processor.add_watchpoint(bcd_watch);
+        processor.add_watchpoint(store_watch);
+
+        let mut hits = Vec::new();
+        for _ in 0..rom.len() / 2 {
+            processor.cycle(&mut display, &mut input).unwrap();
+            hits.extend(processor.watch_hit());
+        }
+
+        let access = |address, old_value, new_value| MemoryAccess {
+            address,
+            kind: WatchKind::Write,
+            old_value,
+            new_value,
+        };
+        let expected = [
+            WatchHit {
+                pc: 0x204,
+                opcode: 0xF033,
+                watchpoint: bcd_watch,
+                access: access(0x301, 0x00, 0x06),
+            },
+            WatchHit {
+                pc: 0x20A,
+                opcode: 0xF155,
+                watchpoint: store_watch,
+                access: access(0x303, 0x00, 0x07),
+            },
+        ];
+        assert_eq!(hits, expected);
+    }
+
+    /// Run a CHIP-8 ROM with the quirks for a number of instructions
+    fn run(rom: &[u8], quirks: Quirks, cycles: usize) -> (Processor, Display) {
+        run_on(Platform::Chip8, rom, quirks, cycles)
+    }
+
+    fn run_on(
+        platform: Platform,
+        rom: &[u8],
+        quirks: Quirks,
+        cycles: usize,
+    ) -> (Processor, Display) {
+        let mut processor = Processor::try_new(rom, platform, quirks).unwrap();
+        let mut display = Display::headless();
+        let mut input = KeyInput::new();
+        for _ in 0..cycles {
+            processor.cycle(&mut display, &mut input).unwrap();
+        }
+        (processor, display)
+    }
+
+    /// Default quirks with one of them turned on or off
+    fn quirk(on: bool, set: fn(&mut Quirks, bool)) -> Quirks {
+        let mut quirks = Quirks::default();
+        set(&mut quirks, on);
+        quirks
+    }
+
+    #[test]
+    fn shift_quirk_shifts_vx_in_place() {
+        // V0 = 1, V1 = 0x81, then V0 = V1 >> 1 or V0 >>= 1
+        let rom = [0x60, 0x01, 0x61, 0x81, 0x80, 0x16];
+        for (on, v0) in [(false, 0x40), (true, 0x00)] {
+            let (processor, _) = run(&rom, quirk(on, |q, on| q.shift = on), 3);
+            assert_eq!(processor.v_reg[0], v0, "shift {on}");
+            assert_eq!(processor.v_reg[0xF], 1, "shift {on}");
+        }
+    }
+
+    #[test]
+    fn memory_increment_quirk_moves_i() {
+        // Load V0 and V1 from 300
+        let rom = [0xA3, 0x00, 0xF1, 0x65];
+        for (on, i_reg) in [(false, 0x300), (true, 0x302)] {
+            let (processor, _) = run(&rom, quirk(on, |q, on| q.memory_increment = on), 2);
+            assert_eq!(processor.i_reg, i_reg, "memory increment {on}");
+        }
+    }
+
+    #[test]
+    fn vf_reset_quirk_clears_vf_after_logic() {
+        // VF = 5, then V0 |= V1
+        let rom = [0x6F, 0x05, 0x80, 0x11];
+        for (on, vf) in [(false, 5), (true, 0)] {
+            let (processor, _) = run(&rom, quirk(on, |q, on| q.vf_reset = on), 2);
+            assert_eq!(processor.v_reg[0xF], vf, "vf reset {on}");
+        }
+    }
+
+    #[test]
+    fn clipping_quirk_stops_sprites_at_the_edge() {
+        // Draw the row of 8 pixels at 208 from x = 60
+        let rom = [0x60, 0x3C, 0x61, 0x00, 0xA2, 0x08, 0xD0, 0x11, 0xFF];
+        for (on, wrapped) in [(false, 1), (true, 0)] {
+            let (_, display) = run(&rom, quirk(on, |q, on| q.clipping = on), 4);
+            assert_eq!(display.pixels()[63], 1, "clipping {on}");
+            assert_eq!(display.pixels()[0], wrapped, "clipping {on}");
+        }
+    }
+
+    #[test]
+    fn display_wait_quirk_draws_once_per_frame() {
+        // Draw the row of 8 pixels at 206 twice
+        let rom = [0xA2, 0x06, 0xD0, 0x11, 0xD0, 0x11, 0xFF];
+        let (_, display) = run(&rom, quirk(false, |q, on| q.display_wait = on), 3);
+        assert!(display.pixels().iter().all(|&pixel| pixel == 0));
+
+        // The second sprite waits for the vertical blank
+        let (mut processor, mut display) = run(&rom, quirk(true, |q, on| q.display_wait = on), 4);
+        assert_eq!(processor.pc, 0x204);
+        assert!(display.pixels().iter().any(|&pixel| pixel != 0));
+        processor.tick_timers();
+        processor.cycle(&mut display, &mut KeyInput::new()).unwrap();
+        assert_eq!(processor.pc, 0x206);
+        assert!(display.pixels().iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn jump_plus_uses_register_given_by_quirk() {
+        let mut processor = processor(Platform::Chip8);
+        processor.v_reg[0] = 0x10;
+        processor.v_reg[3] = 0x20;
+
+        processor.jump_plus(0x300);
+        assert_eq!(processor.pc, 0x310);
+
+        processor.quirks.jump_with_vx = true;
+        processor.jump_plus(0x300);
+        assert_eq!(processor.pc, 0x320);
+    }
+
+    /// Indices of the pixels turned on in any plane
+    fn lit(display: &Display) -> Vec<usize> {
+        let pixels = display.pixels().iter().enumerate();
+        pixels.filter(|(_, &pixel)| pixel != 0).map(|(i, _)| i).collect()
+    }
+
+    #[test]
+    fn super_chip_scrolls_the_screen() {
+        // Draw one pixel at (3, 0), scroll down 2, right, left and left again
+        let rom = [
+            0x60, 0x03, 0xA2, 0x0E, 0xD0, 0x11, 0x00, 0xC2, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC,
+            0x80,
+        ];
+        let quirks = Platform::SuperChip.default_quirks();
+        let (mut processor, mut display) = run_on(Platform::SuperChip, &rom, quirks, 3);
+        assert_eq!(lit(&display), [3]);
+
+        let mut input = KeyInput::new();
+        for expected in [vec![2 * 64 + 3], vec![2 * 64 + 7], vec![2 * 64 + 3], vec![]] {
+            processor.cycle(&mut display, &mut input).unwrap();
+            assert_eq!(lit(&display), expected, "PC {:#X}", processor.pc);
+        }
+    }
+
+    #[test]
+    fn super_chip_switches_resolution_and_draws_large_sprites() {
+        // Draw a row in low resolution, switch to high resolution, draw the
+        // 16x16 sprite at 210 three times, switch back and exit
+        let mut rom = vec![
+            0xA2, 0x10, 0xD0, 0x11, 0x00, 0xFF, 0xD0, 0x10, 0xD0, 0x10, 0xD0, 0x10, 0x00, 0xFE,
+            0x00, 0xFD,
+        ];
+        rom.extend([0xFF; 32]);
+        let quirks = Platform::SuperChip.default_quirks();
+        let (mut processor, mut display) = run_on(Platform::SuperChip, &rom, quirks, 2);
+        assert_eq!(lit(&display).len(), 8);
+        assert!(!display.hires());
+
+        let mut input = KeyInput::new();
+        processor.cycle(&mut display, &mut input).unwrap();
+        assert!(display.hires());
+        assert_eq!(display.width(), 128);
+        assert!(lit(&display).is_empty());
+
+        processor.cycle(&mut display, &mut input).unwrap();
+        let pixels = lit(&display);
+        assert_eq!(pixels.len(), 256);
+        assert!(pixels.contains(&(15 * 128 + 15)));
+        assert!(!pixels.contains(&16));
+        assert_eq!(processor.v_reg[0xF], 0);
+
+        // Drawing the sprite again collides and erases it
+        processor.cycle(&mut display, &mut input).unwrap();
+        assert!(lit(&display).is_empty());
+        assert_eq!(processor.v_reg[0xF], 1);
+
+        processor.cycle(&mut display, &mut input).unwrap();
+        assert_eq!(processor.v_reg[0xF], 0);
+        processor.cycle(&mut display, &mut input).unwrap();
+        assert!(!display.hires());
+        assert!(lit(&display).is_empty());
+
+        assert!(!processor.halted());
+        processor.cycle(&mut display, &mut input).unwrap();
+        assert!(processor.halted());
+    }
+
+    #[test]
+    fn large_font_characters_are_ten_bytes() {
+        // I = address of the large font character in V0
+        let rom = [0x60, 0x0A, 0xF0, 0x30];
+        let quirks = Platform::SuperChip.default_quirks();
+        let (processor, _) = run_on(Platform::SuperChip, &rom, quirks, 2);
+        assert_eq!(processor.i_reg, BIG_FONTSET_ADDR + 100);
+    }
+
+    #[test]
+    fn flag_registers_keep_registers() {
+        // Save V0 to V2, clear them and load them back
+        let rom = [
+            0x60, 0x11, 0x61, 0x22, 0x62, 0x33, 0xF2, 0x75, 0x60, 0x00, 0x61, 0x00, 0x62, 0x00,
+            0xF1, 0x85,
+        ];
+        let quirks = Platform::SuperChip.default_quirks();
+        let (processor, _) = run_on(Platform::SuperChip, &rom, quirks, 8);
+        assert_eq!(processor.v_reg[..3], [0x11, 0x22, 0x00]);
+        assert_eq!(processor.flag_reg[..3], [0x11, 0x22, 0x33]);
     }
 }
@@ -0,0 +1,134 @@
+use std::str::FromStr;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+use crate::errors::Error;
+
+/// Behaviours that differ between CHIP-8 interpreters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place instead of copying VY into VX first
+    pub shift: bool,
+    /// FX55/FX65 leave I pointing past the last register accessed
+    pub memory_increment: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_with_vx: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to zero
+    pub vf_reset: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around
+    pub clipping: bool,
+    /// DXYN waits for the next vertical blank before drawing
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// Quirks as bit flags, as stored in save states and movies
+    pub(crate) fn bits(&self) -> u8 {
+        [
+            self.shift,
+            self.memory_increment,
+            self.jump_with_vx,
+            self.vf_reset,
+            self.clipping,
+            self.display_wait,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, quirk)| bits | ((*quirk as u8) << i))
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Self {
+        let quirk = |i: u8| (bits >> i) & 0x1 != 0;
+        Self {
+            shift: quirk(0),
+            memory_increment: quirk(1),
+            jump_with_vx: quirk(2),
+            vf_reset: quirk(3),
+            clipping: quirk(4),
+            display_wait: quirk(5),
+        }
+    }
+}
+
+impl Default for Quirks {
+    /// The behaviour of this emulator before quirks were configurable
+    fn default() -> Self {
+        Self {
+            shift: false,
+            memory_increment: false,
+            jump_with_vx: false,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+}
+
+/// Named quirk profiles matching well known interpreters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuirkPreset {
+    CosmacVip,
+    Chip48,
+    SuperChip11,
+    XoChip,
+}
+
+impl QuirkPreset {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            QuirkPreset::CosmacVip => Quirks {
+                shift: false,
+                memory_increment: true,
+                jump_with_vx: false,
+                vf_reset: true,
+                clipping: true,
+                display_wait: true,
+            },
+            QuirkPreset::Chip48 => Quirks {
+                shift: true,
+                memory_increment: false,
+                jump_with_vx: true,
+                vf_reset: false,
+                clipping: true,
+                display_wait: false,
+            },
+            QuirkPreset::SuperChip11 => Quirks {
+                shift: true,
+                memory_increment: false,
+                jump_with_vx: true,
+                vf_reset: false,
+                clipping: true,
+                display_wait: false,
+            },
+            QuirkPreset::XoChip => Quirks {
+                shift: false,
+                memory_increment: true,
+                jump_with_vx: false,
+                vf_reset: false,
+                clipping: false,
+                display_wait: false,
+            },
+        }
+    }
+}
+
+impl FromStr for QuirkPreset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac-vip" | "chip8" => Ok(QuirkPreset::CosmacVip),
+            "chip48" | "chip-48" => Ok(QuirkPreset::Chip48),
+            "schip" | "superchip" | "super-chip" | "schip11" => Ok(QuirkPreset::SuperChip11),
+            "xochip" | "xo-chip" => Ok(QuirkPreset::XoChip),
+            _ => Err(Error::UnknownQuirkPresetError(s.into())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for QuirkPreset {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
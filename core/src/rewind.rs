@@ -0,0 +1,176 @@
+use std::collections::VecDeque;
+
+use crate::display::Display;
+use crate::errors::Result;
+use crate::processor::Processor;
+use crate::save_state::{DisplayState, ProcessorState};
+
+/// Machine state at the end of a frame. RAM and pixels are stored as the
+/// bytes needed to undo the changes made during the frame
+struct RewindFrame {
+    processor: ProcessorState,
+    hires: bool,
+    selected_planes: u8,
+    // Address and previous value of each byte changed during the frame
+    ram_undo: Vec<(u16, u8)>,
+    // Index and previous value of each pixel changed during the frame
+    pixel_undo: Vec<(u16, u8)>,
+}
+
+/// Ring buffer of per-frame snapshots used to step backwards in time
+pub struct RewindBuffer {
+    frames: VecDeque<RewindFrame>,
+    capacity: usize,
+    // Pixels at the end of the most recent frame
+    pixels: Vec<u8>,
+}
+
+impl RewindBuffer {
+    /// Create buffer holding at most the given number of frames
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+            pixels: Vec::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Forget all frames, e.g. after RAM was replaced by loading a state
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.pixels.clear();
+    }
+
+    /// Record the state at the end of a frame. RAM changes are taken from
+    /// the processor's undo log. The oldest frame is dropped when the
+    /// buffer is full
+    pub fn record(&mut self, processor: &mut Processor, display: &Display) {
+        if !self.enabled() {
+            return;
+        }
+
+        // Only the value before the first write of each byte is needed
+        let mut ram_undo = processor.take_undo_log();
+        ram_undo.sort_by_key(|(address, _)| *address);
+        ram_undo.dedup_by_key(|(address, _)| *address);
+
+        let pixels = display.pixels();
+        let mut pixel_undo = Vec::new();
+        if self.pixels.len() == pixels.len() {
+            for (index, (old, new)) in self.pixels.iter_mut().zip(pixels).enumerate() {
+                if old != new {
+                    pixel_undo.push((index as u16, *old));
+                    *old = *new;
+                }
+            }
+        } else {
+            // Nothing to undo to before the first frame
+            self.pixels = pixels.to_vec();
+        }
+
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(RewindFrame {
+            processor: processor.snapshot(),
+            hires: display.hires(),
+            selected_planes: display.selected_planes(),
+            ram_undo,
+            pixel_undo,
+        });
+    }
+
+    /// Step the machine one frame back. Returns false when the oldest
+    /// recorded frame has been reached
+    pub fn rewind(&mut self, processor: &mut Processor, display: &mut Display) -> Result<bool> {
+        if self.frames.len() < 2 {
+            return Ok(false);
+        }
+        let previous = &self.frames[self.frames.len() - 2];
+        processor.restore(&previous.processor)?;
+        let (hires, selected_planes) = (previous.hires, previous.selected_planes);
+
+        let Some(frame) = self.frames.pop_back() else {
+            return Ok(false);
+        };
+        // Writes made after the frame was recorded, e.g. by the debugger,
+        // are undone first
+        let pending = processor.take_undo_log();
+        processor.undo_ram_writes(&pending);
+        processor.undo_ram_writes(&frame.ram_undo);
+        for (index, value) in frame.pixel_undo {
+            self.pixels[index as usize] = value;
+        }
+
+        display.restore(&DisplayState {
+            pixels: self.pixels.clone(),
+            hires,
+            selected_planes,
+        });
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_input::{KeyInput, NUM_KEYS};
+    use crate::platform::Platform;
+    use crate::save_state::MachineState;
+
+    // Count in V0, store it as BCD at 0x300, draw its digits at x = V0
+    // and get a random number in V1
+    const ROM: [u8; 12] = [
+        0x70, 0x01, 0xA3, 0x00, 0xF0, 0x33, 0xD0, 0x15, 0xC1, 0xFF, 0x12, 0x00,
+    ];
+
+    fn state(processor: &Processor, display: &Display) -> MachineState {
+        MachineState {
+            processor: processor.snapshot(),
+            ram: processor.ram().to_vec(),
+            display: display.snapshot(),
+            keys: [false; NUM_KEYS],
+        }
+    }
+
+    fn run_frame(processor: &mut Processor, display: &mut Display) {
+        // One pass through the loop per frame
+        let mut input = KeyInput::new();
+        for _ in 0..ROM.len() / 2 {
+            processor.cycle(display, &mut input).unwrap();
+        }
+    }
+
+    #[test]
+    fn rewinding_restores_earlier_frames() {
+        let platform = Platform::Chip8;
+        let mut processor = Processor::try_new(&ROM, platform, platform.default_quirks()).unwrap();
+        let mut display = Display::headless();
+        processor.start_undo_log();
+        let mut buffer = RewindBuffer::new(8);
+
+        let mut states = Vec::new();
+        for _ in 0..12 {
+            run_frame(&mut processor, &mut display);
+            buffer.record(&mut processor, &display);
+            states.push(state(&processor, &display));
+        }
+
+        for frames_back in 1..8 {
+            assert!(buffer.rewind(&mut processor, &mut display).unwrap());
+            assert_eq!(state(&processor, &display), states[11 - frames_back]);
+        }
+        assert!(!buffer.rewind(&mut processor, &mut display).unwrap());
+        assert_eq!(state(&processor, &display), states[4]);
+
+        // Playing forward again gives the same random numbers
+        for expected in &states[5..] {
+            run_frame(&mut processor, &mut display);
+            assert_eq!(&state(&processor, &display), expected);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt::Debug;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::errors::{Error, Result};
+
+// Offset and size of the interpreter page used by the COSMAC VIP random routine
+const VIP_PAGE_ADDR: usize = 0x100;
+const VIP_PAGE_SIZE: usize = 0x100;
+
+/// Position of a random source in its sequence, saved with the machine
+/// so that CXNN gives the same numbers after restoring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RngState {
+    Seeded {
+        seed: [u8; 32],
+        stream: u64,
+        word_pos: u128,
+    },
+    Vip {
+        r9: u16,
+    },
+}
+
+/// Source of the random numbers used by CXNN
+pub trait RandomSource: Debug {
+    fn random_byte(&mut self) -> u8;
+
+    fn state(&self) -> RngState;
+
+    /// Continue from the state. Fails if it belongs to another kind of source
+    fn restore(&mut self, state: &RngState) -> Result<()>;
+}
+
+fn rng_mismatch() -> Error {
+    Error::InvalidSaveStateError("Random number generator does not match".into())
+}
+
+/// Pseudo random generator producing the same sequence for the same seed
+#[derive(Debug)]
+pub struct SeededRng {
+    rng: ChaCha8Rng,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl RandomSource for SeededRng {
+    fn random_byte(&mut self) -> u8 {
+        self.rng.random()
+    }
+
+    fn state(&self) -> RngState {
+        RngState::Seeded {
+            seed: self.rng.get_seed(),
+            stream: self.rng.get_stream(),
+            word_pos: self.rng.get_word_pos(),
+        }
+    }
+
+    fn restore(&mut self, state: &RngState) -> Result<()> {
+        let RngState::Seeded {
+            seed,
+            stream,
+            word_pos,
+        } = *state
+        else {
+            return Err(rng_mismatch());
+        };
+        self.rng = ChaCha8Rng::from_seed(seed);
+        self.rng.set_stream(stream);
+        self.rng.set_word_pos(word_pos);
+        Ok(())
+    }
+}
+
+/// Random routine of the original COSMAC VIP interpreter. The VIP adds
+/// a byte of its own interpreter code, indexed by the low byte of R9, to
+/// the high byte of R9 and keeps the sum as the next high byte
+#[derive(Debug)]
+pub struct VipRng {
+    // Interpreter code at 0x100-0x1FF
+    page: [u8; VIP_PAGE_SIZE],
+    r9: u16,
+}
+
+impl VipRng {
+    /// Create from an image of the 512 byte CHIP-8 interpreter
+    /// with R9 starting at the given value
+    pub fn try_new(interpreter: &[u8], r9: u16) -> Result<Self> {
+        let page = interpreter
+            .get(VIP_PAGE_ADDR..VIP_PAGE_ADDR + VIP_PAGE_SIZE)
+            .ok_or(Error::InvalidInterpreterImageError)?;
+
+        let mut rng = Self {
+            page: [0; VIP_PAGE_SIZE],
+            r9,
+        };
+        rng.page.copy_from_slice(page);
+        Ok(rng)
+    }
+}
+
+impl RandomSource for VipRng {
+    fn random_byte(&mut self) -> u8 {
+        self.r9 = self.r9.wrapping_add(1);
+        let [high, low] = self.r9.to_be_bytes();
+        let random = high.wrapping_add(self.page[low as usize]);
+        self.r9 = u16::from_be_bytes([random, low]);
+        random
+    }
+
+    fn state(&self) -> RngState {
+        RngState::Vip { r9: self.r9 }
+    }
+
+    fn restore(&mut self, state: &RngState) -> Result<()> {
+        let RngState::Vip { r9 } = *state else {
+            return Err(rng_mismatch());
+        };
+        self.r9 = r9;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(rng: &mut dyn RandomSource, count: usize) -> Vec<u8> {
+        (0..count).map(|_| rng.random_byte()).collect()
+    }
+
+    #[test]
+    fn seeded_rng_gives_fixed_sequence() {
+        let mut rng = SeededRng::new(1234);
+        assert_eq!(bytes(&mut rng, 8), [157, 82, 86, 45, 29, 62, 72, 43]);
+    }
+
+    #[test]
+    fn vip_rng_follows_vip_routine() {
+        // With each byte of the page holding its own address, every number
+        // is the previous one plus the incremented low byte of R9
+        let interpreter: Vec<u8> = (0..0x200).map(|address| address as u8).collect();
+        let mut rng = VipRng::try_new(&interpreter, 0x0000).unwrap();
+
+        let expected: Vec<u8> = (1..=255u32).map(|n| (n * (n + 1) / 2) as u8).collect();
+        assert_eq!(bytes(&mut rng, 255), expected);
+        // Incrementing R9 past the end of the page carries into the high byte
+        assert_eq!(rng.random_byte(), expected[254] + 1);
+        assert!(VipRng::try_new(&interpreter[..0x1FF], 0).is_err());
+    }
+
+    #[test]
+    fn restored_rng_continues_sequence() {
+        let interpreter = [0x5A; 0x200];
+        let sources: [Box<dyn RandomSource>; 2] = [
+            Box::new(SeededRng::new(99)),
+            Box::new(VipRng::try_new(&interpreter, 0x1234).unwrap()),
+        ];
+        for mut rng in sources {
+            bytes(rng.as_mut(), 13);
+            let state = rng.state();
+            let expected = bytes(rng.as_mut(), 20);
+
+            let mut restored: Box<dyn RandomSource> = match state {
+                RngState::Seeded { .. } => Box::new(SeededRng::new(0)),
+                RngState::Vip { .. } => Box::new(VipRng::try_new(&interpreter, 0).unwrap()),
+            };
+            restored.restore(&state).unwrap();
+            assert_eq!(bytes(restored.as_mut(), 20), expected);
+        }
+        assert!(SeededRng::new(0).restore(&RngState::Vip { r9: 0 }).is_err());
+    }
+}
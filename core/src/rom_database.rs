@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::{fs, io};
+
+use serde::Deserialize;
+
+use crate::errors::{Error, Result};
+use crate::helpers::{config_path, ROM_HASH_SIZE};
+use crate::key_input::deserialize_keys;
+use crate::keymap::KeyBindings;
+use crate::palette::deserialize_colors;
+use crate::platform::Platform;
+use crate::quirks::QuirkPreset;
+
+// Database shipped with the emulator, in the format of the community CHIP-8
+// database's programs.json
+const BUILTIN_PROGRAMS: &str = include_str!("../data/programs.json");
+// Copy of programs.json from the community CHIP-8 database, inside the
+// configuration directory
+const PROGRAMS_FILE: &str = "programs.json";
+// User database inside the configuration directory
+const USER_DATABASE_FILE: &str = "roms.json";
+// Platforms of the community database run by this emulator
+const DATABASE_PLATFORMS: [(&str, Platform, Option<QuirkPreset>); 7] = [
+    ("originalChip8", Platform::Chip8, Some(QuirkPreset::CosmacVip)),
+    ("hybridVIP", Platform::Chip8, Some(QuirkPreset::CosmacVip)),
+    ("modernChip8", Platform::Chip8, None),
+    ("chip48", Platform::SuperChip, Some(QuirkPreset::Chip48)),
+    ("superchip1", Platform::SuperChip, None),
+    ("superchip", Platform::SuperChip, None),
+    ("xochip", Platform::XoChip, None),
+];
+
+/// Details and settings known for a ROM. Missing values are left to the
+/// emulator configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RomInfo {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<QuirkPreset>,
+    /// Instructions executed per frame
+    pub tickrate: Option<u32>,
+    /// RGB colour of each pixel value, starting with the background
+    #[serde(deserialize_with = "deserialize_colors")]
+    pub colors: Vec<[u8; 3]>,
+    /// Key used for each action in the game, e.g. "up" for key 5
+    #[serde(deserialize_with = "deserialize_keys")]
+    pub keys: BTreeMap<String, u8>,
+    /// Changes to the keymap for this ROM
+    pub keymap: KeyBindings,
+}
+
+impl RomInfo {
+    /// Replace the values that are set in the other entry
+    fn merge(&mut self, other: RomInfo) {
+        self.title = other.title.or(self.title.take());
+        self.platform = other.platform.or(self.platform);
+        self.quirks = other.quirks.or(self.quirks);
+        self.tickrate = other.tickrate.or(self.tickrate);
+        if !other.authors.is_empty() {
+            self.authors = other.authors;
+        }
+        if !other.colors.is_empty() {
+            self.colors = other.colors;
+        }
+        if !other.keys.is_empty() {
+            self.keys = other.keys;
+        }
+        if other.keymap != KeyBindings::default() {
+            self.keymap = other.keymap;
+        }
+    }
+}
+
+/// Program of the community database with the ROMs released for it
+#[derive(Deserialize)]
+struct DatabaseProgram {
+    title: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    roms: BTreeMap<String, DatabaseRom>,
+}
+
+#[derive(Deserialize)]
+struct DatabaseRom {
+    // Platforms the ROM runs on, the preferred one first
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u32>,
+    #[serde(default)]
+    colors: DatabaseColors,
+    #[serde(default, deserialize_with = "deserialize_keys")]
+    keys: BTreeMap<String, u8>,
+}
+
+#[derive(Default, Deserialize)]
+struct DatabaseColors {
+    #[serde(default, deserialize_with = "deserialize_colors")]
+    pixels: Vec<[u8; 3]>,
+}
+
+/// ROM details by SHA-1 hash of the ROM. The database is a JSON object
+/// with lowercase hexadecimal hashes as names, e.g.
+/// `{"0a1b...": {"title": "Pong", "platform": "chip8", "tickrate": 15}}`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RomDatabase {
+    entries: BTreeMap<String, RomInfo>,
+}
+
+impl RomDatabase {
+    /// Database shipped with the emulator, extended by the community
+    /// database's programs.json if copied to the configuration directory.
+    /// Entries from the user's database file replace their values
+    pub fn load() -> Result<Self> {
+        let mut database = Self::parse_programs(BUILTIN_PROGRAMS)?;
+        if let Some(text) = read_config_file(PROGRAMS_FILE)? {
+            database.extend(Self::parse_programs(&text)?);
+        }
+        if let Some(text) = read_config_file(USER_DATABASE_FILE)? {
+            database.extend(Self::parse(&text)?);
+        }
+        Ok(database)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let entries: BTreeMap<String, RomInfo> =
+            serde_json::from_str(text).map_err(|e| Error::RomDatabaseError(e.to_string()))?;
+        let entries = entries
+            .into_iter()
+            .map(|(hash, info)| {
+                if info.tickrate == Some(0) {
+                    return Err(Error::RomDatabaseError(format!("{hash}: Invalid tickrate")));
+                }
+                Ok((hash.to_ascii_lowercase(), info))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { entries })
+    }
+
+    /// Database in the format of the community database's programs.json.
+    /// ROMs only made for platforms this emulator lacks get no platform
+    pub fn parse_programs(text: &str) -> Result<Self> {
+        let programs: Vec<DatabaseProgram> =
+            serde_json::from_str(text).map_err(|e| Error::RomDatabaseError(e.to_string()))?;
+        let mut entries = BTreeMap::new();
+        for program in programs {
+            for (hash, rom) in program.roms {
+                let platform = rom.platforms.iter().find_map(|id| {
+                    DATABASE_PLATFORMS
+                        .iter()
+                        .find(|(name, ..)| name == id)
+                        .map(|&(_, platform, quirks)| (platform, quirks))
+                });
+                let info = RomInfo {
+                    title: program.title.clone(),
+                    authors: program.authors.clone(),
+                    platform: platform.map(|(platform, _)| platform),
+                    quirks: platform.and_then(|(_, quirks)| quirks),
+                    tickrate: rom.tickrate.filter(|&tickrate| tickrate > 0),
+                    colors: rom.colors.pixels,
+                    keys: rom.keys,
+                    keymap: KeyBindings::default(),
+                };
+                entries.insert(hash.to_ascii_lowercase(), info);
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn lookup(&self, rom_hash: &[u8; ROM_HASH_SIZE]) -> Option<&RomInfo> {
+        let hex: String = rom_hash.iter().map(|byte| format!("{byte:02x}")).collect();
+        self.entries.get(&hex)
+    }
+
+    /// Add the other database's entries, replacing values of known ROMs
+    pub fn extend(&mut self, other: RomDatabase) {
+        for (hash, info) in other.entries {
+            self.entries.entry(hash).or_default().merge(info);
+        }
+    }
+}
+
+/// Location of the user's database file in the configuration directory
+pub fn user_database_path() -> Option<PathBuf> {
+    config_path(USER_DATABASE_FILE)
+}
+
+fn read_config_file(name: &str) -> Result<Option<String>> {
+    let Some(path) = config_path(name) else {
+        return Ok(None);
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::RomDatabaseError(format!("{}: {e}", path.display()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::rom_hash;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn hash_bytes() -> [u8; ROM_HASH_SIZE] {
+        let mut bytes = [0; ROM_HASH_SIZE];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&HASH[2 * i..2 * i + 2], 16).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn builtin_database_parses() {
+        let database = RomDatabase::parse_programs(BUILTIN_PROGRAMS).unwrap();
+        let maze = [
+            0xA2, 0x1E, 0xC2, 0x01, 0x32, 0x01, 0xA2, 0x1A, 0xD0, 0x14, 0x70, 0x04, 0x30, 0x40,
+            0x12, 0x00, 0x60, 0x00, 0x71, 0x04, 0x31, 0x20, 0x12, 0x00, 0x12, 0x18, 0x80, 0x40,
+            0x20, 0x10, 0x20, 0x40, 0x80, 0x10,
+        ];
+        let info = database.lookup(&rom_hash(&maze)).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Maze"));
+        assert_eq!(info.platform, Some(Platform::Chip8));
+    }
+
+    #[test]
+    fn community_programs_parse() {
+        let database = RomDatabase::parse_programs(&format!(
+            r##"[{{
+                "title": "Game",
+                "authors": ["Someone"],
+                "release": "1991",
+                "roms": {{
+                    "{HASH}": {{
+                        "file": "game.ch8",
+                        "platforms": ["megachip8", "superchip", "xochip"],
+                        "tickrate": 30,
+                        "keys": {{"left": 7, "right": 9}},
+                        "colors": {{"pixels": ["#000000", "#ffaa00"], "buzzer": "#ff0000"}}
+                    }}
+                }}
+            }}]"##
+        ))
+        .unwrap();
+
+        let info = database.lookup(&hash_bytes()).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Game"));
+        assert_eq!(info.authors, ["Someone"]);
+        assert_eq!(info.platform, Some(Platform::SuperChip));
+        assert_eq!(info.quirks, None);
+        assert_eq!(info.tickrate, Some(30));
+        assert_eq!(info.colors, [[0, 0, 0], [0xFF, 0xAA, 0]]);
+        assert_eq!(info.keys, BTreeMap::from([("left".into(), 7), ("right".into(), 9)]));
+
+        let vip = format!(r#"[{{"roms": {{"{HASH}": {{"platforms": ["originalChip8"]}}}}}}]"#);
+        let database = RomDatabase::parse_programs(&vip).unwrap();
+        let info = database.lookup(&hash_bytes()).unwrap();
+        assert_eq!(info.quirks, Some(QuirkPreset::CosmacVip));
+    }
+
+    #[test]
+    fn user_entries_replace_values() {
+        let mut database = RomDatabase::parse(&format!(
+            r##"{{"{HASH}": {{
+                "title": "Game \"One\"",
+                "authors": ["Someone"],
+                "platform": "schip",
+                "tickrate": 30,
+                "colors": ["#000000", "#FFaa00"],
+                "keys": {{"left": 7, "right": 9}},
+                "keymap": {{"keys": {{"Up": 1, "Down": 4}}}},
+                "release": "1991"
+            }}}}"##
+        ))
+        .unwrap();
+        let user = HASH.to_uppercase();
+        let user = format!(r#"{{"{user}": {{"tickrate": 100, "quirks": "vip"}}}}"#);
+        database.extend(RomDatabase::parse(&user).unwrap());
+
+        let info = database.lookup(&hash_bytes()).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Game \"One\""));
+        assert_eq!(info.authors, ["Someone"]);
+        assert_eq!(info.platform, Some(Platform::SuperChip));
+        assert_eq!(info.quirks, Some(QuirkPreset::CosmacVip));
+        assert_eq!(info.tickrate, Some(100));
+        assert_eq!(info.colors, [[0, 0, 0], [0xFF, 0xAA, 0]]);
+        assert_eq!(info.keys, BTreeMap::from([("left".into(), 7), ("right".into(), 9)]));
+        assert_eq!(info.keymap.keys, BTreeMap::from([("Up".into(), 1), ("Down".into(), 4)]));
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        for entry in [
+            r#"{"tickrate": 0}"#,
+            r#"{"colors": ["red"]}"#,
+            r#"{"keys": {"up": 16}}"#,
+            r#"{"platform": "nes"}"#,
+            r#"{"title": 1}"#,
+            r#"{"keymap": {"layout": "bépo"}}"#,
+        ] {
+            let text = format!(r#"{{"{HASH}": {entry}}}"#);
+            assert!(RomDatabase::parse(&text).is_err(), "{entry}");
+        }
+        assert!(RomDatabase::parse(r#"{"a": {}"#).is_err());
+    }
+}
@@ -0,0 +1,312 @@
+use std::fs;
+
+use crate::audio_output::AUDIO_PATTERN_SIZE;
+use crate::binary::{BinaryReader, BinaryWriter};
+use crate::display::BUFFER_SIZE;
+use crate::errors::{Error, Result};
+use crate::helpers::ROM_HASH_SIZE;
+use crate::key_input::NUM_KEYS;
+use crate::platform::Platform;
+use crate::processor::NUM_REGS;
+use crate::quirks::Quirks;
+use crate::rng::RngState;
+use crate::stack::STACK_SIZE;
+
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+pub const STATE_VERSION: u16 = 1;
+
+/// Processor registers, timers and stack, and the machine they run on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessorState {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub pc: u16,
+    pub v_reg: [u8; NUM_REGS],
+    pub i_reg: u16,
+    pub stack: [u16; STACK_SIZE],
+    pub sp: u16,
+    pub st: u8,
+    pub dt: u8,
+    pub flag_reg: [u8; NUM_REGS],
+    pub audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pub pitch: u8,
+    pub vblank_wait: bool,
+    pub halted: bool,
+    pub rng: RngState,
+}
+
+/// Display pixel buffer and mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayState {
+    pub pixels: Vec<u8>,
+    pub hires: bool,
+    pub selected_planes: u8,
+}
+
+/// Snapshot of the whole machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineState {
+    pub processor: ProcessorState,
+    pub ram: Vec<u8>,
+    pub display: DisplayState,
+    pub keys: [bool; NUM_KEYS],
+}
+
+impl MachineState {
+    /// Write state to file. The file header identifies the ROM by its hash
+    pub fn save(&self, path: &str, rom_hash: &[u8; ROM_HASH_SIZE]) -> Result<()> {
+        fs::write(path, self.to_bytes(rom_hash))
+            .map_err(|e| Error::SaveStateFileError(e.to_string()))
+    }
+
+    /// Read state from file. Fails if the state was saved with another ROM
+    /// or does not describe a valid machine
+    pub fn load(path: &str, rom_hash: &[u8; ROM_HASH_SIZE]) -> Result<Self> {
+        let bytes = fs::read(path).map_err(|e| Error::SaveStateFileError(e.to_string()))?;
+        Self::from_bytes(&bytes, rom_hash)
+    }
+
+    pub fn to_bytes(&self, rom_hash: &[u8; ROM_HASH_SIZE]) -> Vec<u8> {
+        let mut writer = BinaryWriter::new();
+
+        // Header
+        writer.bytes(STATE_MAGIC);
+        writer.u16(STATE_VERSION);
+        writer.bytes(rom_hash);
+
+        // Processor
+        let processor = &self.processor;
+        writer.u8(processor.platform.id());
+        writer.u8(processor.quirks.bits());
+        writer.u16(processor.pc);
+        writer.bytes(&processor.v_reg);
+        writer.u16(processor.i_reg);
+        for address in processor.stack {
+            writer.u16(address);
+        }
+        writer.u16(processor.sp);
+        writer.u8(processor.st);
+        writer.u8(processor.dt);
+        writer.bytes(&processor.flag_reg);
+        writer.bool(processor.audio_pattern.is_some());
+        writer.bytes(&processor.audio_pattern.unwrap_or_default());
+        writer.u8(processor.pitch);
+        writer.bool(processor.vblank_wait);
+        writer.bool(processor.halted);
+        match processor.rng {
+            RngState::Seeded {
+                seed,
+                stream,
+                word_pos,
+            } => {
+                writer.u8(0);
+                writer.bytes(&seed);
+                writer.u64(stream);
+                writer.u128(word_pos);
+            }
+            RngState::Vip { r9 } => {
+                writer.u8(1);
+                writer.u16(r9);
+            }
+        }
+
+        // Memory
+        writer.u32(self.ram.len() as u32);
+        writer.bytes(&self.ram);
+
+        // Display
+        writer.u32(self.display.pixels.len() as u32);
+        writer.bytes(&self.display.pixels);
+        writer.bool(self.display.hires);
+        writer.u8(self.display.selected_planes);
+
+        // Input
+        for key in self.keys {
+            writer.bool(key);
+        }
+
+        writer.into_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8], rom_hash: &[u8; ROM_HASH_SIZE]) -> Result<Self> {
+        let mut reader = BinaryReader::new(bytes, Error::InvalidSaveStateError);
+
+        // Header
+        if reader.bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(Error::InvalidSaveStateError("Not a save state file".into()));
+        }
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(Error::InvalidSaveStateError(format!(
+                "Unsupported version {version}"
+            )));
+        }
+        if reader.bytes(ROM_HASH_SIZE)? != rom_hash {
+            return Err(Error::SaveStateRomMismatchError);
+        }
+
+        // Processor
+        let platform = Platform::from_id(reader.u8()?)
+            .ok_or_else(|| Error::InvalidSaveStateError("Unknown platform".into()))?;
+        let quirks = Quirks::from_bits(reader.u8()?);
+        let pc = reader.u16()?;
+        let v_reg = reader.array()?;
+        let i_reg = reader.u16()?;
+        let mut stack = [0; STACK_SIZE];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let sp = reader.u16()?;
+        let st = reader.u8()?;
+        let dt = reader.u8()?;
+        let flag_reg = reader.array()?;
+        let has_pattern = reader.bool()?;
+        let pattern = reader.array()?;
+        let processor = ProcessorState {
+            platform,
+            quirks,
+            pc,
+            v_reg,
+            i_reg,
+            stack,
+            sp,
+            st,
+            dt,
+            flag_reg,
+            audio_pattern: has_pattern.then_some(pattern),
+            pitch: reader.u8()?,
+            vblank_wait: reader.bool()?,
+            halted: reader.bool()?,
+            rng: match reader.u8()? {
+                0 => RngState::Seeded {
+                    seed: reader.array()?,
+                    stream: reader.u64()?,
+                    word_pos: reader.u128()?,
+                },
+                1 => RngState::Vip { r9: reader.u16()? },
+                _ => return Err(reader.error("Unknown random number generator")),
+            },
+        };
+
+        // Memory
+        let ram_size = reader.u32()? as usize;
+        let ram = reader.bytes(ram_size)?.to_vec();
+
+        // Display
+        let pixel_count = reader.u32()? as usize;
+        let display = DisplayState {
+            pixels: reader.bytes(pixel_count)?.to_vec(),
+            hires: reader.bool()?,
+            selected_planes: reader.u8()?,
+        };
+
+        // Input
+        let mut keys = [false; NUM_KEYS];
+        for key in keys.iter_mut() {
+            *key = reader.bool()?;
+        }
+        reader.finish()?;
+
+        let state = Self {
+            processor,
+            ram,
+            display,
+            keys,
+        };
+        state.validate()?;
+        Ok(state)
+    }
+
+    /// Check that the state can be restored, so that restoring never stops
+    /// halfway through
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(Error::InvalidSaveStateError(message.into()));
+        if self.processor.sp as usize > STACK_SIZE {
+            return invalid("Invalid stack pointer");
+        }
+        if self.ram.len() != self.processor.platform.ram_size() {
+            return invalid("RAM size does not match platform");
+        }
+        if self.display.pixels.len() != BUFFER_SIZE {
+            return invalid("Invalid display size");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::Display;
+    use crate::processor::Processor;
+
+    const ROM_HASH: [u8; ROM_HASH_SIZE] = [0xAB; ROM_HASH_SIZE];
+
+    fn state(platform: Platform) -> MachineState {
+        let rom = [0x60, 0x2A, 0xA2, 0x00, 0xD0, 0x05];
+        let processor = Processor::try_new(&rom, platform, platform.default_quirks()).unwrap();
+        let mut display = Display::headless();
+        display.draw(&[0xF0, 0x90, 0xF0], 3, 4, true);
+        let mut keys = [false; NUM_KEYS];
+        keys[0x5] = true;
+
+        MachineState {
+            processor: processor.snapshot(),
+            ram: processor.ram().to_vec(),
+            display: display.snapshot(),
+            keys,
+        }
+    }
+
+    #[test]
+    fn saved_state_loads_unchanged() {
+        let dir = std::env::temp_dir().join(format!("save-state-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rom.state1").to_string_lossy().into_owned();
+
+        for platform in [Platform::Chip8, Platform::XoChip] {
+            let state = state(platform);
+            state.save(&path, &ROM_HASH).unwrap();
+            assert_eq!(MachineState::load(&path, &ROM_HASH).unwrap(), state);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn state_of_another_rom_is_refused() {
+        let bytes = state(Platform::Chip8).to_bytes(&ROM_HASH);
+        assert!(matches!(
+            MachineState::from_bytes(&bytes, &[0; ROM_HASH_SIZE]),
+            Err(Error::SaveStateRomMismatchError)
+        ));
+    }
+
+    #[test]
+    fn trailing_bytes_are_refused() {
+        let mut bytes = state(Platform::Chip8).to_bytes(&ROM_HASH);
+        bytes.push(0);
+        assert!(matches!(
+            MachineState::from_bytes(&bytes, &ROM_HASH),
+            Err(Error::InvalidSaveStateError(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_states_are_refused() {
+        let mut wrong_platform = state(Platform::Chip8);
+        wrong_platform.processor.platform = Platform::XoChip;
+        let mut short_display = state(Platform::Chip8);
+        short_display.display.pixels.pop();
+        let mut stack_overflow = state(Platform::Chip8);
+        stack_overflow.processor.sp = STACK_SIZE as u16 + 1;
+
+        for state in [wrong_platform, short_display, stack_overflow] {
+            assert!(matches!(state.validate(), Err(Error::InvalidSaveStateError(_))));
+            let bytes = state.to_bytes(&ROM_HASH);
+            assert!(matches!(
+                MachineState::from_bytes(&bytes, &ROM_HASH),
+                Err(Error::InvalidSaveStateError(_))
+            ));
+        }
+    }
+}
@@ -40,6 +40,22 @@ impl Stack {
         Ok(self.stack[self.sp as usize])
     }
 
+    /// Number of return addresses on the stack
+    pub fn depth(&self) -> usize {
+        self.sp as usize
+    }
+
+    /// Get stack contents and stack pointer
+    pub fn snapshot(&self) -> ([u16; STACK_SIZE], u16) {
+        (self.stack, self.sp)
+    }
+
+    /// Restore stack contents and stack pointer
+    pub fn restore(&mut self, stack: [u16; STACK_SIZE], sp: u16) {
+        self.stack = stack;
+        self.sp = sp;
+    }
+
     pub fn reset(&mut self) {
         self.stack = [0; STACK_SIZE];
         self.sp = 0;
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use disassembler::instruction::Instruction;
+use serde::Serialize;
+
+use crate::errors::{Error, Result};
+
+/// Layout of the trace file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// One human readable line per instruction
+    #[default]
+    Text,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl FromStr for TraceFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" | "txt" => Ok(TraceFormat::Text),
+            "json" | "jsonl" => Ok(TraceFormat::JsonLines),
+            _ => Err(Error::UnknownTraceFormatError(s.into())),
+        }
+    }
+}
+
+/// Selects the instructions written to the trace
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only trace instructions at these addresses
+    pub range: Option<RangeInclusive<u16>>,
+    /// Number of instructions to execute before tracing starts
+    pub start: u64,
+    /// Stop tracing after this many instructions have been written
+    pub limit: Option<u64>,
+}
+
+/// Settings for tracing execution to file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceOptions {
+    pub path: String,
+    pub format: TraceFormat,
+    pub filter: TraceFilter,
+}
+
+/// Effects of a single executed instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    /// General purpose registers changed by the instruction and their new values
+    pub changed_registers: Vec<(usize, u8)>,
+    pub i_reg: u16,
+    pub dt: u8,
+    pub st: u8,
+    /// Address and new value of each byte written to RAM
+    pub memory_writes: Vec<(u16, u8)>,
+}
+
+/// Writes executed instructions to a trace file
+#[derive(Debug)]
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    // Instructions executed so far
+    cycle: u64,
+    // Instructions written so far
+    written: u64,
+}
+
+impl TraceWriter {
+    pub fn create(options: &TraceOptions) -> Result<Self> {
+        let file = File::create(&options.path).map_err(|e| Error::TraceFileError(e.to_string()))?;
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+            format: options.format,
+            filter: options.filter.clone(),
+            cycle: 0,
+            written: 0,
+        })
+    }
+
+    /// Count the next instruction and check if it passes the filter
+    pub fn next_cycle(&mut self, pc: u16) -> bool {
+        self.cycle += 1;
+
+        self.cycle > self.filter.start
+            && self.filter.limit.is_none_or(|limit| self.written < limit)
+            && self.filter.range.as_ref().is_none_or(|range| range.contains(&pc))
+    }
+
+    /// Write the instruction counted by the last call to next_cycle
+    pub fn write(&mut self, entry: &TraceEntry) -> Result<()> {
+        let cycle = self.cycle - 1;
+        let line = match self.format {
+            TraceFormat::Text => format_text(cycle, entry),
+            TraceFormat::JsonLines => format_json(cycle, entry),
+        };
+        self.written += 1;
+
+        writeln!(self.writer, "{line}").map_err(|e| Error::TraceFileError(e.to_string()))
+    }
+}
+
+fn mnemonic(opcode: u16) -> String {
+    match Instruction::decode(opcode) {
+        Some(instruction) => instruction.to_string(),
+        None => "???".into(),
+    }
+}
+
+fn format_text(cycle: u64, entry: &TraceEntry) -> String {
+    let mut line = format!(
+        "{cycle:>10} {:03X}: {:04X} {:<18} I={:03X} DT={:02X} ST={:02X}",
+        entry.pc,
+        entry.opcode,
+        mnemonic(entry.opcode),
+        entry.i_reg,
+        entry.dt,
+        entry.st
+    );
+    for (register, value) in &entry.changed_registers {
+        line.push_str(&format!(" V{register:X}={value:02X}"));
+    }
+    for (address, value) in &entry.memory_writes {
+        line.push_str(&format!(" [{address:03X}]={value:02X}"));
+    }
+    line
+}
+
+/// Line of the JSON lines format
+#[derive(Serialize)]
+struct JsonEntry {
+    cycle: u64,
+    pc: u16,
+    opcode: u16,
+    mnemonic: String,
+    // Changed registers by name, e.g. "VA"
+    registers: BTreeMap<String, u8>,
+    i: u16,
+    dt: u8,
+    st: u8,
+    writes: Vec<JsonWrite>,
+}
+
+#[derive(Serialize)]
+struct JsonWrite {
+    address: u16,
+    value: u8,
+}
+
+fn format_json(cycle: u64, entry: &TraceEntry) -> String {
+    let line = JsonEntry {
+        cycle,
+        pc: entry.pc,
+        opcode: entry.opcode,
+        mnemonic: mnemonic(entry.opcode),
+        registers: entry
+            .changed_registers
+            .iter()
+            .map(|(register, value)| (format!("V{register:X}"), *value))
+            .collect(),
+        i: entry.i_reg,
+        dt: entry.dt,
+        st: entry.st,
+        writes: entry
+            .memory_writes
+            .iter()
+            .map(|&(address, value)| JsonWrite { address, value })
+            .collect(),
+    };
+    serde_json::to_string(&line).expect("Trace entries are serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn entry() -> TraceEntry {
+        TraceEntry {
+            pc: 0x20A,
+            opcode: 0xF233,
+            changed_registers: vec![(0x1, 0x07), (0xA, 0xFF)],
+            i_reg: 0x300,
+            dt: 0x10,
+            st: 0,
+            memory_writes: vec![(0x300, 2), (0x301, 5), (0x302, 5)],
+        }
+    }
+
+    fn writer(name: &str, format: TraceFormat, filter: TraceFilter) -> (TraceWriter, PathBuf) {
+        let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let options = TraceOptions {
+            path: path.to_string_lossy().into_owned(),
+            format,
+            filter,
+        };
+        (TraceWriter::create(&options).unwrap(), path)
+    }
+
+    #[test]
+    fn filter_selects_instructions() {
+        let filter = TraceFilter {
+            range: Some(0x200..=0x204),
+            start: 2,
+            limit: Some(2),
+        };
+        let (mut trace, path) = writer("trace-filter", TraceFormat::Text, filter);
+        // Before the start, then the range boundaries
+        let pcs = [0x200, 0x200, 0x1FE, 0x200, 0x206];
+        let traced: Vec<bool> = pcs.iter().map(|&pc| trace.next_cycle(pc)).collect();
+        assert_eq!(traced, [false, false, false, true, false]);
+
+        trace.write(&entry()).unwrap();
+        assert!(trace.next_cycle(0x204));
+        trace.write(&entry()).unwrap();
+        // The limit is reached
+        assert!(!trace.next_cycle(0x202));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn lines_are_formatted() {
+        assert_eq!(
+            format_text(6, &entry()),
+            "         6 20A: F233 BCDI   V2          I=300 DT=10 ST=00 V1=07 VA=FF \
+             [300]=02 [301]=05 [302]=05"
+        );
+        assert_eq!(
+            format_json(6, &entry()),
+            r#"{"cycle":6,"pc":522,"opcode":62003,"mnemonic":"BCDI   V2","registers":"#
+                .to_owned()
+                + r#"{"V1":7,"VA":255},"i":768,"dt":16,"st":0,"writes":[{"address":768,"#
+                + r#""value":2},{"address":769,"value":5},{"address":770,"value":5}]}"#
+        );
+    }
+
+    #[test]
+    fn written_cycles_start_at_zero() {
+        let (mut trace, path) =
+            writer("trace-cycles", TraceFormat::JsonLines, TraceFilter::default());
+        assert!(trace.next_cycle(0x20A));
+        trace.write(&entry()).unwrap();
+        drop(trace);
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text, format_json(0, &entry()) + "\n");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::errors::Error;
+
+/// Memory accesses a watchpoint reacts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Writes storing a different value than the one in memory
+    Change,
+    /// Reads and writes
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, access: WatchKind, old_value: u8, new_value: u8) -> bool {
+        match self {
+            WatchKind::Read | WatchKind::Write => self == access,
+            WatchKind::Change => access == WatchKind::Write && old_value != new_value,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+impl FromStr for WatchKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "r" | "read" => Ok(WatchKind::Read),
+            "w" | "write" => Ok(WatchKind::Write),
+            "c" | "change" => Ok(WatchKind::Change),
+            "a" | "access" => Ok(WatchKind::Access),
+            _ => Err(Error::UnknownWatchKindError(s.into())),
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Change => "change",
+            WatchKind::Access => "access",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Watched range of addresses. Both ends are inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn new(start: u16, end: u16, kind: WatchKind) -> Self {
+        Self {
+            start: start.min(end),
+            end: start.max(end),
+            kind,
+        }
+    }
+
+    /// Check if the access to the address triggers the watchpoint
+    pub fn triggered_by(&self, access: &MemoryAccess) -> bool {
+        (self.start..=self.end).contains(&access.address)
+            && self.kind.matches(access.kind, access.old_value, access.new_value)
+    }
+}
+
+/// Single byte read or written by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u16,
+    /// Either read or write
+    pub kind: WatchKind,
+    /// Value in memory before the access
+    pub old_value: u8,
+    /// Value in memory after the access. Same as the old value for reads
+    pub new_value: u8,
+}
+
+/// Access triggering a watchpoint and the instruction responsible
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub pc: u16,
+    pub opcode: u16,
+    pub watchpoint: Watchpoint,
+    pub access: MemoryAccess,
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use crate::instruction::Instruction;
+
+/// Address the ROM is loaded at and execution starts from
+pub const START_ADDRESS: u32 = 0x200;
+
+/// Instruction reached by following the control flow of a ROM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub instruction: Instruction,
+    /// Address word following an XO-CHIP long load
+    pub long_address: Option<u16>,
+}
+
+impl DecodedInstruction {
+    pub fn size(&self) -> u32 {
+        self.instruction.size() as u32
+    }
+
+    /// Address the instruction jumps to, calls or loads into the I register
+    pub fn target(&self) -> Option<u32> {
+        match self.instruction {
+            Instruction::Jump(address)
+            | Instruction::Call(address)
+            | Instruction::LoadI(address) => Some(address as u32),
+            Instruction::LoadLongI => self.long_address.map(u32::from),
+            _ => None,
+        }
+    }
+}
+
+/// Reachable code of a ROM. Every byte not covered by an instruction is data
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeMap {
+    /// Reachable instructions by address
+    pub instructions: BTreeMap<u32, DecodedInstruction>,
+    /// Addresses of BNNN jumps. Their targets depend on a register so
+    /// they are not followed
+    pub indirect_jumps: Vec<u32>,
+    /// Reachable addresses holding words that are not instructions
+    pub unknown: Vec<u32>,
+}
+
+impl CodeMap {
+    /// Follow jumps, calls and skips from the start address
+    pub fn trace(rom: &[u8]) -> Self {
+        let mut map = Self::default();
+        let mut pending = vec![START_ADDRESS];
+
+        while let Some(address) = pending.pop() {
+            if map.instructions.contains_key(&address) || map.unknown.contains(&address) {
+                continue;
+            }
+            // Paths running off the end of the ROM are dropped
+            let Some(opcode) = read_word(rom, address) else {
+                continue;
+            };
+            let Some(instruction) = Instruction::decode(opcode) else {
+                map.unknown.push(address);
+                continue;
+            };
+            let long_address = match instruction {
+                Instruction::LoadLongI => match read_word(rom, address + 2) {
+                    Some(word) => Some(word),
+                    None => continue,
+                },
+                _ => None,
+            };
+
+            let decoded = DecodedInstruction {
+                instruction,
+                long_address,
+            };
+            if let Instruction::JumpPlus(_) = instruction {
+                map.indirect_jumps.push(address);
+            }
+            pending.extend(successors(rom, address, &decoded));
+            map.instructions.insert(address, decoded);
+        }
+
+        map.indirect_jumps.sort_unstable();
+        map.unknown.sort_unstable();
+        map
+    }
+
+    /// Instructions executed after the one at the address if no skips are
+    /// taken. Jumps are followed and the path ends at calls, returns and
+    /// anything else that cannot be followed
+    pub fn path_after(&self, address: u32) -> impl Iterator<Item = (u32, &DecodedInstruction)> {
+        let mut next = self.instructions.get(&address).map(|decoded| address + decoded.size());
+        std::iter::from_fn(move || {
+            let address = next?;
+            let decoded = self.instructions.get(&address)?;
+            next = match decoded.instruction {
+                Instruction::Jump(target) => Some(target as u32),
+                Instruction::Call(_)
+                | Instruction::Return
+                | Instruction::Exit
+                | Instruction::JumpPlus(_)
+                | Instruction::System(_) => None,
+                _ => Some(address + decoded.size()),
+            };
+            Some((address, decoded))
+        })
+    }
+}
+
+/// Addresses execution may continue at after the instruction
+pub fn successors(rom: &[u8], address: u32, decoded: &DecodedInstruction) -> Vec<u32> {
+    let next = address + decoded.size();
+    match decoded.instruction {
+        Instruction::Jump(target) => vec![target as u32],
+        // Assume the subroutine returns
+        Instruction::Call(target) => vec![target as u32, next],
+        Instruction::SkipEqual { .. }
+        | Instruction::SkipNotEqual { .. }
+        | Instruction::SkipRegistersEqual { .. }
+        | Instruction::SkipRegistersNotEqual { .. }
+        | Instruction::SkipKeyDown(_)
+        | Instruction::SkipKeyUp(_) => {
+            // Skipping a long load skips its address word as well
+            let skipped = match read_word(rom, next) {
+                Some(opcode) if opcode == Instruction::LoadLongI.encode() => 4,
+                _ => 2,
+            };
+            vec![next, next + skipped]
+        }
+        // Machine code routines cannot be executed, so the path ends there as well
+        Instruction::Return
+        | Instruction::Exit
+        | Instruction::JumpPlus(_)
+        | Instruction::System(_) => Vec::new(),
+        _ => vec![next],
+    }
+}
+
+fn read_word(rom: &[u8], address: u32) -> Option<u16> {
+    let offset = address.checked_sub(START_ADDRESS)? as usize;
+    let bytes = rom.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
@@ -1,300 +1,430 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::{fs::File, io::Write};
 
+use crate::control_flow::{CodeMap, DecodedInstruction, START_ADDRESS};
 use crate::errors::Error;
-
-pub fn disassembler(rom_path: &str, output: Option<String>) -> Result<(), Error> {
+use crate::instruction::Instruction;
+use crate::labels::{Labels, read_symbols};
+use crate::output::{self, OutputFormat};
+use crate::sprites::{find_sprites, pixel_row};
+
+const DATA_BYTES_PER_LINE: u32 = 8;
+
+/// Disassemble a ROM file, naming addresses with the symbol file if given
+pub fn disassembler(
+    rom_path: &str,
+    symbols_path: Option<&str>,
+    format: OutputFormat,
+) -> Result<Disassembly, Error> {
     let rom = std::fs::read(rom_path).map_err(|e| Error::FileReadError(e.to_string()))?;
-    let mut assembly: Vec<String> = Vec::new();
-
-    for (i, bytes) in rom.chunks_exact(2).enumerate() {
-        let opcode = ((bytes[0] as u32) << 8) | bytes[1] as u32;
-        let address = 0x200 + i as u32;
-
-        let line = match opcode & 0xF000 {
-            0x0000 => match opcode {
-                0x00E0 => clear_display(address),
-                0x00EE => return_subroutine(address),
-                _ => sys_call(address, opcode),
-            },
-
-            0x1000 => jump(address, opcode),
-            0x2000 => call_subroutine(address, opcode),
-            0x3000 => skip_equal(address, opcode),
-            0x4000 => skip_not_equal(address, opcode),
-            0x5000 => skip_register_equal(address, opcode),
-            0x6000 => load_number(address, opcode),
-            0x7000 => add_number(address, opcode),
-
-            // Register loading opcodes
-            0x8000 => match opcode & 0x000F {
-                0x0 => move_register(address, opcode),
-                // OR
-                0x1 => load_register_op(address, opcode, "OR"),
-                // AND
-                0x2 => load_register_op(address, opcode, "AND"),
-                // XOR
-                0x3 => load_register_op(address, opcode, "XOR"),
-                0x4 => add_register_carry(address, opcode),
-                0x5 => sub_register(address, opcode),
-                0x6 => shift_right(address, opcode),
-                0x7 => sub_register_reversed(address, opcode),
-                0xE => shift_left(address, opcode),
-                _ => return Err(Error::UnknownOpcodeError(opcode)),
-            },
-
-            0x9000 => skip_register_not_equal(address, opcode),
-            0xA000 => load_i(address, opcode),
-            0xB000 => jump_plus(address, opcode),
-            0xC000 => random_and(address, opcode),
-            0xD000 => draw_sprite(address, opcode),
-
-            0xE000 => match opcode & 0x00FF {
-                0x9E => skip_if_keypress(address, opcode),
-                0xA1 => skip_if_not_keypress(address, opcode),
-                _ => return Err(Error::UnknownOpcodeError(opcode)),
-            },
-
-            0xF000 => match opcode & 0x00FF {
-                0x07 => move_delay_timer(address, opcode),
-                0x0A => wait_for_keypress(address, opcode),
-                0x15 => set_delay_timer(address, opcode),
-                0x18 => set_sound_timer(address, opcode),
-                0x1E => load_add_i(address, opcode),
-                0x29 => find_character(address, opcode),
-                0x33 => store_bcd(address, opcode),
-                0x55 => dump_registers_to_ram(address, opcode),
-                0x65 => load_registers_from_ram(address, opcode),
-                _ => return Err(Error::UnknownOpcodeError(opcode)),
-            },
+    let symbols = read_symbols(symbols_path)?;
+    Ok(disassemble(&rom, symbols, format))
+}
 
-            _ => return Err(Error::UnknownOpcodeError(opcode)),
-        };
-        
-        // println!("{opcode:04X},   {:03X}       {line}", i * 2);
-        
-        assembly.push(line);
+/// Listing of a ROM and the problems found while producing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    pub listing: String,
+    pub warnings: Vec<Warning>,
+}
 
+impl Disassembly {
+    /// Write the listing to a new file, or to stdout if no file is given
+    pub fn write(&self, output: Option<String>) -> Result<(), Error> {
+        write_output(&self.listing, output)
     }
+}
 
-    let assembly = assembly.join("\n");
+/// Write text to a new file, or to stdout if no file is given
+pub(crate) fn write_output(text: &str, output: Option<String>) -> Result<(), Error> {
     match output {
         Some(output) => {
             let mut file =
                 File::create_new(output).map_err(|e| Error::FileWriteError(e.to_string()))?;
-            file.write_all(assembly.as_bytes())
+            file.write_all(text.as_bytes())
                 .map_err(|e| Error::FileWriteError(e.to_string()))?;
         }
-        None => println!("{}", assembly),
+        None => println!("{}", text),
     }
 
     Ok(())
 }
 
-fn format_no_arg(address: u32, mnemonic: &str) -> String {
-    format!("{address:03X}: {mnemonic:<6}")
-}
-
-fn format_one_arg(address: u32, mnemonic: &str, arg: u32) -> String {
-    format!("{address:03X}: {mnemonic:<6} {arg:#X}")
-}
-
-fn format_one_reg(address: u32, mnemonic: &str, reg: u32) -> String {
-    format!("{address:03X}: {mnemonic:<6} V{reg:X}")
-}
-
-fn format_reg_arg(address: u32, mnemonic: &str, reg: u32, arg: u32) -> String {
-    format!("{address:03X}: {mnemonic:<6} V{reg:X}, {arg:#X}")
-}
-
-fn format_reg_reg(address: u32, mnemonic: &str, reg1: u32, reg2: u32) -> String {
-    format!("{address:03X}: {mnemonic:<6} V{reg1:X}, V{reg2:X}")
-}
-
-fn format_reg_reg_arg(address: u32, mnemonic: &str, reg1: u32, reg2: u32, arg: u32) -> String {
-    format!("{address:03X}: {mnemonic:<6} V{reg1:X}, V{reg2:X}, {arg:#X}")
-}
-
-fn get_hex_digit(hex: u32, i: u32) -> u32 {
-    (hex >> (i * 4)) & 0xF
-}
-
-/***** OPCODE FUNCTIONS *****/
-
-fn clear_display(address: u32) -> String {
-    format_no_arg(address, "CLS")
-}
-
-fn return_subroutine(address: u32) -> String {
-    format_no_arg(address, "RTS")
-}
-
-fn sys_call(address: u32, opcode: u32) -> String {
-    let target = opcode & 0x0FFF;
-    format_one_arg(address, "SYS", target)
-}
-
-fn jump(address: u32, opcode: u32) -> String {
-    let target = opcode & 0x0FFF;
-    format_one_arg(address, "JUMP", target)
-}
-
-fn call_subroutine(address: u32, opcode: u32) -> String {
-    let target = opcode & 0x0FFF;
-    format_one_arg(address, "CALL", target)
-}
-
-fn skip_equal(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    let number = opcode & 0x00FF;
-    format_reg_arg(address, "SKE", reg, number)
-}
-
-fn skip_not_equal(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    let number = opcode & 0x00FF;
-    format_reg_arg(address, "SKNE", reg, number)
-}
-
-fn skip_register_equal(address: u32, opcode: u32) -> String {
-    let reg_x = get_hex_digit(opcode, 2);
-    let reg_y = get_hex_digit(opcode, 1);
-    format_reg_arg(address, "SKRE", reg_x, reg_y)
-}
-
-fn load_number(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    let number = opcode & 0x00FF;
-    format_reg_arg(address, "LOAD", reg, number)
-}
-
-fn add_number(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    let number = opcode & 0x00FF;
-    format_reg_arg(address, "ADD", reg, number)
+/// Problem that makes the listing less certain without stopping it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    /// Reachable word that is not an instruction. It is listed as data
+    UnknownOpcode { address: u32, opcode: u16 },
+    /// BNNN jump. Code only reached through it is listed as data
+    IndirectJump { address: u32 },
 }
 
-fn move_register(address: u32, opcode: u32) -> String {
-    let reg_x = get_hex_digit(opcode, 2);
-    let reg_y = get_hex_digit(opcode, 1);
-    format_reg_reg(address, "MOVE", reg_x, reg_y)
-}
-
-fn load_register_op(address: u32, opcode: u32, operation: &str) -> String {
-    let reg_x = get_hex_digit(opcode, 2);
-    let reg_y = get_hex_digit(opcode, 1);
-    format_reg_reg(address, operation, reg_x, reg_y)
-}
-
-fn add_register_carry(address: u32, opcode: u32) -> String {
-    let reg_x = get_hex_digit(opcode, 2);
-    let reg_y = get_hex_digit(opcode, 1);
-    format_reg_reg(address, "ADDR", reg_x, reg_y)
-}
-
-fn sub_register(address: u32, opcode: u32) -> String {
-    let reg_x = get_hex_digit(opcode, 2);
-    let reg_y = get_hex_digit(opcode, 1);
-    format_reg_reg(address, "SUB", reg_x, reg_y)
-}
-
-fn shift_right(address: u32, opcode: u32) -> String {
-    let reg_x = get_hex_digit(opcode, 2);
-    let reg_y = get_hex_digit(opcode, 1);
-    format_reg_reg(address, "SHR", reg_x, reg_y)
-}
-
-fn sub_register_reversed(address: u32, opcode: u32) -> String {
-    let reg_x = get_hex_digit(opcode, 2);
-    let reg_y = get_hex_digit(opcode, 1);
-    format_reg_reg(address, "SUBR", reg_x, reg_y)
-}
-
-fn shift_left(address: u32, opcode: u32) -> String {
-    let reg_x = get_hex_digit(opcode, 2);
-    let reg_y = get_hex_digit(opcode, 1);
-    format_reg_reg(address, "SHL", reg_x, reg_y)
-}
-
-fn skip_register_not_equal(address: u32, opcode: u32) -> String {
-    let reg_x = get_hex_digit(opcode, 2);
-    let reg_y = get_hex_digit(opcode, 1);
-    format_reg_reg(address, "SKRNE", reg_x, reg_y)
+impl Warning {
+    pub fn address(&self) -> u32 {
+        match *self {
+            Warning::UnknownOpcode { address, .. } | Warning::IndirectJump { address } => address,
+        }
+    }
 }
 
-fn load_i(address: u32, opcode: u32) -> String {
-    let number = opcode & 0x0FFF;
-    format_one_arg(address, "LOADI", number)
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::UnknownOpcode { address, opcode } => {
+                write!(f, "{address:03X}: Unknown opcode {opcode:04X}")
+            }
+            Warning::IndirectJump { address } => {
+                write!(f, "{address:03X}: Target of indirect jump not followed")
+            }
+        }
+    }
 }
 
-fn jump_plus(address: u32, opcode: u32) -> String {
-    let number = opcode & 0x0FFF;
-    format_one_arg(number, "JUMPI", address)
+// Contents of a line in the listing
+enum LineKind {
+    Instruction(DecodedInstruction),
+    /// Reachable word that is not an instruction
+    Unknown,
+    Data,
+    /// Data drawn as one row of a sprite
+    SpriteRow,
 }
 
-fn random_and(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    let number = opcode & 0x00FF;
-    format_reg_arg(address, "RAND", reg, number)
+struct Line {
+    address: u32,
+    size: u32,
+    kind: LineKind,
 }
 
-fn draw_sprite(address: u32, opcode: u32) -> String {
-    let reg_x = get_hex_digit(opcode, 2);
-    let reg_y = get_hex_digit(opcode, 1);
-    let number = opcode & 0x000F;
-    format_reg_reg_arg(address, "DRAW", reg_x, reg_y, number)
+impl Line {
+    fn bytes<'a>(&self, rom: &'a [u8]) -> &'a [u8] {
+        let offset = (self.address - START_ADDRESS) as usize;
+        &rom[offset..offset + self.size as usize]
+    }
 }
 
-fn skip_if_keypress(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "SKEYD", reg)
-}
+/// Disassemble a ROM loaded at 0x200. Instructions are found by following
+/// the control flow from the start address and all other bytes are data.
+/// Jump, call and I register load targets are labelled, using the names
+/// given by the symbols where available. Unknown opcodes are listed as data
+/// and sprites are drawn in comments next to their rows
+pub fn disassemble(
+    rom: &[u8],
+    symbols: BTreeMap<u32, String>,
+    format: OutputFormat,
+) -> Disassembly {
+    let code = CodeMap::trace(rom);
+    let mut labels = Labels::new(&code, symbols);
+    let label_addresses: BTreeSet<u32> = labels.addresses().collect();
+    let unknown: BTreeSet<u32> = code.unknown.iter().copied().collect();
+    let end = START_ADDRESS + rom.len() as u32;
+
+    // Start address and size of every sprite row
+    let mut sprite_rows: BTreeMap<u32, u32> = BTreeMap::new();
+    for (address, sprite) in find_sprites(&code) {
+        for row in 0..sprite.rows {
+            sprite_rows.insert(address + row * sprite.row_size, sprite.row_size);
+        }
+    }
 
-fn skip_if_not_keypress(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "SKEYU", reg)
-}
+    let mut lines: Vec<Line> = Vec::new();
+    let mut address = START_ADDRESS;
+    while address < end {
+        if let Some(decoded) = code.instructions.get(&address) {
+            lines.push(Line {
+                address,
+                size: decoded.size(),
+                kind: LineKind::Instruction(*decoded),
+            });
+            address += decoded.size();
+            continue;
+        }
+        if unknown.contains(&address) && address + 2 <= end {
+            lines.push(Line {
+                address,
+                size: 2,
+                kind: LineKind::Unknown,
+            });
+            address += 2;
+            continue;
+        }
 
-fn move_delay_timer(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "MOVEDT", reg)
-}
+        // Data runs until the next instruction, unknown opcode, label or sprite row
+        let sprite_row = sprite_rows.get(&address).copied();
+        let next_instruction = code.instructions.range(address..).next().map(|(&a, _)| a);
+        let next_unknown = unknown.range(address + 1..).next().copied();
+        let next_label = label_addresses.range(address + 1..).next().copied();
+        let next_row = sprite_rows.range(address + 1..).next().map(|(&a, _)| a);
+        let data_end = [next_instruction, next_unknown, next_label, next_row, Some(end)]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(end)
+            .min(address + sprite_row.unwrap_or(DATA_BYTES_PER_LINE));
+        lines.push(Line {
+            address,
+            size: data_end - address,
+            kind: match sprite_row {
+                Some(_) => LineKind::SpriteRow,
+                None => LineKind::Data,
+            },
+        });
+        address = data_end;
+    }
 
-fn wait_for_keypress(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "KEYW", reg)
-}
+    let line_starts: BTreeSet<u32> = lines.iter().map(|line| line.address).collect();
+    labels.place(|address| line_starts.contains(&address));
+
+    let listing = match format {
+        OutputFormat::Json => render_json(rom, &lines, &labels),
+        _ => render_text(format, rom, &lines, &labels),
+    };
+
+    let mut warnings: Vec<Warning> = code
+        .indirect_jumps
+        .iter()
+        .map(|&address| Warning::IndirectJump { address })
+        .chain(code.unknown.iter().map(|&address| {
+            let offset = (address - START_ADDRESS) as usize;
+            let opcode = u16::from_be_bytes([rom[offset], rom[offset + 1]]);
+            Warning::UnknownOpcode { address, opcode }
+        }))
+        .collect();
+    warnings.sort_by_key(Warning::address);
+
+    Disassembly { listing, warnings }
+}
+
+fn render_text(format: OutputFormat, rom: &[u8], lines: &[Line], labels: &Labels) -> String {
+    let mut text: Vec<String> = labels
+        .constants()
+        .iter()
+        .map(|(&address, name)| format.constant(name, address))
+        .collect();
+    // Octo programs start at the main label
+    if format == OutputFormat::Octo && labels.label(START_ADDRESS) != Some("main") {
+        text.push(format.label("main"));
+    }
 
-fn set_delay_timer(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "LOADD", reg)
-}
+    for line in lines {
+        if let Some(label) = labels.label(line.address) {
+            text.push(format.label(label));
+        }
+        let (line_text, comment) = match &line.kind {
+            LineKind::Instruction(decoded) => {
+                let target = decoded.target().and_then(|target| labels.get(target));
+                (format.instruction(decoded, target), None)
+            }
+            LineKind::Unknown | LineKind::Data => (format.data(line.bytes(rom)), None),
+            LineKind::SpriteRow => {
+                let bytes = line.bytes(rom);
+                (format.data(bytes), Some(pixel_row(bytes)))
+            }
+        };
+        text.push(format.line(line.address, &line_text, comment.as_deref()));
+    }
 
-fn set_sound_timer(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "LOADS", reg)
-}
+    text.join("\n")
+}
+
+fn render_json(rom: &[u8], lines: &[Line], labels: &Labels) -> String {
+    let objects: Vec<String> = lines
+        .iter()
+        .map(|line| {
+            let bytes = line.bytes(rom);
+            let (mnemonic, operands) = match &line.kind {
+                LineKind::Instruction(decoded) => {
+                    let target = decoded.target().and_then(|target| labels.get(target));
+                    output::native(decoded, target)
+                }
+                LineKind::Unknown | LineKind::Data | LineKind::SpriteRow => {
+                    ("db", bytes.iter().map(|byte| format!("{byte:#04X}")).collect())
+                }
+            };
+            let label = labels
+                .label(line.address)
+                .map_or_else(|| "null".into(), |label| format!("\"{label}\""));
+            let bytes: Vec<String> = bytes.iter().map(u8::to_string).collect();
+            let operands: Vec<String> = operands.iter().map(|o| format!("\"{o}\"")).collect();
+
+            format!(
+                "  {{\"address\":{},\"bytes\":[{}],\"label\":{label},\
+                 \"mnemonic\":\"{mnemonic}\",\"operands\":[{}]}}",
+                line.address,
+                bytes.join(","),
+                operands.join(",")
+            )
+        })
+        .collect();
+
+    format!("[\n{}\n]", objects.join(",\n"))
+}
+
+/// Disassemble a single opcode located at the given address
+pub fn disassemble_opcode(address: u32, opcode: u32) -> Result<String, Error> {
+    let instruction =
+        Instruction::decode(opcode as u16).ok_or(Error::UnknownOpcodeError(opcode))?;
+    Ok(format!("{address:03X}: {instruction}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::parse_symbols;
+
+    #[test]
+    fn disassemble_every_opcode() {
+        for opcode in 0..=u16::MAX as u32 {
+            let line = disassemble_opcode(0x200, opcode);
+            match Instruction::decode(opcode as u16) {
+                Some(instruction) => assert_eq!(line.unwrap(), format!("200: {instruction}")),
+                None => assert!(matches!(line, Err(Error::UnknownOpcodeError(o)) if o == opcode)),
+            }
+        }
+    }
 
-fn load_add_i(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "ADDI", reg)
-}
+    #[test]
+    fn addresses_are_byte_offsets() {
+        let dir = std::env::temp_dir().join(format!("disassembler-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("rom.ch8");
+        let output_path = dir.join("rom.asm");
+        std::fs::write(&rom_path, [0x00, 0xE0, 0xF0, 0x00, 0x12, 0x34, 0xB3, 0x00]).unwrap();
+        let _ = std::fs::remove_file(&output_path);
+
+        let disassembly =
+            disassembler(rom_path.to_str().unwrap(), None, OutputFormat::Native).unwrap();
+        disassembly.write(Some(output_path.to_str().unwrap().into())).unwrap();
+        let assembly = std::fs::read_to_string(&output_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(assembly, "200: CLS\n202: LOADL  0x1234\n206: JUMPI  0x300");
+        assert_eq!(disassembly.warnings, [Warning::IndirectJump { address: 0x206 }]);
+    }
 
-fn find_character(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "LDCHR", reg)
-}
+    #[test]
+    fn interleaved_data_is_not_decoded() {
+        // The sprite drawn from 0x206 has an odd number of rows
+        let rom = [
+            0xA2, 0x06, 0xD0, 0x13, 0x12, 0x09, 0xF0, 0x90, 0xF0, 0x3E, 0x01, 0x00, 0xEE, 0xB2,
+            0x00, 0x80,
+        ];
+
+        let code = CodeMap::trace(&rom);
+        assert_eq!(code.indirect_jumps, [0x20D]);
+        assert!(code.unknown.is_empty());
+
+        let expected = [
+            "200: LOADI  data_206",
+            "202: DRAW   V0, V1, 0x3",
+            "204: JUMP   loop_209",
+            "data_206:",
+            "206: db     0xF0              ; ####....",
+            "207: db     0x90              ; #..#....",
+            "208: db     0xF0              ; ####....",
+            "loop_209:",
+            "209: SKE    VE, 0x1",
+            "20B: RTS",
+            "20D: JUMPI  0x200",
+            "20F: db     0x80",
+        ];
+        let disassembly = disassemble(&rom, BTreeMap::new(), OutputFormat::Native);
+        assert_eq!(disassembly.listing, expected.join("\n"));
+    }
 
-fn store_bcd(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "BCDI", reg)
-}
+    #[test]
+    fn symbols_name_labels() {
+        let rom = [0x22, 0x06, 0xA0, 0x50, 0x12, 0x00, 0x00, 0xEE];
+        let symbols = parse_symbols("206 draw_player ; subroutine\n0x050 font\n").unwrap();
+
+        let expected = [
+            "font equ 0x50",
+            "loop_200:",
+            "200: CALL   draw_player",
+            "202: LOADI  font",
+            "204: JUMP   loop_200",
+            "draw_player:",
+            "206: RTS",
+        ];
+        assert_eq!(disassemble(&rom, symbols, OutputFormat::Native).listing, expected.join("\n"));
+        assert!(parse_symbols("206 V1").is_err());
+        assert!(parse_symbols("main").is_err());
+    }
 
-fn dump_registers_to_ram(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "STORE", reg)
-}
+    #[test]
+    fn unknown_opcodes_are_listed_as_data() {
+        // The unknown word ends the only path, so nothing after it is reached
+        let rom = [0x80, 0x1F, 0x00, 0xE0];
+        let disassembly = disassemble(&rom, BTreeMap::new(), OutputFormat::Native);
+        assert_eq!(disassembly.listing, "200: db     0x80, 0x1F\n202: db     0x00, 0xE0");
+
+        // Jump past an odd length data block onto 8XYF
+        let rom = [0x12, 0x03, 0xAA, 0x81, 0x2F];
+        let disassembly = disassemble(&rom, BTreeMap::new(), OutputFormat::Native);
+        let expected = [
+            "200: JUMP   loop_203",
+            "202: db     0xAA",
+            "loop_203:",
+            "203: db     0x81, 0x2F",
+        ];
+        assert_eq!(disassembly.listing, expected.join("\n"));
+        let warning = Warning::UnknownOpcode {
+            address: 0x203,
+            opcode: 0x812F,
+        };
+        assert_eq!(disassembly.warnings, [warning]);
+        assert_eq!(warning.to_string(), "203: Unknown opcode 812F");
+    }
 
-fn load_registers_from_ram(address: u32, opcode: u32) -> String {
-    let reg = get_hex_digit(opcode, 2);
-    format_one_reg(address, "READ", reg)
+    #[test]
+    fn output_formats() {
+        let rom = [0x63, 0x01, 0x40, 0x05, 0xA2, 0x08, 0xD0, 0x15, 0xF0];
+        let listing = |format| disassemble(&rom, BTreeMap::new(), format).listing;
+
+        let native = [
+            "200: LOAD   V3, 0x1",
+            "202: SKNE   V0, 0x5",
+            "204: LOADI  data_208",
+            "206: DRAW   V0, V1, 0x5",
+            "data_208:",
+            "208: db     0xF0              ; ####....",
+        ];
+        assert_eq!(listing(OutputFormat::Native), native.join("\n"));
+
+        let cowgod = [
+            "200: LD     V3, 0x1",
+            "202: SNE    V0, 0x5",
+            "204: LD     I, data_208",
+            "206: DRW    V0, V1, 0x5",
+            "data_208:",
+            "208: DB     0xF0              ; ####....",
+        ];
+        assert_eq!(listing(OutputFormat::Cowgod), cowgod.join("\n"));
+
+        let octo = [
+            ": main",
+            "  v3 := 1                  # 200",
+            "  if v0 == 5 then          # 202",
+            "  i := data_208            # 204",
+            "  sprite v0 v1 5           # 206",
+            ": data_208",
+            "  0xF0                     # 208 ####....",
+        ];
+        assert_eq!(listing(OutputFormat::Octo), octo.join("\n"));
+
+        let json = listing(OutputFormat::Json);
+        let objects: Vec<&str> = json.lines().collect();
+        assert_eq!(objects.len(), 7);
+        assert_eq!(
+            objects[1],
+            "  {\"address\":512,\"bytes\":[99,1],\"label\":null,\
+             \"mnemonic\":\"LOAD\",\"operands\":[\"V3\",\"0x1\"]},"
+        );
+        assert_eq!(
+            objects[5],
+            "  {\"address\":520,\"bytes\":[240],\"label\":\"data_208\",\
+             \"mnemonic\":\"db\",\"operands\":[\"0xF0\"]}"
+        );
+    }
 }
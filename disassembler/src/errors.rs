@@ -8,4 +8,8 @@ pub enum Error {
     FileReadError(String),
     #[error("Failed to write to assembly file")]
     FileWriteError(String),
+    #[error("Invalid symbol file entry: {0}")]
+    SymbolFileError(String),
+    #[error("Unknown output format: {0}")]
+    UnknownOutputFormatError(String),
 }
\ No newline at end of file
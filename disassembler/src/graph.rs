@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::control_flow::{CodeMap, DecodedInstruction, START_ADDRESS, successors};
+use crate::disassembler::write_output;
+use crate::errors::Error;
+use crate::instruction::Instruction;
+use crate::labels::{Labels, read_symbols};
+use crate::output::OutputFormat;
+
+/// Build the control flow graph of a ROM file and write it as DOT
+pub fn cfg(
+    rom_path: &str,
+    symbols_path: Option<&str>,
+    output: Option<String>,
+) -> Result<(), Error> {
+    let rom = std::fs::read(rom_path).map_err(|e| Error::FileReadError(e.to_string()))?;
+    let symbols = read_symbols(symbols_path)?;
+    let code = CodeMap::trace(&rom);
+    let labels = Labels::new(&code, symbols);
+    write_output(&ControlFlowGraph::new(&rom, &code).to_dot(&labels), output)
+}
+
+/// How control passes from one block to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Jump,
+    Call,
+    /// Taken skip over the next instruction
+    Skip,
+    /// Next instruction, including the return from a call and a skip not taken
+    FallThrough,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub from: u32,
+    pub to: u32,
+    pub kind: EdgeKind,
+}
+
+/// Straight line code entered only at its first instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// Instructions by address
+    pub instructions: Vec<(u32, DecodedInstruction)>,
+}
+
+/// Basic blocks of the reachable code and the edges between them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    /// Blocks by start address
+    pub blocks: BTreeMap<u32, Block>,
+    pub edges: Vec<Edge>,
+    /// Blocks of each subroutine by entry address. Blocks reachable from
+    /// the start address without calls belong to the main program instead
+    pub subroutines: BTreeMap<u32, BTreeSet<u32>>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(rom: &[u8], code: &CodeMap) -> Self {
+        // Every branch target and every instruction after a branch starts a block
+        let mut leaders = BTreeSet::from([START_ADDRESS]);
+        let mut exits = BTreeMap::new();
+        for (&address, decoded) in &code.instructions {
+            let exit = instruction_edges(rom, address, decoded);
+            if ends_block(&decoded.instruction) {
+                leaders.extend(exit.iter().map(|edge| edge.to));
+            }
+            exits.insert(address, exit);
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut edges = Vec::new();
+        for &start in &leaders {
+            let mut instructions = Vec::new();
+            let mut address = start;
+            while let Some(decoded) = code.instructions.get(&address) {
+                instructions.push((address, *decoded));
+                let next = address + decoded.size();
+                if ends_block(&decoded.instruction) || leaders.contains(&next) {
+                    edges.extend(exits[&address].iter().copied().map(|edge| Edge {
+                        from: start,
+                        ..edge
+                    }));
+                    break;
+                }
+                address = next;
+            }
+            if !instructions.is_empty() {
+                blocks.insert(start, Block { instructions });
+            }
+        }
+        // Branches into the middle of an instruction or out of the code
+        edges.retain(|edge| blocks.contains_key(&edge.to));
+
+        let mut graph = Self {
+            blocks,
+            edges,
+            subroutines: BTreeMap::new(),
+        };
+        graph.subroutines = graph.find_subroutines();
+        graph
+    }
+
+    fn find_subroutines(&self) -> BTreeMap<u32, BTreeSet<u32>> {
+        let mut claimed = self.reachable_without_calls(START_ADDRESS);
+        let entries: BTreeSet<u32> = self
+            .edges
+            .iter()
+            .filter(|edge| edge.kind == EdgeKind::Call)
+            .map(|edge| edge.to)
+            .collect();
+
+        let mut subroutines = BTreeMap::new();
+        for entry in entries {
+            let blocks: BTreeSet<u32> =
+                self.reachable_without_calls(entry).difference(&claimed).copied().collect();
+            if !blocks.is_empty() {
+                claimed.extend(&blocks);
+                subroutines.insert(entry, blocks);
+            }
+        }
+        subroutines
+    }
+
+    fn reachable_without_calls(&self, entry: u32) -> BTreeSet<u32> {
+        let mut reachable = BTreeSet::new();
+        let mut pending = vec![entry];
+        while let Some(block) = pending.pop() {
+            if self.blocks.contains_key(&block) && reachable.insert(block) {
+                pending.extend(
+                    self.edges
+                        .iter()
+                        .filter(|edge| edge.from == block && edge.kind != EdgeKind::Call)
+                        .map(|edge| edge.to),
+                );
+            }
+        }
+        reachable
+    }
+
+    /// Graphviz DOT with one node per block, subroutines grouped in clusters
+    pub fn to_dot(&self, labels: &Labels) -> String {
+        let node = |start: u32| {
+            let block = &self.blocks[&start];
+            let mut text = String::new();
+            if let Some(label) = labels.get(start) {
+                text.push_str(&format!("{label}:\\l"));
+            }
+            for (address, decoded) in &block.instructions {
+                let target = decoded.target().and_then(|target| labels.get(target));
+                let instruction = OutputFormat::Native.instruction(decoded, target);
+                text.push_str(&format!("{address:03X}: {instruction}\\l"));
+            }
+            format!("b{start:03X} [label=\"{text}\"];")
+        };
+
+        let mut dot = vec![
+            "digraph cfg {".to_string(),
+            "    node [shape=box, fontname=\"monospace\"];".to_string(),
+        ];
+        for (entry, blocks) in &self.subroutines {
+            let name = labels.get(*entry).map_or_else(|| format!("{entry:03X}"), String::from);
+            dot.push(format!("    subgraph cluster_{entry:03X} {{"));
+            dot.push(format!("        label=\"{name}\";"));
+            dot.extend(blocks.iter().map(|&start| format!("        {}", node(start))));
+            dot.push("    }".to_string());
+        }
+        let in_subroutine: BTreeSet<u32> = self.subroutines.values().flatten().copied().collect();
+        for &start in self.blocks.keys().filter(|start| !in_subroutine.contains(start)) {
+            dot.push(format!("    {}", node(start)));
+        }
+
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Jump => "",
+                EdgeKind::Call => " [style=dashed, label=\"call\"]",
+                EdgeKind::Skip => " [color=red, label=\"skip\"]",
+                EdgeKind::FallThrough => " [color=gray]",
+            };
+            dot.push(format!("    b{:03X} -> b{:03X}{style};", edge.from, edge.to));
+        }
+        dot.push("}".to_string());
+
+        dot.join("\n")
+    }
+}
+
+// Instructions after which control does not simply continue with the next one
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jump(_)
+            | Instruction::Call(_)
+            | Instruction::Return
+            | Instruction::Exit
+            | Instruction::JumpPlus(_)
+            | Instruction::System(_)
+            | Instruction::SkipEqual { .. }
+            | Instruction::SkipNotEqual { .. }
+            | Instruction::SkipRegistersEqual { .. }
+            | Instruction::SkipRegistersNotEqual { .. }
+            | Instruction::SkipKeyDown(_)
+            | Instruction::SkipKeyUp(_)
+    )
+}
+
+fn instruction_edges(rom: &[u8], address: u32, decoded: &DecodedInstruction) -> Vec<Edge> {
+    let edge = |to: u32, kind: EdgeKind| Edge {
+        from: address,
+        to,
+        kind,
+    };
+    match decoded.instruction {
+        Instruction::Jump(target) => vec![edge(target as u32, EdgeKind::Jump)],
+        Instruction::Call(target) => vec![
+            edge(target as u32, EdgeKind::Call),
+            edge(address + decoded.size(), EdgeKind::FallThrough),
+        ],
+        _ => successors(rom, address, decoded)
+            .into_iter()
+            .enumerate()
+            .map(|(i, to)| match i {
+                0 => edge(to, EdgeKind::FallThrough),
+                _ => edge(to, EdgeKind::Skip),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_edges_and_subroutines() {
+        let rom = [
+            0x22, 0x08, 0x30, 0x01, 0x12, 0x00, 0x12, 0x06, 0x70, 0x01, 0x00, 0xEE,
+        ];
+        let code = CodeMap::trace(&rom);
+        let graph = ControlFlowGraph::new(&rom, &code);
+
+        let starts: Vec<u32> = graph.blocks.keys().copied().collect();
+        assert_eq!(starts, [0x200, 0x202, 0x204, 0x206, 0x208]);
+        assert_eq!(graph.blocks[&0x208].instructions.len(), 2);
+
+        let edge = |from, to, kind| Edge { from, to, kind };
+        assert_eq!(
+            graph.edges,
+            [
+                edge(0x200, 0x208, EdgeKind::Call),
+                edge(0x200, 0x202, EdgeKind::FallThrough),
+                edge(0x202, 0x204, EdgeKind::FallThrough),
+                edge(0x202, 0x206, EdgeKind::Skip),
+                edge(0x204, 0x200, EdgeKind::Jump),
+                edge(0x206, 0x206, EdgeKind::Jump),
+            ]
+        );
+        assert_eq!(graph.subroutines, BTreeMap::from([(0x208, BTreeSet::from([0x208]))]));
+
+        let dot = graph.to_dot(&Labels::new(&code, BTreeMap::new()));
+        let cluster = [
+            "    subgraph cluster_208 {",
+            "        label=\"sub_208\";",
+            "        b208 [label=\"sub_208:\\l208: ADD    V0, 0x1\\l20A: RTS\\l\"];",
+            "    }",
+        ];
+        assert!(dot.contains(&cluster.join("\n")), "{dot}");
+        assert!(dot.contains("    b200 -> b208 [style=dashed, label=\"call\"];"));
+    }
+}
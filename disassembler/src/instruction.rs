@@ -0,0 +1,505 @@
+use std::fmt;
+
+/// Instruction set extension an instruction belongs to. XO-CHIP
+/// includes SUPER-CHIP, which includes the original CHIP-8 set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Extension {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl fmt::Display for Extension {
+    /// Name accepted by the --platform option of the emulator
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Extension::Chip8 => write!(f, "chip8"),
+            Extension::SuperChip => write!(f, "schip"),
+            Extension::XoChip => write!(f, "xochip"),
+        }
+    }
+}
+
+/// Decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00CN
+    ScrollDown(u8),
+    /// 00DN
+    ScrollUp(u8),
+    /// 00E0
+    ClearScreen,
+    /// 00EE
+    Return,
+    /// 00FB
+    ScrollRight,
+    /// 00FC
+    ScrollLeft,
+    /// 00FD
+    Exit,
+    /// 00FE
+    LowRes,
+    /// 00FF
+    HighRes,
+    /// 0NNN
+    System(u16),
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SkipEqual { x: u8, value: u8 },
+    /// 4XNN
+    SkipNotEqual { x: u8, value: u8 },
+    /// 5XY0
+    SkipRegistersEqual { x: u8, y: u8 },
+    /// 5XY2
+    SaveRange { x: u8, y: u8 },
+    /// 5XY3
+    LoadRange { x: u8, y: u8 },
+    /// 6XNN
+    Load { x: u8, value: u8 },
+    /// 7XNN
+    Add { x: u8, value: u8 },
+    /// 8XY0
+    Move { x: u8, y: u8 },
+    /// 8XY1
+    Or { x: u8, y: u8 },
+    /// 8XY2
+    And { x: u8, y: u8 },
+    /// 8XY3
+    Xor { x: u8, y: u8 },
+    /// 8XY4
+    AddRegisters { x: u8, y: u8 },
+    /// 8XY5
+    Sub { x: u8, y: u8 },
+    /// 8XY6
+    ShiftRight { x: u8, y: u8 },
+    /// 8XY7
+    SubReversed { x: u8, y: u8 },
+    /// 8XYE
+    ShiftLeft { x: u8, y: u8 },
+    /// 9XY0
+    SkipRegistersNotEqual { x: u8, y: u8 },
+    /// ANNN
+    LoadI(u16),
+    /// BNNN
+    JumpPlus(u16),
+    /// CXNN
+    Random { x: u8, mask: u8 },
+    /// DXYN
+    Draw { x: u8, y: u8, n: u8 },
+    /// EX9E
+    SkipKeyDown(u8),
+    /// EXA1
+    SkipKeyUp(u8),
+    /// F000 NNNN. The address is the word following the instruction
+    LoadLongI,
+    /// FN01
+    SelectPlanes(u8),
+    /// F002
+    LoadAudioPattern,
+    /// FX07
+    MoveDelayTimer(u8),
+    /// FX0A
+    WaitKey(u8),
+    /// FX15
+    SetDelayTimer(u8),
+    /// FX18
+    SetSoundTimer(u8),
+    /// FX1E
+    AddI(u8),
+    /// FX29
+    Character(u8),
+    /// FX30
+    BigCharacter(u8),
+    /// FX33
+    Bcd(u8),
+    /// FX3A
+    SetPitch(u8),
+    /// FX55
+    StoreRegisters(u8),
+    /// FX65
+    ReadRegisters(u8),
+    /// FX75
+    SaveFlags(u8),
+    /// FX85
+    LoadFlags(u8),
+}
+
+impl Instruction {
+    /// Decode an opcode. Returns None for opcodes not in any instruction set
+    pub fn decode(opcode: u16) -> Option<Self> {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let value = (opcode & 0x00FF) as u8;
+        let address = opcode & 0x0FFF;
+
+        let instruction = match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00C0..=0x00CF => Instruction::ScrollDown(n),
+                0x00D0..=0x00DF => Instruction::ScrollUp(n),
+                0x00E0 => Instruction::ClearScreen,
+                0x00EE => Instruction::Return,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowRes,
+                0x00FF => Instruction::HighRes,
+                _ => Instruction::System(address),
+            },
+            0x1000 => Instruction::Jump(address),
+            0x2000 => Instruction::Call(address),
+            0x3000 => Instruction::SkipEqual { x, value },
+            0x4000 => Instruction::SkipNotEqual { x, value },
+            0x5000 => match n {
+                0x0 => Instruction::SkipRegistersEqual { x, y },
+                0x2 => Instruction::SaveRange { x, y },
+                0x3 => Instruction::LoadRange { x, y },
+                _ => return None,
+            },
+            0x6000 => Instruction::Load { x, value },
+            0x7000 => Instruction::Add { x, value },
+            0x8000 => match n {
+                0x0 => Instruction::Move { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::AddRegisters { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::ShiftRight { x, y },
+                0x7 => Instruction::SubReversed { x, y },
+                0xE => Instruction::ShiftLeft { x, y },
+                _ => return None,
+            },
+            0x9000 if n == 0 => Instruction::SkipRegistersNotEqual { x, y },
+            0xA000 => Instruction::LoadI(address),
+            0xB000 => Instruction::JumpPlus(address),
+            0xC000 => Instruction::Random { x, mask: value },
+            0xD000 => Instruction::Draw { x, y, n },
+            0xE000 => match value {
+                0x9E => Instruction::SkipKeyDown(x),
+                0xA1 => Instruction::SkipKeyUp(x),
+                _ => return None,
+            },
+            0xF000 => match value {
+                0x00 if x == 0 => Instruction::LoadLongI,
+                0x01 => Instruction::SelectPlanes(x),
+                0x02 if x == 0 => Instruction::LoadAudioPattern,
+                0x07 => Instruction::MoveDelayTimer(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelayTimer(x),
+                0x18 => Instruction::SetSoundTimer(x),
+                0x1E => Instruction::AddI(x),
+                0x29 => Instruction::Character(x),
+                0x30 => Instruction::BigCharacter(x),
+                0x33 => Instruction::Bcd(x),
+                0x3A => Instruction::SetPitch(x),
+                0x55 => Instruction::StoreRegisters(x),
+                0x65 => Instruction::ReadRegisters(x),
+                0x75 => Instruction::SaveFlags(x),
+                0x85 => Instruction::LoadFlags(x),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        Some(instruction)
+    }
+
+    /// Encode the instruction as an opcode. Operands are masked to
+    /// the size of their field
+    pub fn encode(&self) -> u16 {
+        let xy = |high: u16, x: u8, y: u8, low: u16| {
+            high | ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | low
+        };
+        let xnn = |high: u16, x: u8, value: u8| high | ((x as u16 & 0xF) << 8) | value as u16;
+        let fx = |x: u8, low: u16| 0xF000 | ((x as u16 & 0xF) << 8) | low;
+
+        match *self {
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::System(address) => address & 0x0FFF,
+            Instruction::Jump(address) => 0x1000 | (address & 0x0FFF),
+            Instruction::Call(address) => 0x2000 | (address & 0x0FFF),
+            Instruction::SkipEqual { x, value } => xnn(0x3000, x, value),
+            Instruction::SkipNotEqual { x, value } => xnn(0x4000, x, value),
+            Instruction::SkipRegistersEqual { x, y } => xy(0x5000, x, y, 0x0),
+            Instruction::SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            Instruction::Load { x, value } => xnn(0x6000, x, value),
+            Instruction::Add { x, value } => xnn(0x7000, x, value),
+            Instruction::Move { x, y } => xy(0x8000, x, y, 0x0),
+            Instruction::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Instruction::And { x, y } => xy(0x8000, x, y, 0x2),
+            Instruction::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Instruction::AddRegisters { x, y } => xy(0x8000, x, y, 0x4),
+            Instruction::Sub { x, y } => xy(0x8000, x, y, 0x5),
+            Instruction::ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            Instruction::SubReversed { x, y } => xy(0x8000, x, y, 0x7),
+            Instruction::ShiftLeft { x, y } => xy(0x8000, x, y, 0xE),
+            Instruction::SkipRegistersNotEqual { x, y } => xy(0x9000, x, y, 0x0),
+            Instruction::LoadI(address) => 0xA000 | (address & 0x0FFF),
+            Instruction::JumpPlus(address) => 0xB000 | (address & 0x0FFF),
+            Instruction::Random { x, mask } => xnn(0xC000, x, mask),
+            Instruction::Draw { x, y, n } => xy(0xD000, x, y, n as u16 & 0xF),
+            Instruction::SkipKeyDown(x) => xnn(0xE000, x, 0x9E),
+            Instruction::SkipKeyUp(x) => xnn(0xE000, x, 0xA1),
+            Instruction::LoadLongI => 0xF000,
+            Instruction::SelectPlanes(planes) => fx(planes, 0x01),
+            Instruction::LoadAudioPattern => 0xF002,
+            Instruction::MoveDelayTimer(x) => fx(x, 0x07),
+            Instruction::WaitKey(x) => fx(x, 0x0A),
+            Instruction::SetDelayTimer(x) => fx(x, 0x15),
+            Instruction::SetSoundTimer(x) => fx(x, 0x18),
+            Instruction::AddI(x) => fx(x, 0x1E),
+            Instruction::Character(x) => fx(x, 0x29),
+            Instruction::BigCharacter(x) => fx(x, 0x30),
+            Instruction::Bcd(x) => fx(x, 0x33),
+            Instruction::SetPitch(x) => fx(x, 0x3A),
+            Instruction::StoreRegisters(x) => fx(x, 0x55),
+            Instruction::ReadRegisters(x) => fx(x, 0x65),
+            Instruction::SaveFlags(x) => fx(x, 0x75),
+            Instruction::LoadFlags(x) => fx(x, 0x85),
+        }
+    }
+
+    /// Instruction set extension that introduced the instruction
+    pub fn extension(&self) -> Extension {
+        match self {
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::BigCharacter(_)
+            | Instruction::SaveFlags(_)
+            | Instruction::LoadFlags(_) => Extension::SuperChip,
+
+            Instruction::ScrollUp(_)
+            | Instruction::SaveRange { .. }
+            | Instruction::LoadRange { .. }
+            | Instruction::LoadLongI
+            | Instruction::SelectPlanes(_)
+            | Instruction::LoadAudioPattern
+            | Instruction::SetPitch(_) => Extension::XoChip,
+
+            _ => Extension::Chip8,
+        }
+    }
+
+    /// Size of the instruction in bytes including any operand words
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadLongI => 4,
+            _ => 2,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::ScrollDown(_) => "SCD",
+            Instruction::ScrollUp(_) => "SCU",
+            Instruction::ClearScreen => "CLS",
+            Instruction::Return => "RTS",
+            Instruction::ScrollRight => "SCR",
+            Instruction::ScrollLeft => "SCL",
+            Instruction::Exit => "EXIT",
+            Instruction::LowRes => "LOW",
+            Instruction::HighRes => "HIGH",
+            Instruction::System(_) => "SYS",
+            Instruction::Jump(_) => "JUMP",
+            Instruction::Call(_) => "CALL",
+            Instruction::SkipEqual { .. } => "SKE",
+            Instruction::SkipNotEqual { .. } => "SKNE",
+            Instruction::SkipRegistersEqual { .. } => "SKRE",
+            Instruction::SaveRange { .. } => "SAVER",
+            Instruction::LoadRange { .. } => "LOADR",
+            Instruction::Load { .. } => "LOAD",
+            Instruction::Add { .. } => "ADD",
+            Instruction::Move { .. } => "MOVE",
+            Instruction::Or { .. } => "OR",
+            Instruction::And { .. } => "AND",
+            Instruction::Xor { .. } => "XOR",
+            Instruction::AddRegisters { .. } => "ADDR",
+            Instruction::Sub { .. } => "SUB",
+            Instruction::ShiftRight { .. } => "SHR",
+            Instruction::SubReversed { .. } => "SUBR",
+            Instruction::ShiftLeft { .. } => "SHL",
+            Instruction::SkipRegistersNotEqual { .. } => "SKRNE",
+            Instruction::LoadI(_) => "LOADI",
+            Instruction::JumpPlus(_) => "JUMPI",
+            Instruction::Random { .. } => "RAND",
+            Instruction::Draw { .. } => "DRAW",
+            Instruction::SkipKeyDown(_) => "SKEYD",
+            Instruction::SkipKeyUp(_) => "SKEYU",
+            Instruction::LoadLongI => "LOADL",
+            Instruction::SelectPlanes(_) => "PLANE",
+            Instruction::LoadAudioPattern => "AUDIO",
+            Instruction::MoveDelayTimer(_) => "MOVEDT",
+            Instruction::WaitKey(_) => "KEYW",
+            Instruction::SetDelayTimer(_) => "LOADD",
+            Instruction::SetSoundTimer(_) => "LOADS",
+            Instruction::AddI(_) => "ADDI",
+            Instruction::Character(_) => "LDCHR",
+            Instruction::BigCharacter(_) => "LDHCHR",
+            Instruction::Bcd(_) => "BCDI",
+            Instruction::SetPitch(_) => "PITCH",
+            Instruction::StoreRegisters(_) => "STORE",
+            Instruction::ReadRegisters(_) => "READ",
+            Instruction::SaveFlags(_) => "SAVEF",
+            Instruction::LoadFlags(_) => "LOADF",
+        }
+    }
+
+    /// Operands as written after the mnemonic. The address of a long load is
+    /// not part of the instruction and is left out
+    pub fn operands(&self) -> Vec<String> {
+        let register = |x: u8| format!("V{x:X}");
+        let number = |n: u16| format!("{n:#X}");
+
+        match *self {
+            Instruction::ClearScreen
+            | Instruction::Return
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::LoadLongI
+            | Instruction::LoadAudioPattern => Vec::new(),
+
+            Instruction::ScrollDown(n)
+            | Instruction::ScrollUp(n)
+            | Instruction::SelectPlanes(n) => vec![number(n as u16)],
+
+            Instruction::System(address)
+            | Instruction::Jump(address)
+            | Instruction::Call(address)
+            | Instruction::LoadI(address)
+            | Instruction::JumpPlus(address) => vec![number(address)],
+
+            Instruction::SkipEqual { x, value }
+            | Instruction::SkipNotEqual { x, value }
+            | Instruction::Load { x, value }
+            | Instruction::Add { x, value }
+            | Instruction::Random { x, mask: value } => vec![register(x), number(value as u16)],
+
+            Instruction::SkipRegistersEqual { x, y }
+            | Instruction::SaveRange { x, y }
+            | Instruction::LoadRange { x, y }
+            | Instruction::Move { x, y }
+            | Instruction::Or { x, y }
+            | Instruction::And { x, y }
+            | Instruction::Xor { x, y }
+            | Instruction::AddRegisters { x, y }
+            | Instruction::Sub { x, y }
+            | Instruction::ShiftRight { x, y }
+            | Instruction::SubReversed { x, y }
+            | Instruction::ShiftLeft { x, y }
+            | Instruction::SkipRegistersNotEqual { x, y } => vec![register(x), register(y)],
+
+            Instruction::Draw { x, y, n } => vec![register(x), register(y), number(n as u16)],
+
+            Instruction::SkipKeyDown(x)
+            | Instruction::SkipKeyUp(x)
+            | Instruction::MoveDelayTimer(x)
+            | Instruction::WaitKey(x)
+            | Instruction::SetDelayTimer(x)
+            | Instruction::SetSoundTimer(x)
+            | Instruction::AddI(x)
+            | Instruction::Character(x)
+            | Instruction::BigCharacter(x)
+            | Instruction::Bcd(x)
+            | Instruction::SetPitch(x)
+            | Instruction::StoreRegisters(x)
+            | Instruction::ReadRegisters(x)
+            | Instruction::SaveFlags(x)
+            | Instruction::LoadFlags(x) => vec![register(x)],
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = self.mnemonic();
+        let operands = self.operands();
+        if operands.is_empty() {
+            write!(f, "{mnemonic}")
+        } else {
+            write!(f, "{mnemonic:<6} {}", operands.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encode_round_trip() {
+        for opcode in 0..=u16::MAX {
+            if let Some(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{opcode:04X} decoded as {instruction:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn decode_covers_every_instruction_set() {
+        let decoded: Vec<Instruction> = (0..=u16::MAX).filter_map(Instruction::decode).collect();
+
+        // Every 0NNN, 1NNN, 2NNN, 3XNN, 4XNN, 6XNN, 7XNN, ANNN, BNNN, CXNN and
+        // DXYN opcode plus the sparse groups
+        let full_groups = 11 * 0x1000;
+        let sparse = 3 * 0x100 + 9 * 0x100 + 0x100 + 2 * 0x10 + 14 * 0x10 + 2;
+        assert_eq!(decoded.len(), full_groups + sparse);
+
+        for extension in [Extension::Chip8, Extension::SuperChip, Extension::XoChip] {
+            assert!(decoded.iter().any(|i| i.extension() == extension));
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes() {
+        for opcode in [0x5001, 0x8008, 0x800F, 0x9001, 0xE000, 0xF100, 0xF102, 0xF0FF] {
+            assert_eq!(Instruction::decode(opcode), None, "{opcode:04X}");
+        }
+    }
+
+    #[test]
+    fn display_every_opcode() {
+        for opcode in 0..=u16::MAX {
+            if let Some(instruction) = Instruction::decode(opcode) {
+                let text = instruction.to_string();
+                assert!(text.starts_with(instruction.mnemonic()), "{opcode:04X}: {text}");
+                assert_eq!(text.trim_end(), text, "{opcode:04X} has trailing whitespace");
+            }
+        }
+    }
+
+    #[test]
+    fn display_operands() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x1234, "JUMP   0x234"),
+            (0x5120, "SKRE   V1, V2"),
+            (0xB300, "JUMPI  0x300"),
+            (0xD125, "DRAW   V1, V2, 0x5"),
+            (0xF233, "BCDI   V2"),
+            (0xF201, "PLANE  0x2"),
+        ];
+        for (opcode, text) in cases {
+            assert_eq!(Instruction::decode(opcode).unwrap().to_string(), text);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use crate::control_flow::CodeMap;
+use crate::errors::Error;
+use crate::instruction::Instruction;
+
+/// Names given to addresses in a disassembly listing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Labels {
+    generated: BTreeMap<u32, String>,
+    symbols: BTreeMap<u32, String>,
+    // User symbols that cannot be placed in the listing
+    constants: BTreeMap<u32, String>,
+}
+
+// Kinds of generated labels, from least to most important
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Target {
+    Data,
+    Loop,
+    Subroutine,
+}
+
+impl Labels {
+    /// Label every jump, call and I register load target. A user symbol
+    /// replaces the generated name at its address
+    pub fn new(code: &CodeMap, symbols: BTreeMap<u32, String>) -> Self {
+        let mut targets: BTreeMap<u32, Target> = BTreeMap::new();
+        for decoded in code.instructions.values() {
+            let target = match decoded.instruction {
+                Instruction::Call(address) => (address as u32, Target::Subroutine),
+                Instruction::Jump(address) => (address as u32, Target::Loop),
+                Instruction::LoadI(address) => (address as u32, Target::Data),
+                Instruction::LoadLongI => match decoded.long_address {
+                    Some(address) => (address as u32, Target::Data),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            let kind = targets.entry(target.0).or_insert(target.1);
+            *kind = (*kind).max(target.1);
+        }
+
+        let generated = targets
+            .into_iter()
+            .filter(|(address, _)| !symbols.contains_key(address))
+            .map(|(address, kind)| {
+                let prefix = match kind {
+                    Target::Data => "data",
+                    Target::Loop => "loop",
+                    Target::Subroutine => "sub",
+                };
+                (address, format!("{prefix}_{address:03X}"))
+            })
+            .collect();
+
+        Self {
+            generated,
+            symbols,
+            constants: BTreeMap::new(),
+        }
+    }
+
+    /// Name of the address, if any, for use as an operand
+    pub fn get(&self, address: u32) -> Option<&str> {
+        self.label(address)
+            .or_else(|| self.constants.get(&address).map(String::as_str))
+    }
+
+    /// Label placed in the listing at the address
+    pub fn label(&self, address: u32) -> Option<&str> {
+        self.symbols
+            .get(&address)
+            .or_else(|| self.generated.get(&address))
+            .map(String::as_str)
+    }
+
+    /// User symbols defined as constants, as they cannot be placed in the listing
+    pub fn constants(&self) -> &BTreeMap<u32, String> {
+        &self.constants
+    }
+
+    /// Addresses of all labels in order
+    pub fn addresses(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut addresses: Vec<u32> =
+            self.generated.keys().chain(self.symbols.keys()).copied().collect();
+        addresses.sort_unstable();
+        addresses.into_iter()
+    }
+
+    /// Keep only the labels that can be placed in the listing. User symbols at
+    /// other addresses become constants
+    pub fn place(&mut self, placeable: impl Fn(u32) -> bool) {
+        self.generated.retain(|&address, _| placeable(address));
+        let (placed, constants) = std::mem::take(&mut self.symbols)
+            .into_iter()
+            .partition(|&(address, _)| placeable(address));
+        self.symbols = placed;
+        self.constants.extend(constants);
+    }
+}
+
+/// Read the symbol file if given
+pub fn read_symbols(path: Option<&str>) -> Result<BTreeMap<u32, String>, Error> {
+    match path {
+        Some(path) => {
+            let text =
+                std::fs::read_to_string(path).map_err(|e| Error::FileReadError(e.to_string()))?;
+            parse_symbols(&text)
+        }
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Parse a symbol file. Each line holds a hexadecimal address and a name,
+/// e.g. `2A4 main_loop`. Text after a semicolon is ignored
+pub fn parse_symbols(text: &str) -> Result<BTreeMap<u32, String>, Error> {
+    let mut symbols = BTreeMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = || Error::SymbolFileError(format!("line {}: {line}", i + 1));
+        let (address, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let address = address.trim_start_matches("0x");
+        let address = u32::from_str_radix(address, 16).map_err(|_| invalid())?;
+        let name = name.trim();
+        if !is_identifier(name) {
+            return Err(invalid());
+        }
+        symbols.insert(address, name.to_string());
+    }
+    Ok(symbols)
+}
+
+// Names the assembler accepts as labels. Register names are reserved and
+// uppercase hexadecimal would be read as the address column
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let is_register = name.len() == 2
+        && name.starts_with(['V', 'v'])
+        && name[1..].chars().all(|c| c.is_ascii_hexdigit());
+    let is_address = name.chars().all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c));
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && !is_register
+        && !is_address
+}
@@ -1,2 +1,9 @@
+pub mod control_flow;
 pub mod disassembler;
-pub mod errors;
\ No newline at end of file
+pub mod errors;
+pub mod graph;
+pub mod instruction;
+pub mod labels;
+pub mod output;
+pub mod quirks;
+pub mod sprites;
\ No newline at end of file
@@ -0,0 +1,235 @@
+use std::str::FromStr;
+
+use crate::control_flow::DecodedInstruction;
+use crate::errors::Error;
+use crate::instruction::Instruction;
+
+/// Syntax or file format of a disassembly listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Mnemonics understood by the assembler
+    #[default]
+    Native,
+    /// Mnemonics from Cowgod's Chip-8 technical reference
+    Cowgod,
+    /// Octo assembly language
+    Octo,
+    /// JSON array with one object per line of the native listing
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "native" => Ok(OutputFormat::Native),
+            "cowgod" => Ok(OutputFormat::Cowgod),
+            "octo" => Ok(OutputFormat::Octo),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(Error::UnknownOutputFormatError(s.into())),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Line of code or data at the address followed by an optional comment
+    pub(crate) fn line(self, address: u32, text: &str, comment: Option<&str>) -> String {
+        match (self, comment) {
+            (OutputFormat::Octo, Some(comment)) => {
+                format!("  {text:<24} # {address:03X} {comment}")
+            }
+            (OutputFormat::Octo, None) => format!("  {text:<24} # {address:03X}"),
+            (_, Some(comment)) => format!("{address:03X}: {text:<24} ; {comment}"),
+            (_, None) => format!("{address:03X}: {text}"),
+        }
+    }
+
+    pub(crate) fn label(self, name: &str) -> String {
+        match self {
+            OutputFormat::Octo => format!(": {name}"),
+            _ => format!("{name}:"),
+        }
+    }
+
+    pub(crate) fn constant(self, name: &str, address: u32) -> String {
+        match self {
+            OutputFormat::Cowgod => format!("{name} EQU {address:#X}"),
+            OutputFormat::Octo => format!(":const {name} {address:#X}"),
+            _ => format!("{name} equ {address:#X}"),
+        }
+    }
+
+    pub(crate) fn data(self, bytes: &[u8]) -> String {
+        let bytes: Vec<String> = bytes.iter().map(|byte| format!("{byte:#04X}")).collect();
+        match self {
+            OutputFormat::Cowgod => with_operands("DB", &bytes),
+            OutputFormat::Octo => bytes.join(" "),
+            _ => with_operands("db", &bytes),
+        }
+    }
+
+    /// Text of an instruction. The target address is replaced by its label if given
+    pub(crate) fn instruction(self, decoded: &DecodedInstruction, target: Option<&str>) -> String {
+        match self {
+            OutputFormat::Cowgod => cowgod(decoded, target),
+            OutputFormat::Octo => octo(decoded, target),
+            _ => {
+                let (mnemonic, operands) = native(decoded, target);
+                with_operands(mnemonic, &operands)
+            }
+        }
+    }
+}
+
+/// Mnemonic and operands in the native syntax
+pub(crate) fn native(
+    decoded: &DecodedInstruction,
+    target: Option<&str>,
+) -> (&'static str, Vec<String>) {
+    let operands = match (target, decoded.long_address) {
+        (Some(target), _) => vec![target.to_string()],
+        (None, Some(long_address)) => vec![format!("{long_address:#X}")],
+        (None, None) => decoded.instruction.operands(),
+    };
+    (decoded.instruction.mnemonic(), operands)
+}
+
+fn with_operands(mnemonic: &str, operands: &[String]) -> String {
+    if operands.is_empty() {
+        mnemonic.to_string()
+    } else {
+        format!("{mnemonic:<6} {}", operands.join(", "))
+    }
+}
+
+fn cowgod(decoded: &DecodedInstruction, target: Option<&str>) -> String {
+    let address = |address: u16| target.map_or_else(|| format!("{address:#X}"), String::from);
+    let v = |x: u8| format!("V{x:X}");
+    let n = |n: u8| format!("{n:#X}");
+    let op = |mnemonic: &str, operands: &[String]| with_operands(mnemonic, operands);
+
+    match decoded.instruction {
+        Instruction::ScrollDown(rows) => op("SCD", &[n(rows)]),
+        Instruction::ScrollUp(rows) => op("SCU", &[n(rows)]),
+        Instruction::ClearScreen => op("CLS", &[]),
+        Instruction::Return => op("RET", &[]),
+        Instruction::ScrollRight => op("SCR", &[]),
+        Instruction::ScrollLeft => op("SCL", &[]),
+        Instruction::Exit => op("EXIT", &[]),
+        Instruction::LowRes => op("LOW", &[]),
+        Instruction::HighRes => op("HIGH", &[]),
+        Instruction::System(nnn) => op("SYS", &[address(nnn)]),
+        Instruction::Jump(nnn) => op("JP", &[address(nnn)]),
+        Instruction::Call(nnn) => op("CALL", &[address(nnn)]),
+        Instruction::SkipEqual { x, value } => op("SE", &[v(x), n(value)]),
+        Instruction::SkipNotEqual { x, value } => op("SNE", &[v(x), n(value)]),
+        Instruction::SkipRegistersEqual { x, y } => op("SE", &[v(x), v(y)]),
+        Instruction::SaveRange { x, y } => op("SAVE", &[v(x), v(y)]),
+        Instruction::LoadRange { x, y } => op("LOAD", &[v(x), v(y)]),
+        Instruction::Load { x, value } => op("LD", &[v(x), n(value)]),
+        Instruction::Add { x, value } => op("ADD", &[v(x), n(value)]),
+        Instruction::Move { x, y } => op("LD", &[v(x), v(y)]),
+        Instruction::Or { x, y } => op("OR", &[v(x), v(y)]),
+        Instruction::And { x, y } => op("AND", &[v(x), v(y)]),
+        Instruction::Xor { x, y } => op("XOR", &[v(x), v(y)]),
+        Instruction::AddRegisters { x, y } => op("ADD", &[v(x), v(y)]),
+        Instruction::Sub { x, y } => op("SUB", &[v(x), v(y)]),
+        Instruction::ShiftRight { x, y } => op("SHR", &[v(x), v(y)]),
+        Instruction::SubReversed { x, y } => op("SUBN", &[v(x), v(y)]),
+        Instruction::ShiftLeft { x, y } => op("SHL", &[v(x), v(y)]),
+        Instruction::SkipRegistersNotEqual { x, y } => op("SNE", &[v(x), v(y)]),
+        Instruction::LoadI(nnn) => op("LD", &["I".into(), address(nnn)]),
+        Instruction::JumpPlus(nnn) => op("JP", &["V0".into(), address(nnn)]),
+        Instruction::Random { x, mask } => op("RND", &[v(x), n(mask)]),
+        Instruction::Draw { x, y, n: rows } => op("DRW", &[v(x), v(y), n(rows)]),
+        Instruction::SkipKeyDown(x) => op("SKP", &[v(x)]),
+        Instruction::SkipKeyUp(x) => op("SKNP", &[v(x)]),
+        Instruction::LoadLongI => {
+            let long_address = address(decoded.long_address.unwrap_or_default());
+            op("LDL", &["I".into(), long_address])
+        }
+        Instruction::SelectPlanes(planes) => op("PLANE", &[n(planes)]),
+        Instruction::LoadAudioPattern => op("AUDIO", &[]),
+        Instruction::MoveDelayTimer(x) => op("LD", &[v(x), "DT".into()]),
+        Instruction::WaitKey(x) => op("LD", &[v(x), "K".into()]),
+        Instruction::SetDelayTimer(x) => op("LD", &["DT".into(), v(x)]),
+        Instruction::SetSoundTimer(x) => op("LD", &["ST".into(), v(x)]),
+        Instruction::AddI(x) => op("ADD", &["I".into(), v(x)]),
+        Instruction::Character(x) => op("LD", &["F".into(), v(x)]),
+        Instruction::BigCharacter(x) => op("LD", &["HF".into(), v(x)]),
+        Instruction::Bcd(x) => op("LD", &["B".into(), v(x)]),
+        Instruction::SetPitch(x) => op("PITCH", &[v(x)]),
+        Instruction::StoreRegisters(x) => op("LD", &["[I]".into(), v(x)]),
+        Instruction::ReadRegisters(x) => op("LD", &[v(x), "[I]".into()]),
+        Instruction::SaveFlags(x) => op("LD", &["R".into(), v(x)]),
+        Instruction::LoadFlags(x) => op("LD", &[v(x), "R".into()]),
+    }
+}
+
+fn octo(decoded: &DecodedInstruction, target: Option<&str>) -> String {
+    let address = |address: u16| target.map_or_else(|| format!("{address:#X}"), String::from);
+
+    match decoded.instruction {
+        Instruction::ScrollDown(n) => format!("scroll-down {n}"),
+        Instruction::ScrollUp(n) => format!("scroll-up {n}"),
+        Instruction::ClearScreen => "clear".into(),
+        Instruction::Return => "return".into(),
+        Instruction::ScrollRight => "scroll-right".into(),
+        Instruction::ScrollLeft => "scroll-left".into(),
+        Instruction::Exit => "exit".into(),
+        Instruction::LowRes => "lores".into(),
+        Instruction::HighRes => "hires".into(),
+        // Octo has no machine code calls, so the opcode is written as data
+        Instruction::System(nnn) => format!("{:#04X} {:#04X}", nnn >> 8, nnn & 0xFF),
+        Instruction::Jump(nnn) => format!("jump {}", address(nnn)),
+        // Octo calls a subroutine by naming its label
+        Instruction::Call(nnn) => match target {
+            Some(target) => target.into(),
+            None => format!(":call {nnn:#X}"),
+        },
+        // Octo conditions state when the next instruction runs, which is
+        // when the skip is not taken
+        Instruction::SkipEqual { x, value } => format!("if v{x:x} != {value} then"),
+        Instruction::SkipNotEqual { x, value } => format!("if v{x:x} == {value} then"),
+        Instruction::SkipRegistersEqual { x, y } => format!("if v{x:x} != v{y:x} then"),
+        Instruction::SaveRange { x, y } => format!("save v{x:x} - v{y:x}"),
+        Instruction::LoadRange { x, y } => format!("load v{x:x} - v{y:x}"),
+        Instruction::Load { x, value } => format!("v{x:x} := {value}"),
+        Instruction::Add { x, value } => format!("v{x:x} += {value}"),
+        Instruction::Move { x, y } => format!("v{x:x} := v{y:x}"),
+        Instruction::Or { x, y } => format!("v{x:x} |= v{y:x}"),
+        Instruction::And { x, y } => format!("v{x:x} &= v{y:x}"),
+        Instruction::Xor { x, y } => format!("v{x:x} ^= v{y:x}"),
+        Instruction::AddRegisters { x, y } => format!("v{x:x} += v{y:x}"),
+        Instruction::Sub { x, y } => format!("v{x:x} -= v{y:x}"),
+        Instruction::ShiftRight { x, y } => format!("v{x:x} >>= v{y:x}"),
+        Instruction::SubReversed { x, y } => format!("v{x:x} =- v{y:x}"),
+        Instruction::ShiftLeft { x, y } => format!("v{x:x} <<= v{y:x}"),
+        Instruction::SkipRegistersNotEqual { x, y } => format!("if v{x:x} == v{y:x} then"),
+        Instruction::LoadI(nnn) => format!("i := {}", address(nnn)),
+        Instruction::JumpPlus(nnn) => format!("jump0 {}", address(nnn)),
+        Instruction::Random { x, mask } => format!("v{x:x} := random {mask}"),
+        Instruction::Draw { x, y, n } => format!("sprite v{x:x} v{y:x} {n}"),
+        Instruction::SkipKeyDown(x) => format!("if v{x:x} -key then"),
+        Instruction::SkipKeyUp(x) => format!("if v{x:x} key then"),
+        Instruction::LoadLongI => {
+            format!("i := long {}", address(decoded.long_address.unwrap_or_default()))
+        }
+        Instruction::SelectPlanes(n) => format!("plane {n}"),
+        Instruction::LoadAudioPattern => "audio".into(),
+        Instruction::MoveDelayTimer(x) => format!("v{x:x} := delay"),
+        Instruction::WaitKey(x) => format!("v{x:x} := key"),
+        Instruction::SetDelayTimer(x) => format!("delay := v{x:x}"),
+        Instruction::SetSoundTimer(x) => format!("buzzer := v{x:x}"),
+        Instruction::AddI(x) => format!("i += v{x:x}"),
+        Instruction::Character(x) => format!("i := hex v{x:x}"),
+        Instruction::BigCharacter(x) => format!("i := bighex v{x:x}"),
+        Instruction::Bcd(x) => format!("bcd v{x:x}"),
+        Instruction::SetPitch(x) => format!("pitch := v{x:x}"),
+        Instruction::StoreRegisters(x) => format!("save v{x:x}"),
+        Instruction::ReadRegisters(x) => format!("load v{x:x}"),
+        Instruction::SaveFlags(x) => format!("saveflags v{x:x}"),
+        Instruction::LoadFlags(x) => format!("loadflags v{x:x}"),
+    }
+}
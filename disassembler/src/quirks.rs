@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::control_flow::CodeMap;
+use crate::errors::Error;
+use crate::instruction::{Extension, Instruction};
+
+// Instructions searched after a register transfer for the next use of I
+const MAX_TRANSFER_DISTANCE: usize = 16;
+// Instructions searched before a BNNN jump for the register it adds
+const MAX_OFFSET_DISTANCE: u32 = 4;
+
+/// Quirk profile of the interpreters a ROM was written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+}
+
+impl Profile {
+    /// Name accepted by the --quirks option of the emulator
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::CosmacVip => "vip",
+            Profile::Chip48 => "chip48",
+            Profile::SuperChip => "schip",
+            Profile::XoChip => "xochip",
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Confidence::Low => write!(f, "low"),
+            Confidence::Medium => write!(f, "medium"),
+            Confidence::High => write!(f, "high"),
+        }
+    }
+}
+
+/// What a piece of evidence suggests about the ROM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hint {
+    SuperChipInstruction,
+    XoChipInstruction,
+    /// Relies on the original COSMAC VIP behaviour, kept by XO-CHIP
+    VipQuirk,
+    /// Relies on the behaviour introduced by CHIP-48 and SUPER-CHIP
+    SuperChipQuirk,
+}
+
+/// Code pattern found in the ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Evidence {
+    /// Address of the first occurrence
+    pub address: u32,
+    pub count: u32,
+    pub hint: Hint,
+    pub reason: String,
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:03X}: {}", self.address, self.reason)?;
+        if self.count > 1 {
+            write!(f, " ({} times)", self.count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Platform and quirk profile a ROM most likely needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuirkReport {
+    pub platform: Extension,
+    pub profile: Profile,
+    pub confidence: Confidence,
+    pub evidence: Vec<Evidence>,
+}
+
+/// Guess the platform and quirk profile of a ROM file
+pub fn detect_quirks_file(rom_path: &str) -> Result<QuirkReport, Error> {
+    let rom = std::fs::read(rom_path).map_err(|e| Error::FileReadError(e.to_string()))?;
+    Ok(detect_quirks(&rom))
+}
+
+/// Guess the platform and quirk profile from the instructions and code
+/// patterns reachable in the ROM
+pub fn detect_quirks(rom: &[u8]) -> QuirkReport {
+    let code = CodeMap::trace(rom);
+    // Evidence by reason, so that repeated patterns are reported once
+    let mut found: BTreeMap<String, Evidence> = BTreeMap::new();
+    let mut add = |address: u32, hint: Hint, reason: String| {
+        found
+            .entry(reason.clone())
+            .and_modify(|evidence| evidence.count += 1)
+            .or_insert(Evidence {
+                address,
+                count: 1,
+                hint,
+                reason,
+            });
+    };
+
+    for (&address, decoded) in &code.instructions {
+        let instruction = decoded.instruction;
+        match instruction.extension() {
+            Extension::XoChip => add(
+                address,
+                Hint::XoChipInstruction,
+                format!("XO-CHIP instruction {}", instruction.mnemonic()),
+            ),
+            Extension::SuperChip => add(
+                address,
+                Hint::SuperChipInstruction,
+                format!("SUPER-CHIP instruction {}", instruction.mnemonic()),
+            ),
+            Extension::Chip8 => {}
+        }
+
+        match instruction {
+            Instruction::Draw { n: 0, .. } => add(
+                address,
+                Hint::SuperChipInstruction,
+                "SUPER-CHIP 16x16 sprite drawn".to_string(),
+            ),
+            Instruction::ShiftRight { x, y } | Instruction::ShiftLeft { x, y } if x != y => add(
+                address,
+                Hint::VipQuirk,
+                "Shift from a different register, which only matters if VY is shifted".to_string(),
+            ),
+            Instruction::StoreRegisters(_) | Instruction::ReadRegisters(_) => {
+                if let Some((hint, reason)) = next_transfer(&code, address) {
+                    add(address, hint, reason.to_string());
+                }
+            }
+            Instruction::JumpPlus(target) => {
+                if let Some((hint, reason)) = jump_offset(&code, address, target) {
+                    add(address, hint, reason);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut evidence: Vec<Evidence> = found.into_values().collect();
+    evidence.sort_by_key(|evidence| evidence.address);
+    recommend(evidence)
+}
+
+// How I is used after FX55/FX65 without being loaded again
+fn next_transfer(code: &CodeMap, address: u32) -> Option<(Hint, &'static str)> {
+    for (_, decoded) in code.path_after(address).take(MAX_TRANSFER_DISTANCE) {
+        match decoded.instruction {
+            Instruction::StoreRegisters(_)
+            | Instruction::ReadRegisters(_)
+            | Instruction::Bcd(_)
+            | Instruction::Draw { .. } => {
+                return Some((
+                    Hint::VipQuirk,
+                    "FX55/FX65 followed by another use of I without reloading it",
+                ));
+            }
+            Instruction::AddI(_) => {
+                return Some((
+                    Hint::SuperChipQuirk,
+                    "FX55/FX65 followed by advancing I with FX1E",
+                ));
+            }
+            Instruction::LoadI(_)
+            | Instruction::LoadLongI
+            | Instruction::Character(_)
+            | Instruction::BigCharacter(_) => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+// Which register was set for a BNNN jump with a nonzero X, where V0 is added
+// by the COSMAC VIP but VX by CHIP-48 and SUPER-CHIP
+fn jump_offset(code: &CodeMap, address: u32, target: u16) -> Option<(Hint, String)> {
+    let x = (target >> 8) as u8 & 0xF;
+    if x == 0 {
+        return None;
+    }
+
+    for distance in 1..=MAX_OFFSET_DISTANCE {
+        let previous = address.checked_sub(2 * distance)?;
+        let decoded = code.instructions.get(&previous)?;
+        match written_register(&decoded.instruction) {
+            Some(0) => {
+                return Some((Hint::VipQuirk, "BNNN jump after setting V0".to_string()));
+            }
+            Some(register) if register == x => {
+                return Some((
+                    Hint::SuperChipQuirk,
+                    format!("BNNN jump after setting V{x:X}, the register in its address"),
+                ));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn written_register(instruction: &Instruction) -> Option<u8> {
+    match *instruction {
+        Instruction::Load { x, .. }
+        | Instruction::Add { x, .. }
+        | Instruction::Move { x, .. }
+        | Instruction::Or { x, .. }
+        | Instruction::And { x, .. }
+        | Instruction::Xor { x, .. }
+        | Instruction::AddRegisters { x, .. }
+        | Instruction::Sub { x, .. }
+        | Instruction::SubReversed { x, .. }
+        | Instruction::ShiftRight { x, .. }
+        | Instruction::ShiftLeft { x, .. }
+        | Instruction::Random { x, .. }
+        | Instruction::MoveDelayTimer(x)
+        | Instruction::WaitKey(x) => Some(x),
+        _ => None,
+    }
+}
+
+fn recommend(evidence: Vec<Evidence>) -> QuirkReport {
+    let has = |hint: Hint| evidence.iter().any(|evidence| evidence.hint == hint);
+    let count = |hints: &[Hint]| {
+        evidence
+            .iter()
+            .filter(|evidence| hints.contains(&evidence.hint))
+            .count()
+    };
+
+    let (platform, profile, supporting, opposing) = if has(Hint::XoChipInstruction) {
+        (
+            Extension::XoChip,
+            Profile::XoChip,
+            &[Hint::XoChipInstruction, Hint::SuperChipInstruction, Hint::VipQuirk][..],
+            &[Hint::SuperChipQuirk][..],
+        )
+    } else if has(Hint::SuperChipInstruction) {
+        (
+            Extension::SuperChip,
+            Profile::SuperChip,
+            &[Hint::SuperChipInstruction, Hint::SuperChipQuirk][..],
+            &[Hint::VipQuirk][..],
+        )
+    } else if count(&[Hint::SuperChipQuirk]) > count(&[Hint::VipQuirk]) {
+        (
+            Extension::Chip8,
+            Profile::Chip48,
+            &[Hint::SuperChipQuirk][..],
+            &[Hint::VipQuirk][..],
+        )
+    } else {
+        (
+            Extension::Chip8,
+            Profile::CosmacVip,
+            &[Hint::VipQuirk][..],
+            &[Hint::SuperChipQuirk][..],
+        )
+    };
+
+    // New instructions are conclusive about the platform, quirk patterns less so
+    let support = count(supporting);
+    let against = count(opposing);
+    let conclusive = platform != Extension::Chip8;
+    let confidence = if support == 0 || against >= support {
+        Confidence::Low
+    } else if against == 0 && (conclusive || support >= 2) {
+        Confidence::High
+    } else {
+        Confidence::Medium
+    };
+
+    QuirkReport {
+        platform,
+        profile,
+        confidence,
+        evidence,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_rom_defaults_to_vip_with_low_confidence() {
+        let report = detect_quirks(&[0x00, 0xE0, 0x12, 0x02]);
+        assert_eq!(report.platform, Extension::Chip8);
+        assert_eq!(report.profile, Profile::CosmacVip);
+        assert_eq!(report.confidence, Confidence::Low);
+        assert!(report.evidence.is_empty());
+    }
+
+    #[test]
+    fn quirk_patterns_suggest_chip48() {
+        let rom = [
+            0xA3, 0x00, 0xF2, 0x55, 0xF3, 0x1E, 0x61, 0x04, 0xB1, 0x00,
+        ];
+        let report = detect_quirks(&rom);
+        assert_eq!(report.platform, Extension::Chip8);
+        assert_eq!(report.profile, Profile::Chip48);
+        assert_eq!(report.confidence, Confidence::High);
+        let reasons: Vec<String> = report.evidence.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            reasons,
+            [
+                "202: FX55/FX65 followed by advancing I with FX1E",
+                "208: BNNN jump after setting V1, the register in its address",
+            ]
+        );
+    }
+
+    #[test]
+    fn xo_chip_instructions_outweigh_quirk_patterns() {
+        let rom = [
+            0xF0, 0x00, 0x03, 0x00, 0x81, 0x26, 0xF1, 0x65, 0xF1, 0x65, 0x12, 0x04,
+        ];
+        let report = detect_quirks(&rom);
+        assert_eq!(report.platform, Extension::XoChip);
+        assert_eq!(report.profile, Profile::XoChip);
+        assert_eq!(report.confidence, Confidence::High);
+        assert_eq!(report.evidence.len(), 3);
+        assert_eq!(report.evidence[2].count, 2);
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+
+use crate::control_flow::CodeMap;
+use crate::instruction::Instruction;
+
+// Instructions searched after an I register load for the draw using it
+const MAX_DRAW_DISTANCE: usize = 32;
+
+/// Sprite drawn from data at a fixed address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sprite {
+    /// Bytes per row. SUPER-CHIP 16x16 sprites use two
+    pub row_size: u32,
+    pub rows: u32,
+}
+
+impl Sprite {
+    pub fn size(&self) -> u32 {
+        self.row_size * self.rows
+    }
+}
+
+/// Find sprites by pairing each I register load with the first draw on the
+/// path following it, unless I changes first. The largest sprite drawn from
+/// an address is kept
+pub fn find_sprites(code: &CodeMap) -> BTreeMap<u32, Sprite> {
+    let mut sprites: BTreeMap<u32, Sprite> = BTreeMap::new();
+
+    for (&address, decoded) in &code.instructions {
+        let sprite_address = match decoded.instruction {
+            Instruction::LoadI(target) => target as u32,
+            Instruction::LoadLongI => match decoded.long_address {
+                Some(target) => target as u32,
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        for (_, decoded) in code.path_after(address).take(MAX_DRAW_DISTANCE) {
+            match decoded.instruction {
+                Instruction::Draw { n, .. } => {
+                    let sprite = match n {
+                        0 => Sprite {
+                            row_size: 2,
+                            rows: 16,
+                        },
+                        _ => Sprite {
+                            row_size: 1,
+                            rows: n as u32,
+                        },
+                    };
+                    let known = sprites.entry(sprite_address).or_insert(sprite);
+                    if sprite.size() > known.size() {
+                        *known = sprite;
+                    }
+                    break;
+                }
+                Instruction::LoadI(_)
+                | Instruction::LoadLongI
+                | Instruction::AddI(_)
+                | Instruction::Character(_)
+                | Instruction::BigCharacter(_)
+                | Instruction::StoreRegisters(_)
+                | Instruction::ReadRegisters(_) => break,
+                _ => {}
+            }
+        }
+    }
+
+    sprites
+}
+
+/// Pixels of a sprite row, drawn as # for set and . for clear
+pub fn pixel_row(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1))
+        .map(|set| if set { '#' } else { '.' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn big_sprite_drawn_after_jump() {
+        let mut rom = vec![0xA2, 0x0A, 0x12, 0x06, 0x00, 0x00, 0xD0, 0x10, 0x00, 0xEE];
+        rom.extend([0xC3, 0x81].repeat(16));
+
+        let sprites = find_sprites(&CodeMap::trace(&rom));
+        let sprite = Sprite {
+            row_size: 2,
+            rows: 16,
+        };
+        assert_eq!(sprites, BTreeMap::from([(0x20A, sprite)]));
+        assert_eq!(pixel_row(&[0xC3, 0x81]), "##....###......#");
+    }
+}